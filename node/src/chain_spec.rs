@@ -1,12 +1,12 @@
 use node_template_runtime::{
-	AccountId, AuraConfig, BalancesConfig, GrandpaConfig, RuntimeGenesisConfig, Signature,
+	AccountId, AssetsConfig, AuraConfig, BalancesConfig, GrandpaConfig, RuntimeGenesisConfig, Signature,
 	SudoConfig, SystemConfig, WASM_BINARY,
 };
 use sc_service::ChainType;
 use sp_consensus_aura::sr25519::AuthorityId as AuraId;
 use sp_consensus_grandpa::AuthorityId as GrandpaId;
 use sp_core::{sr25519, Pair, Public};
-use sp_runtime::traits::{IdentifyAccount, Verify};
+use sp_runtime::traits::{AccountIdConversion, IdentifyAccount, Verify};
 
 // The URL for the telemetry server.
 // const STAGING_TELEMETRY_URL: &str = "wss://telemetry.polkadot.io/submit/";
@@ -154,6 +154,16 @@ fn testnet_genesis(
 			key: Some(root_key),
 		},
 		transaction_payment: Default::default(),
-		assets: Default::default(),
+		assets: AssetsConfig {
+			// Wrapped native token traded in DEX pools, sufficient so fee payers need no native balance.
+			// Owned by the DEX so that it can only be minted against locked native tokens.
+			assets: vec![(
+				node_template_runtime::NATIVE_ASSET_ID,
+				node_template_runtime::DexPalletId::get().into_account_truncating(),
+				true,
+				1,
+			)],
+			..Default::default()
+		},
 	}
 }
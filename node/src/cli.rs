@@ -51,4 +51,7 @@ pub enum Subcommand {
 
 	/// Db meta columns information.
 	ChainInfo(sc_cli::ChainInfoCmd),
+
+	/// Index the DEX pools and trades of the finalized blocks into an SQLite database.
+	Index(crate::indexer::IndexCmd),
 }
@@ -0,0 +1,628 @@
+//! Off-chain indexer of the DEX.
+//!
+//! Replays the finalized blocks of the local database, decodes the events of `pallet_dex` and
+//! writes pools, trades, liquidity changes and OHLC candles into an SQLite database. Indexing
+//! resumes after the last block indexed, so the command can be run periodically.
+//!
+//! Blocks from before swap events carried their pool and reserves only have the legacy `Swaped`
+//! event, which is indexed instead for them.
+//!
+//! Events are decoded with the types of this node's runtime. A block whose events do not decode
+//! with them stops the indexer before it is recorded, so it is retried by the next run instead of
+//! being skipped for good. Candles are rebuilt from the recorded trades, so indexing a block again,
+//! e.g. with `--from`, never counts its trades twice.
+
+use codec::{Decode, DecodeAll};
+use node_template_runtime::{AccountId, Balance, RuntimeEvent};
+use pallet_dex::Event as DexEvent;
+use rusqlite::{params, Connection, OptionalExtension};
+use sc_cli::{CliConfiguration, SharedParams};
+use sc_client_api::StorageProvider;
+use sp_blockchain::HeaderBackend;
+use sp_core::{hashing::twox_128, storage::StorageKey, H256};
+use std::path::PathBuf;
+
+use crate::service::FullClient;
+
+type EventRecord = frame_system::EventRecord<RuntimeEvent, H256>;
+type AssetId = u32;
+
+/// Index the DEX events of the finalized blocks into an SQLite database.
+#[derive(Debug, Clone, clap::Parser)]
+pub struct IndexCmd {
+	/// Path of the SQLite database, created if missing.
+	#[arg(long, value_name = "PATH")]
+	pub output: PathBuf,
+
+	/// First block to index. Defaults to the block after the last one indexed.
+	#[arg(long, value_name = "BLOCK")]
+	pub from: Option<u32>,
+
+	/// Last block to index. Defaults to the last finalized block.
+	#[arg(long, value_name = "BLOCK")]
+	pub to: Option<u32>,
+
+	/// Lengths of the candles to build, in seconds.
+	#[arg(long, value_name = "SECONDS", value_delimiter = ',', default_values_t = [60, 3_600, 86_400])]
+	pub candle_intervals: Vec<u64>,
+
+	#[allow(missing_docs)]
+	#[clap(flatten)]
+	pub shared_params: SharedParams,
+}
+
+impl CliConfiguration for IndexCmd {
+	fn shared_params(&self) -> &SharedParams {
+		&self.shared_params
+	}
+}
+
+impl IndexCmd {
+	/// Index the blocks of `client` into the database.
+	pub fn run(&self, client: &FullClient) -> sc_cli::Result<()> {
+		let mut db = Connection::open(&self.output).map_err(db_error)?;
+		db.execute_batch(SCHEMA).map_err(db_error)?;
+
+		let last_indexed: Option<u32> = db
+			.query_row("SELECT last_block FROM indexer_state WHERE id = 0", [], |row| row.get(0))
+			.optional()
+			.map_err(db_error)?;
+		let from = self.from.unwrap_or_else(|| last_indexed.map_or(0, |block| block + 1));
+		let to = self.to.unwrap_or(client.info().finalized_number);
+
+		for number in from..=to {
+			let Some(hash) = client.hash(number).map_err(|e| e.to_string())? else { break };
+			let events = block_events(client, number, hash)?;
+			let timestamp = block_timestamp(client, hash)?;
+
+			// Each block is written atomically, along with the progress.
+			let tx = db.transaction().map_err(db_error)?;
+			index_block(&tx, number, timestamp, &events, &self.candle_intervals).map_err(db_error)?;
+			tx.execute(
+				"INSERT OR REPLACE INTO indexer_state (id, last_block) VALUES (0, ?1)",
+				params![number],
+			)
+			.map_err(db_error)?;
+			tx.commit().map_err(db_error)?;
+
+			if number % 1_000 == 0 {
+				log::info!("Indexed block #{number}");
+			}
+		}
+
+		Ok(())
+	}
+}
+
+const SCHEMA: &str = "
+	CREATE TABLE IF NOT EXISTS indexer_state (
+		id INTEGER PRIMARY KEY CHECK (id = 0),
+		last_block INTEGER NOT NULL
+	);
+	CREATE TABLE IF NOT EXISTS pools (
+		asset_a INTEGER NOT NULL,
+		asset_b INTEGER NOT NULL,
+		lp_asset INTEGER NOT NULL,
+		creator TEXT NOT NULL,
+		block INTEGER NOT NULL,
+		timestamp INTEGER NOT NULL,
+		PRIMARY KEY (asset_a, asset_b)
+	);
+	CREATE TABLE IF NOT EXISTS trades (
+		block INTEGER NOT NULL,
+		event_index INTEGER NOT NULL,
+		timestamp INTEGER NOT NULL,
+		asset_a INTEGER NOT NULL,
+		asset_b INTEGER NOT NULL,
+		trader TEXT NOT NULL,
+		asset_in INTEGER NOT NULL,
+		amount_in TEXT NOT NULL,
+		asset_out INTEGER NOT NULL,
+		amount_out TEXT NOT NULL,
+		fee TEXT,
+		reserve_a TEXT,
+		reserve_b TEXT,
+		PRIMARY KEY (block, event_index)
+	);
+	CREATE TABLE IF NOT EXISTS liquidity_changes (
+		block INTEGER NOT NULL,
+		event_index INTEGER NOT NULL,
+		timestamp INTEGER NOT NULL,
+		asset_a INTEGER NOT NULL,
+		asset_b INTEGER NOT NULL,
+		provider TEXT NOT NULL,
+		kind TEXT NOT NULL CHECK (kind IN ('add', 'remove')),
+		amount_a TEXT NOT NULL,
+		amount_b TEXT NOT NULL,
+		lp_amount TEXT NOT NULL,
+		fee TEXT NOT NULL,
+		reserve_a TEXT NOT NULL,
+		reserve_b TEXT NOT NULL,
+		PRIMARY KEY (block, event_index)
+	);
+	CREATE TABLE IF NOT EXISTS candles (
+		asset_a INTEGER NOT NULL,
+		asset_b INTEGER NOT NULL,
+		interval INTEGER NOT NULL,
+		start INTEGER NOT NULL,
+		open REAL NOT NULL,
+		high REAL NOT NULL,
+		low REAL NOT NULL,
+		close REAL NOT NULL,
+		volume_a TEXT NOT NULL,
+		volume_b TEXT NOT NULL,
+		trades INTEGER NOT NULL,
+		PRIMARY KEY (asset_a, asset_b, interval, start)
+	);
+";
+
+/// A swap through a pool, as far as the indexer is concerned.
+struct Trade {
+	asset_a: AssetId,
+	asset_b: AssetId,
+	trader: AccountId,
+	asset_in: AssetId,
+	amount_in: Balance,
+	asset_out: AssetId,
+	amount_out: Balance,
+	fee: Option<Balance>,
+	reserves: Option<(Balance, Balance)>,
+}
+
+fn index_block(
+	tx: &rusqlite::Transaction,
+	number: u32,
+	timestamp: u64,
+	events: &[EventRecord],
+	candle_intervals: &[u64],
+) -> rusqlite::Result<()> {
+	let dex_events = events.iter().enumerate().filter_map(|(index, record)| match &record.event {
+		RuntimeEvent::Dex(event) => Some((index as u32, event)),
+		_ => None,
+	});
+	let has_v2_swaps = events
+		.iter()
+		.any(|record| matches!(record.event, RuntimeEvent::Dex(DexEvent::SwapExecuted { .. })));
+	// Pools traded in the block, whose candles are rebuilt once all of its trades are recorded.
+	let mut traded = Vec::new();
+
+	for (index, event) in dex_events {
+		match event {
+			DexEvent::PoolCreated { creator, pair, lp_asset } => {
+				tx.execute(
+					"INSERT OR REPLACE INTO pools VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+					params![pair.asset_a, pair.asset_b, lp_asset, creator.to_string(), number, timestamp],
+				)?;
+			},
+			DexEvent::PoolDestroyed { pair, .. } => {
+				tx.execute(
+					"DELETE FROM pools WHERE asset_a = ?1 AND asset_b = ?2",
+					params![pair.asset_a, pair.asset_b],
+				)?;
+			},
+			DexEvent::SwapExecuted {
+				sender,
+				pair,
+				asset_in,
+				amount_in,
+				asset_out,
+				amount_out,
+				fee,
+				reserve_a,
+				reserve_b,
+			} => {
+				let trade = Trade {
+					asset_a: pair.asset_a,
+					asset_b: pair.asset_b,
+					trader: sender.clone(),
+					asset_in: *asset_in,
+					amount_in: *amount_in,
+					asset_out: *asset_out,
+					amount_out: *amount_out,
+					fee: Some(*fee),
+					reserves: Some((*reserve_a, *reserve_b)),
+				};
+				insert_trade(tx, number, index, timestamp, &trade)?;
+				traded.push((trade.asset_a, trade.asset_b));
+			},
+			DexEvent::Swaped { sender, asset_in, amount_in, asset_out, amount_out }
+				if !has_v2_swaps =>
+			{
+				let trade = Trade {
+					asset_a: *asset_in.min(asset_out),
+					asset_b: *asset_in.max(asset_out),
+					trader: sender.clone(),
+					asset_in: *asset_in,
+					amount_in: *amount_in,
+					asset_out: *asset_out,
+					amount_out: *amount_out,
+					fee: None,
+					reserves: None,
+				};
+				insert_trade(tx, number, index, timestamp, &trade)?;
+				traded.push((trade.asset_a, trade.asset_b));
+			},
+			DexEvent::LiquidityProvided {
+				sender,
+				pair,
+				amount_a,
+				amount_b,
+				minted,
+				reserve_a,
+				reserve_b,
+				..
+			} => {
+				tx.execute(
+					"INSERT OR REPLACE INTO liquidity_changes
+						VALUES (?1, ?2, ?3, ?4, ?5, ?6, 'add', ?7, ?8, ?9, '0', ?10, ?11)",
+					params![
+						number,
+						index,
+						timestamp,
+						pair.asset_a,
+						pair.asset_b,
+						sender.to_string(),
+						amount_a.to_string(),
+						amount_b.to_string(),
+						minted.to_string(),
+						reserve_a.to_string(),
+						reserve_b.to_string(),
+					],
+				)?;
+			},
+			DexEvent::LiquidityWithdrawn {
+				sender,
+				pair,
+				amount_a,
+				amount_b,
+				burned,
+				fee,
+				reserve_a,
+				reserve_b,
+				..
+			} => {
+				tx.execute(
+					"INSERT OR REPLACE INTO liquidity_changes
+						VALUES (?1, ?2, ?3, ?4, ?5, ?6, 'remove', ?7, ?8, ?9, ?10, ?11, ?12)",
+					params![
+						number,
+						index,
+						timestamp,
+						pair.asset_a,
+						pair.asset_b,
+						sender.to_string(),
+						amount_a.to_string(),
+						amount_b.to_string(),
+						burned.to_string(),
+						fee.to_string(),
+						reserve_a.to_string(),
+						reserve_b.to_string(),
+					],
+				)?;
+			},
+			_ => {},
+		}
+	}
+
+	traded.sort_unstable();
+	traded.dedup();
+	for (asset_a, asset_b) in traded {
+		for &interval in candle_intervals.iter().filter(|&&interval| interval > 0) {
+			// Timestamps are in milliseconds, intervals in seconds.
+			let start = timestamp / 1_000 / interval * interval;
+			rebuild_candle(tx, asset_a, asset_b, interval, start)?;
+		}
+	}
+
+	Ok(())
+}
+
+/// Record `trade`, replacing the trade recorded at the same position if the block is indexed
+/// again.
+fn insert_trade(
+	tx: &rusqlite::Transaction,
+	number: u32,
+	index: u32,
+	timestamp: u64,
+	trade: &Trade,
+) -> rusqlite::Result<()> {
+	tx.execute(
+		"INSERT OR REPLACE INTO trades VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+		params![
+			number,
+			index,
+			timestamp,
+			trade.asset_a,
+			trade.asset_b,
+			trade.trader.to_string(),
+			trade.asset_in,
+			trade.amount_in.to_string(),
+			trade.asset_out,
+			trade.amount_out.to_string(),
+			trade.fee.map(|fee| fee.to_string()),
+			trade.reserves.map(|(reserve_a, _)| reserve_a.to_string()),
+			trade.reserves.map(|(_, reserve_b)| reserve_b.to_string()),
+		],
+	)?;
+
+	Ok(())
+}
+
+/// OHLC candle of a pool over one interval, with prices of `asset_a` in `asset_b`.
+#[derive(Debug, Clone, PartialEq)]
+struct Candle {
+	open: f64,
+	high: f64,
+	low: f64,
+	close: f64,
+	volume_a: Balance,
+	volume_b: Balance,
+	trades: u32,
+}
+
+impl Candle {
+	/// Aggregate trades, given as the amounts of `asset_a` and `asset_b` they swapped in the
+	/// order they happened. Trades that moved no `asset_a` have no price and are left out.
+	fn aggregate(trades: impl IntoIterator<Item = (Balance, Balance)>) -> Option<Self> {
+		let mut candle: Option<Candle> = None;
+		for (amount_a, amount_b) in trades.into_iter().filter(|&(amount_a, _)| amount_a > 0) {
+			let price = amount_b as f64 / amount_a as f64;
+			match candle.as_mut() {
+				Some(candle) => {
+					candle.high = candle.high.max(price);
+					candle.low = candle.low.min(price);
+					candle.close = price;
+					candle.volume_a = candle.volume_a.saturating_add(amount_a);
+					candle.volume_b = candle.volume_b.saturating_add(amount_b);
+					candle.trades += 1;
+				},
+				None =>
+					candle = Some(Candle {
+						open: price,
+						high: price,
+						low: price,
+						close: price,
+						volume_a: amount_a,
+						volume_b: amount_b,
+						trades: 1,
+					}),
+			}
+		}
+		candle
+	}
+}
+
+/// Rebuild the candle of the pool of `asset_a` and `asset_b` lasting `interval` seconds from
+/// `start` out of the trades recorded in it.
+fn rebuild_candle(
+	tx: &rusqlite::Transaction,
+	asset_a: AssetId,
+	asset_b: AssetId,
+	interval: u64,
+	start: u64,
+) -> rusqlite::Result<()> {
+	let mut statement = tx.prepare(
+		"SELECT asset_in, amount_in, amount_out FROM trades
+			WHERE asset_a = ?1 AND asset_b = ?2 AND timestamp >= ?3 AND timestamp < ?4
+			ORDER BY block, event_index",
+	)?;
+	let trades = statement
+		.query_map(
+			params![asset_a, asset_b, start * 1_000, (start + interval) * 1_000],
+			|row| Ok((row.get::<_, AssetId>(0)?, row.get::<_, String>(1)?, row.get::<_, String>(2)?)),
+		)?
+		.collect::<rusqlite::Result<Vec<_>>>()?;
+
+	let amounts = trades.into_iter().map(|(asset_in, amount_in, amount_out)| {
+		let amount_in = amount_in.parse::<Balance>().unwrap_or_default();
+		let amount_out = amount_out.parse::<Balance>().unwrap_or_default();
+		if asset_in == asset_a {
+			(amount_in, amount_out)
+		} else {
+			(amount_out, amount_in)
+		}
+	});
+
+	match Candle::aggregate(amounts) {
+		Some(candle) => tx.execute(
+			"INSERT OR REPLACE INTO candles VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+			params![
+				asset_a,
+				asset_b,
+				interval,
+				start,
+				candle.open,
+				candle.high,
+				candle.low,
+				candle.close,
+				candle.volume_a.to_string(),
+				candle.volume_b.to_string(),
+				candle.trades,
+			],
+		)?,
+		None => tx.execute(
+			"DELETE FROM candles WHERE asset_a = ?1 AND asset_b = ?2 AND interval = ?3 AND start = ?4",
+			params![asset_a, asset_b, interval, start],
+		)?,
+	};
+
+	Ok(())
+}
+
+/// Events of the block `number`, with hash `hash`, decoded with the types of this node's runtime.
+fn block_events(client: &FullClient, number: u32, hash: H256) -> sc_cli::Result<Vec<EventRecord>> {
+	let key = storage_key(b"System", b"Events");
+	let Some(data) = client.storage(hash, &key).map_err(|e| e.to_string())? else {
+		return Ok(Vec::new())
+	};
+	Vec::<EventRecord>::decode_all(&mut &data.0[..]).map_err(|error| {
+		let spec_version = client
+			.runtime_version_at(hash)
+			.map_or_else(|_| "unknown".into(), |version| version.spec_version.to_string());
+		format!(
+			"Events of block #{number}, from spec version {spec_version}, cannot be decoded with the \
+			 types of spec version {}: {error}. Index it with a node of its runtime's version.",
+			node_template_runtime::VERSION.spec_version,
+		)
+		.into()
+	})
+}
+
+/// Timestamp of the block `hash`, in milliseconds.
+fn block_timestamp(client: &FullClient, hash: H256) -> sc_cli::Result<u64> {
+	let key = storage_key(b"Timestamp", b"Now");
+	let data = client.storage(hash, &key).map_err(|e| e.to_string())?;
+	Ok(data.and_then(|data| u64::decode(&mut &data.0[..]).ok()).unwrap_or_default())
+}
+
+fn storage_key(pallet: &[u8], item: &[u8]) -> StorageKey {
+	StorageKey([twox_128(pallet), twox_128(item)].concat())
+}
+
+fn db_error(error: rusqlite::Error) -> sc_cli::Error {
+	sc_cli::Error::Application(Box::new(error))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use frame_system::Phase;
+	use node_template_runtime::Runtime;
+	use pallet_dex::AssetPair;
+
+	fn database() -> Connection {
+		let db = Connection::open_in_memory().unwrap();
+		db.execute_batch(SCHEMA).unwrap();
+		db
+	}
+
+	fn swap(
+		asset_in: AssetId,
+		amount_in: Balance,
+		asset_out: AssetId,
+		amount_out: Balance,
+	) -> EventRecord {
+		EventRecord {
+			phase: Phase::ApplyExtrinsic(0),
+			event: RuntimeEvent::Dex(DexEvent::SwapExecuted {
+				sender: AccountId::from([1u8; 32]),
+				pair: AssetPair::<Runtime>::new(asset_in, asset_out).unwrap(),
+				asset_in,
+				amount_in,
+				asset_out,
+				amount_out,
+				fee: amount_in / 100,
+				reserve_a: 0,
+				reserve_b: 0,
+			}),
+			topics: Vec::new(),
+		}
+	}
+
+	fn index(db: &mut Connection, number: u32, timestamp: u64, events: &[EventRecord]) {
+		let tx = db.transaction().unwrap();
+		index_block(&tx, number, timestamp, events, &[60]).unwrap();
+		tx.commit().unwrap();
+	}
+
+	fn candle(db: &Connection, start: u64) -> (f64, f64, f64, f64, String, String, u32) {
+		db.query_row(
+			"SELECT open, high, low, close, volume_a, volume_b, trades FROM candles
+				WHERE asset_a = 1 AND asset_b = 2 AND interval = 60 AND start = ?1",
+			params![start],
+			|row| {
+				Ok((
+					row.get(0)?,
+					row.get(1)?,
+					row.get(2)?,
+					row.get(3)?,
+					row.get(4)?,
+					row.get(5)?,
+					row.get(6)?,
+				))
+			},
+		)
+		.unwrap()
+	}
+
+	fn trades(db: &Connection) -> u32 {
+		db.query_row("SELECT COUNT(*) FROM trades", [], |row| row.get(0)).unwrap()
+	}
+
+	#[test]
+	fn index_block_records_trades_and_rebuilds_their_candles() {
+		let mut db = database();
+
+		// Selling asset 1 at 0.9, then buying it back at 500 / 520.
+		let events = [swap(1, 1_000, 2, 900), swap(2, 500, 1, 520)];
+		index(&mut db, 1, 61_000, &events);
+		assert_eq!(trades(&db), 2);
+		let expected =
+			(0.9, 500.0 / 520.0, 0.9, 500.0 / 520.0, "1520".to_string(), "1400".to_string(), 2);
+		assert_eq!(candle(&db, 60), expected);
+
+		// Indexing the block again, as `--from` does, counts its trades once.
+		index(&mut db, 1, 61_000, &events);
+		assert_eq!(trades(&db), 2);
+		assert_eq!(candle(&db, 60), expected);
+
+		// Later trades of the interval extend its candle, the next interval gets its own.
+		index(&mut db, 2, 62_000, &[swap(1, 100, 2, 80)]);
+		assert_eq!(
+			candle(&db, 60),
+			(0.9, 500.0 / 520.0, 0.8, 0.8, "1620".to_string(), "1480".to_string(), 3)
+		);
+		index(&mut db, 3, 120_000, &[swap(1, 100, 2, 70)]);
+		assert_eq!(candle(&db, 120), (0.7, 0.7, 0.7, 0.7, "100".to_string(), "70".to_string(), 1));
+		assert_eq!(candle(&db, 60).6, 3);
+	}
+
+	#[test]
+	fn insert_trade_replaces_the_trade_at_the_same_position() {
+		let mut db = database();
+		let trade = |amount_out| Trade {
+			asset_a: 1,
+			asset_b: 2,
+			trader: AccountId::from([1u8; 32]),
+			asset_in: 2,
+			amount_in: 500,
+			asset_out: 1,
+			amount_out,
+			fee: None,
+			reserves: None,
+		};
+
+		let tx = db.transaction().unwrap();
+		insert_trade(&tx, 1, 0, 61_000, &trade(520)).unwrap();
+		insert_trade(&tx, 1, 0, 61_000, &trade(510)).unwrap();
+		insert_trade(&tx, 1, 1, 61_000, &trade(400)).unwrap();
+		tx.commit().unwrap();
+
+		assert_eq!(trades(&db), 2);
+		let (amount_out, fee, reserve_a): (String, Option<String>, Option<String>) = db
+			.query_row(
+				"SELECT amount_out, fee, reserve_a FROM trades WHERE block = 1 AND event_index = 0",
+				[],
+				|row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+			)
+			.unwrap();
+		assert_eq!((amount_out.as_str(), fee, reserve_a), ("510", None, None));
+	}
+
+	#[test]
+	fn candles_leave_out_trades_without_a_price() {
+		assert_eq!(Candle::aggregate([]), None);
+		assert_eq!(
+			Candle::aggregate([(0, 10), (100, 50), (200, 200)]),
+			Some(Candle {
+				open: 0.5,
+				high: 1.0,
+				low: 0.5,
+				close: 1.0,
+				volume_a: 300,
+				volume_b: 250,
+				trades: 2,
+			})
+		);
+	}
+}
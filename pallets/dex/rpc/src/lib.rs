@@ -0,0 +1,468 @@
+//! RPC interface of the DEX pallet.
+//!
+//! Candles are built on request from the swaps of each block in the range, read through the
+//! runtime API at that block, so the node needs the state of those blocks (e.g. an archive node).
+//! As a single call may run thousands of runtime calls, `dex_candles` is an unsafe method.
+//!
+//! Splits of large trades are optimized off-chain against the best block: the node quotes
+//! candidate splits through the runtime API, exactly as `swap_exact_in_along_paths` would execute
+//! them, and keeps the one receiving the most. A suggestion takes up to
+//! `MAX_SPLIT_PARTS * MAX_SPLIT_PATHS` quotes, so `dex_suggestSplit` is an unsafe method too.
+//!
+//! LP positions are valued at the best block by the runtime, with the math of `remove_liquidity`.
+
+use std::{marker::PhantomData, sync::Arc};
+
+use codec::Codec;
+use jsonrpsee::{
+	core::{Error as JsonRpseeError, RpcResult},
+	proc_macros::rpc,
+	types::error::{CallError, ErrorObject},
+};
+use pallet_dex_runtime_api::split_greedily;
+pub use pallet_dex_runtime_api::{
+	DexApi as DexRuntimeApi, DexPositionsApi as DexPositionsRuntimeApi,
+};
+use sc_rpc_api::DenyUnsafe;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sp_api::{ApiExt, ProvideRuntimeApi};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::{
+	traits::{AtLeast32BitUnsigned, Block as BlockT, NumberFor, One, Zero},
+	PerThing, Perbill, SaturatedConversion,
+};
+
+/// Most blocks a single `dex_candles` call may scan.
+pub const MAX_CANDLE_BLOCKS: u32 = 10_000;
+
+/// Version of `DexRuntimeApi` that added `block_swaps`.
+const BLOCK_SWAPS_VERSION: u32 = 2;
+
+/// Version of `DexRuntimeApi` that added `max_split_routes`, the last call `dex_suggestSplit`
+/// relies on.
+const SPLIT_VERSION: u32 = 5;
+
+/// Most slices a single `dex_suggestSplit` call may cut a trade into.
+pub const MAX_SPLIT_PARTS: u32 = 100;
+
+/// Most paths a single `dex_suggestSplit` call tries.
+pub const MAX_SPLIT_PATHS: usize = 8;
+
+/// An OHLCV bar of the price of a pair's first asset in its second asset.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Candle<BlockNumber, Balance> {
+	/// First block of the bar.
+	pub start: BlockNumber,
+	/// Price paid by the first swap of the bar.
+	pub open: f64,
+	/// Highest price paid.
+	pub high: f64,
+	/// Lowest price paid.
+	pub low: f64,
+	/// Price paid by the last swap of the bar.
+	pub close: f64,
+	/// Amount of the first asset swapped.
+	pub volume_a: Balance,
+	/// Amount of the second asset swapped.
+	pub volume_b: Balance,
+	/// Number of swaps.
+	pub trades: u32,
+}
+
+/// A trade split across parallel paths, ready for `swap_exact_in_along_paths`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Split<AssetId, Balance> {
+	/// Paths to swap along, with the amount sold along each of them.
+	pub routes: Vec<(Vec<AssetId>, Balance)>,
+	/// Amount the routes are expected to receive in total.
+	pub amount_out: Balance,
+}
+
+/// What an account's LP tokens of a pool are worth if withdrawn now.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LpPosition<AssetId, Balance> {
+	/// Assets of the pool's pair.
+	pub pair: (AssetId, AssetId),
+	/// LP token of the pool.
+	pub lp_asset: AssetId,
+	/// LP tokens held by the account.
+	pub lp_balance: Balance,
+	/// LP tokens staked by the account in the pool's farm.
+	pub staked: Balance,
+	/// Percentage of the pool's LP tokens held or staked by the account.
+	pub share_percent: f64,
+	/// Amounts of the pair's assets paid out for withdrawing all of the LP tokens.
+	pub amounts: (Balance, Balance),
+	/// LP tokens kept as removal fee on withdrawal.
+	pub fee: Balance,
+	/// Value of the removal fee in the pair's assets.
+	pub fee_amounts: (Balance, Balance),
+}
+
+#[rpc(client, server)]
+pub trait DexApi<AccountId, AssetId, BlockNumber, Balance> {
+	/// OHLCV bars of `interval` blocks of the price of `pair.0` in `pair.1`, built from the swaps
+	/// of blocks `from` to `to`. Bars without swaps are left out.
+	///
+	/// Unsafe, as it calls into the runtime once per block of the range.
+	#[method(name = "dex_candles")]
+	fn candles(
+		&self,
+		pair: (AssetId, AssetId),
+		interval: BlockNumber,
+		from: BlockNumber,
+		to: BlockNumber,
+	) -> RpcResult<Vec<Candle<BlockNumber, Balance>>>;
+
+	/// Split of selling `amount_in` of `asset_in` for `asset_out` across at most `max_routes`
+	/// paths, and no more than the runtime's `MaxSplitRoutes`. The trade is handed out in `parts`
+	/// equal slices, each to the path receiving the most for it on top of the slices already handed
+	/// out, so more parts give a finer split. Only the `MAX_SPLIT_PATHS` paths receiving the most
+	/// for the whole trade are tried.
+	///
+	/// Unsafe, as it quotes every candidate split through the runtime.
+	#[method(name = "dex_suggestSplit")]
+	fn suggest_split(
+		&self,
+		asset_in: AssetId,
+		asset_out: AssetId,
+		amount_in: Balance,
+		parts: u32,
+		max_routes: u32,
+	) -> RpcResult<Split<AssetId, Balance>>;
+
+	/// Positions of `who` in every pool it holds or stakes LP tokens of, valued at the best block.
+	#[method(name = "dex_lpPositions")]
+	fn lp_positions(&self, who: AccountId) -> RpcResult<Vec<LpPosition<AssetId, Balance>>>;
+}
+
+/// Provides RPC methods to query the DEX.
+pub struct Dex<C, Block> {
+	client: Arc<C>,
+	deny_unsafe: DenyUnsafe,
+	_marker: PhantomData<Block>,
+}
+
+impl<C, Block> Dex<C, Block> {
+	/// Create a new instance of the DEX RPC methods.
+	pub fn new(client: Arc<C>, deny_unsafe: DenyUnsafe) -> Self {
+		Self { client, deny_unsafe, _marker: Default::default() }
+	}
+}
+
+/// Error type of this RPC api.
+pub enum Error {
+	/// The requested range of blocks is invalid or too long.
+	InvalidRange,
+	/// The call to the runtime failed.
+	RuntimeError,
+	/// The requested split is invalid.
+	InvalidSplit,
+	/// No path connects the assets.
+	NoRoute,
+	/// The runtime does not provide the calls the method needs.
+	UnsupportedRuntime,
+}
+
+impl From<Error> for i32 {
+	fn from(e: Error) -> i32 {
+		match e {
+			Error::InvalidRange => 1,
+			Error::RuntimeError => 2,
+			Error::InvalidSplit => 3,
+			Error::NoRoute => 4,
+			Error::UnsupportedRuntime => 5,
+		}
+	}
+}
+
+impl<C, Block, AccountId, AssetId, Balance>
+	DexApiServer<AccountId, AssetId, NumberFor<Block>, Balance> for Dex<C, Block>
+where
+	Block: BlockT,
+	C: ProvideRuntimeApi<Block> + HeaderBackend<Block> + Send + Sync + 'static,
+	C::Api: DexRuntimeApi<Block, AssetId, Balance, NumberFor<Block>>,
+	C::Api: DexPositionsRuntimeApi<Block, AccountId, AssetId, Balance>,
+	AccountId: Codec + DeserializeOwned + Send + Sync + 'static,
+	AssetId: Codec + Serialize + DeserializeOwned + Copy + Send + Sync + 'static,
+	Balance: Codec + AtLeast32BitUnsigned + Serialize + DeserializeOwned + Copy + Send + Sync + 'static,
+{
+	fn candles(
+		&self,
+		pair: (AssetId, AssetId),
+		interval: NumberFor<Block>,
+		from: NumberFor<Block>,
+		to: NumberFor<Block>,
+	) -> RpcResult<Vec<Candle<NumberFor<Block>, Balance>>> {
+		self.deny_unsafe.check_if_safe()?;
+		if interval.is_zero() || to < from || to - from >= MAX_CANDLE_BLOCKS.into() {
+			return Err(invalid_range())
+		}
+
+		let mut candles = Vec::new();
+		let mut number = from;
+		loop {
+			if let Some(hash) = self.client.hash(number).map_err(runtime_error)? {
+				let api = self.client.runtime_api();
+				// Blocks of runtimes from before `block_swaps` have no swaps to report.
+				let has_block_swaps = api
+					.has_api_with::<dyn DexRuntimeApi<Block, AssetId, Balance, NumberFor<Block>>, _>(
+						hash,
+						|version| version >= BLOCK_SWAPS_VERSION,
+					)
+					.map_err(runtime_error)?;
+				if has_block_swaps {
+					let swaps = api.block_swaps(hash, pair.0, pair.1).map_err(runtime_error)?;
+					add_swaps(&mut candles, number - number % interval, swaps);
+				}
+			}
+
+			if number >= to {
+				break
+			}
+			number += One::one();
+		}
+
+		Ok(candles)
+	}
+
+	fn suggest_split(
+		&self,
+		asset_in: AssetId,
+		asset_out: AssetId,
+		amount_in: Balance,
+		parts: u32,
+		max_routes: u32,
+	) -> RpcResult<Split<AssetId, Balance>> {
+		self.deny_unsafe.check_if_safe()?;
+		if amount_in.is_zero() || parts == 0 || parts > MAX_SPLIT_PARTS || max_routes == 0 {
+			return Err(invalid_split())
+		}
+
+		let api = self.client.runtime_api();
+		let at = self.client.info().best_hash;
+		let has_split = api
+			.has_api_with::<dyn DexRuntimeApi<Block, AssetId, Balance, NumberFor<Block>>, _>(
+				at,
+				|version| version >= SPLIT_VERSION,
+			)
+			.map_err(runtime_error)?;
+		if !has_split {
+			return Err(unsupported_runtime())
+		}
+
+		// Splits across more paths than the runtime takes could never be executed.
+		let max_routes = max_routes.min(api.max_split_routes(at).map_err(runtime_error)?);
+		if max_routes == 0 {
+			return Err(invalid_split())
+		}
+
+		// Every part quotes every path, so only the paths taking the whole trade best are tried.
+		let mut ranked = Vec::new();
+		for path in api.routes(at, asset_in, asset_out).map_err(runtime_error)? {
+			let amount_out = api
+				.quote_split_exact_in(at, vec![(path.clone(), amount_in)])
+				.map_err(runtime_error)?
+				.and_then(|amounts| amounts.first().copied())
+				.unwrap_or_else(Zero::zero);
+			ranked.push((amount_out, path));
+		}
+		ranked.sort_by(|(a, _), (b, _)| b.cmp(a));
+		let paths: Vec<_> =
+			ranked.into_iter().take(MAX_SPLIT_PATHS).map(|(_, path)| path).collect();
+
+		let (allocation, amount_out) =
+			split_greedily(paths.len(), amount_in, parts, max_routes, |allocation| {
+				let amounts = api
+					.quote_split_exact_in(at, split_routes(&paths, allocation))
+					.map_err(runtime_error)?;
+				Ok::<_, JsonRpseeError>(amounts.map(|amounts| {
+					amounts
+						.into_iter()
+						.fold(Balance::zero(), |total, amount| total.saturating_add(amount))
+				}))
+			})?
+			.ok_or_else(no_route)?;
+
+		Ok(Split { routes: split_routes(&paths, &allocation), amount_out })
+	}
+
+	fn lp_positions(&self, who: AccountId) -> RpcResult<Vec<LpPosition<AssetId, Balance>>> {
+		let at = self.client.info().best_hash;
+		let positions = self.client.runtime_api().lp_positions(at, who).map_err(runtime_error)?;
+
+		Ok(positions
+			.into_iter()
+			.map(|position| LpPosition {
+				pair: (position.asset_a, position.asset_b),
+				lp_asset: position.lp_asset,
+				lp_balance: position.lp_balance,
+				staked: position.staked,
+				share_percent: position.share.deconstruct() as f64 * 100.0 /
+					Perbill::ACCURACY as f64,
+				amounts: (position.amount_a, position.amount_b),
+				fee: position.fee,
+				fee_amounts: (position.fee_a, position.fee_b),
+			})
+			.collect())
+	}
+}
+
+/// Add `swaps`, as amounts of the pair's first and second asset, to the bar starting at block
+/// `start`, opening it if `candles` does not end with it. Swaps of none of the first asset have no
+/// price and are skipped.
+fn add_swaps<BlockNumber: PartialEq, Balance: AtLeast32BitUnsigned + Copy>(
+	candles: &mut Vec<Candle<BlockNumber, Balance>>,
+	start: BlockNumber,
+	swaps: impl IntoIterator<Item = (Balance, Balance)>,
+) {
+	for (amount_a, amount_b) in swaps {
+		if amount_a.is_zero() {
+			continue
+		}
+		let price =
+			amount_b.saturated_into::<u128>() as f64 / amount_a.saturated_into::<u128>() as f64;
+
+		match candles.last_mut() {
+			Some(candle) if candle.start == start => {
+				candle.high = candle.high.max(price);
+				candle.low = candle.low.min(price);
+				candle.close = price;
+				candle.volume_a = candle.volume_a.saturating_add(amount_a);
+				candle.volume_b = candle.volume_b.saturating_add(amount_b);
+				candle.trades += 1;
+			},
+			_ => candles.push(Candle {
+				start,
+				open: price,
+				high: price,
+				low: price,
+				close: price,
+				volume_a: amount_a,
+				volume_b: amount_b,
+				trades: 1,
+			}),
+		}
+	}
+}
+
+/// The paths handed a non-zero amount by `allocation`, with that amount.
+fn split_routes<AssetId: Clone, Balance: Zero + Copy>(
+	paths: &[Vec<AssetId>],
+	allocation: &[Balance],
+) -> Vec<(Vec<AssetId>, Balance)> {
+	paths
+		.iter()
+		.zip(allocation)
+		.filter(|(_, amount)| !amount.is_zero())
+		.map(|(path, amount)| (path.clone(), *amount))
+		.collect()
+}
+
+fn invalid_range() -> JsonRpseeError {
+	CallError::Custom(ErrorObject::owned(
+		Error::InvalidRange.into(),
+		"Invalid range of blocks.",
+		Some(format!("The interval must be positive and the range at most {MAX_CANDLE_BLOCKS} blocks.")),
+	))
+	.into()
+}
+
+fn invalid_split() -> JsonRpseeError {
+	CallError::Custom(ErrorObject::owned(
+		Error::InvalidSplit.into(),
+		"Invalid split.",
+		Some(format!(
+			"The amount and the number of routes must be positive and the parts between 1 and {MAX_SPLIT_PARTS}."
+		)),
+	))
+	.into()
+}
+
+fn no_route() -> JsonRpseeError {
+	CallError::Custom(ErrorObject::owned(
+		Error::NoRoute.into(),
+		"No route.",
+		Some("No path of pools can take the trade."),
+	))
+	.into()
+}
+
+fn unsupported_runtime() -> JsonRpseeError {
+	CallError::Custom(ErrorObject::owned(
+		Error::UnsupportedRuntime.into(),
+		"Unsupported runtime.",
+		Some(format!("The runtime must provide version {SPLIT_VERSION} of the DEX runtime API.")),
+	))
+	.into()
+}
+
+fn runtime_error(e: impl std::fmt::Display) -> JsonRpseeError {
+	CallError::Custom(ErrorObject::owned(
+		Error::RuntimeError.into(),
+		"Unable to query swaps.",
+		Some(e.to_string()),
+	))
+	.into()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn candle(
+		start: u32,
+		prices: (f64, f64, f64, f64),
+		volumes: (u128, u128),
+		trades: u32,
+	) -> Candle<u32, u128> {
+		let (open, high, low, close) = prices;
+		let (volume_a, volume_b) = volumes;
+		Candle { start, open, high, low, close, volume_a, volume_b, trades }
+	}
+
+	#[test]
+	fn swaps_of_a_bar_are_aggregated_in_order() {
+		let mut candles = Vec::new();
+		add_swaps(&mut candles, 10, vec![(100, 200), (100, 400), (200, 100), (100, 300)]);
+
+		assert_eq!(candles, vec![candle(10, (2.0, 4.0, 0.5, 3.0), (500, 1_000), 4)]);
+	}
+
+	#[test]
+	fn swaps_of_later_blocks_extend_the_open_bar() {
+		let mut candles = Vec::new();
+		add_swaps(&mut candles, 10, vec![(100, 200)]);
+		add_swaps(&mut candles, 10, vec![(100, 100)]);
+
+		assert_eq!(candles, vec![candle(10, (2.0, 2.0, 1.0, 1.0), (200, 300), 2)]);
+	}
+
+	#[test]
+	fn swaps_of_a_new_bar_open_it() {
+		let mut candles = Vec::new();
+		add_swaps(&mut candles, 10, vec![(100, 200)]);
+		add_swaps(&mut candles, 20, vec![(100, 300)]);
+
+		assert_eq!(
+			candles,
+			vec![
+				candle(10, (2.0, 2.0, 2.0, 2.0), (100, 200), 1),
+				candle(20, (3.0, 3.0, 3.0, 3.0), (100, 300), 1),
+			]
+		);
+	}
+
+	#[test]
+	fn swaps_without_a_price_are_skipped() {
+		let mut candles = Vec::new();
+		add_swaps(&mut candles, 10, vec![(0, 100)]);
+		assert!(candles.is_empty());
+
+		add_swaps(&mut candles, 10, vec![(100, 100), (0, 100)]);
+		assert_eq!(candles, vec![candle(10, (1.0, 1.0, 1.0, 1.0), (100, 100), 1)]);
+	}
+}
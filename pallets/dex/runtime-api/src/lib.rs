@@ -0,0 +1,93 @@
+//! Runtime API of the DEX pallet, for dashboards and RPCs to query pools without decoding storage.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use codec::Codec;
+pub use pallet_dex::{split_greedily, LpPosition, Route, StatsBucket, SwapSimulation};
+use sp_runtime::DispatchError;
+use sp_std::vec::Vec;
+
+sp_api::decl_runtime_apis! {
+	/// Queries of pools and routes.
+	///
+	/// Version 2 added `block_swaps`, version 3 the routing queries, version 4
+	/// `quote_split_exact_in` and version 5 `max_split_routes`; callers check the version before
+	/// calling them at older blocks.
+	#[api_version(5)]
+	pub trait DexApi<AssetId, Balance, BlockNumber>
+	where
+		AssetId: Codec,
+		Balance: Codec,
+		BlockNumber: Codec,
+	{
+		/// Trading statistics of the pool of `asset_a` and `asset_b`, oldest bucket first. Empty if
+		/// there is no such pool or it has not been traded recently.
+		fn pool_stats(asset_a: AssetId, asset_b: AssetId) -> Vec<StatsBucket<BlockNumber, Balance>>;
+
+		/// Swaps through the pool of `asset_a` and `asset_b` in the current block, in order, as the
+		/// amounts of `asset_a` and `asset_b` swapped.
+		fn block_swaps(asset_a: AssetId, asset_b: AssetId) -> Vec<(Balance, Balance)>;
+
+		/// Best path of at most `MaxHops` pools to sell exactly `amount_in` of `asset_in` for
+		/// `asset_out` at the current reserves, with the amounts it is expected to move. `None` if
+		/// the assets are not connected by pools.
+		fn best_route_exact_in(asset_in: AssetId, asset_out: AssetId, amount_in: Balance)
+			-> Option<Route<AssetId, Balance>>;
+
+		/// Best path of at most `MaxHops` pools to buy exactly `amount_out` of `asset_out` with
+		/// `asset_in` at the current reserves, with the amounts it is expected to move. `None` if
+		/// the assets are not connected by pools or cannot provide `amount_out`.
+		fn best_route_exact_out(asset_in: AssetId, asset_out: AssetId, amount_out: Balance)
+			-> Option<Route<AssetId, Balance>>;
+
+		/// Every path of at most `MaxHops` pools from `asset_in` to `asset_out`.
+		fn routes(asset_in: AssetId, asset_out: AssetId) -> Vec<Vec<AssetId>>;
+
+		/// Amounts received by each route of a trade split across `routes`, each a path and the
+		/// amount sold along it, at the current reserves. `None` if the split cannot be executed.
+		fn quote_split_exact_in(routes: Vec<(Vec<AssetId>, Balance)>) -> Option<Vec<Balance>>;
+
+		/// Most paths a single trade may be split across, `MaxSplitRoutes`.
+		fn max_split_routes() -> u32;
+	}
+
+	/// Dry runs of swaps, kept apart from `DexApi` as they depend on the trader's account.
+	pub trait DexSimulationApi<AccountId, AssetId, Balance>
+	where
+		AccountId: Codec,
+		AssetId: Codec,
+		Balance: Codec,
+	{
+		/// Outcome of `who` selling exactly `amount_in` of `asset_in` for at least
+		/// `amount_out_min` of `asset_out` now, or the error the swap would fail with.
+		fn simulate_swap_exact_in(
+			who: AccountId,
+			asset_in: AssetId,
+			asset_out: AssetId,
+			amount_in: Balance,
+			amount_out_min: Balance,
+		) -> Result<SwapSimulation<Balance>, DispatchError>;
+
+		/// Outcome of `who` buying exactly `amount_out` of `asset_out` with at most
+		/// `amount_in_max` of `asset_in` now, or the error the swap would fail with.
+		fn simulate_swap_exact_out(
+			who: AccountId,
+			asset_in: AssetId,
+			asset_out: AssetId,
+			amount_out: Balance,
+			amount_in_max: Balance,
+		) -> Result<SwapSimulation<Balance>, DispatchError>;
+	}
+
+	/// Liquidity positions of accounts.
+	pub trait DexPositionsApi<AccountId, AssetId, Balance>
+	where
+		AccountId: Codec,
+		AssetId: Codec,
+		Balance: Codec,
+	{
+		/// What the LP tokens `who` holds or stakes in each pool would pay out if withdrawn now,
+		/// net of the removal fee.
+		fn lp_positions(who: AccountId) -> Vec<LpPosition<AssetId, Balance>>;
+	}
+}
@@ -0,0 +1,380 @@
+//! Batch settlement of swap intents.
+//!
+//! Intents submitted during a block are escrowed in the pallet account and settled together in
+//! `on_finalize`. Opposing flows of a pair are crossed against each other and only the net
+//! imbalance is traded against the pool, so every intent of the pair fills at the same price.
+
+use crate::*;
+use frame_support::{
+	pallet_prelude::*,
+	sp_std::vec::Vec,
+	storage::with_storage_layer,
+	traits::{
+		fungibles::Mutate,
+		tokens::Preservation::{Expendable, Preserve},
+	},
+};
+use sp_runtime::traits::{CheckedAdd, CheckedSub, One, Zero};
+
+/// A swap submitted for batch settlement. Its `amount_in` is already held by the pallet account.
+#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebugNoBound, MaxEncodedLen, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+pub struct SwapIntent<T: Config> {
+	pub who: T::AccountId,
+	pub asset_in: AssetIdOf<T>,
+	pub asset_out: AssetIdOf<T>,
+	pub amount_in: BalanceOf<T>,
+	pub amount_out_min: BalanceOf<T>,
+}
+
+/// Outcome of crossing both sides of a pair at a single clearing price.
+pub(crate) struct Clearing<Balance> {
+	/// Amount of asset a sold into the pool by the net selling side.
+	pub pool_in_a: Balance,
+	/// Amount of asset b sold into the pool by the net selling side.
+	pub pool_in_b: Balance,
+	/// Amount of asset a bought from the pool.
+	pub pool_out_a: Balance,
+	/// Amount of asset b bought from the pool.
+	pub pool_out_b: Balance,
+	/// Total amount of asset a paid out to the sellers of asset b.
+	pub paid_a: Balance,
+	/// Total amount of asset b paid out to the sellers of asset a.
+	pub paid_b: Balance,
+}
+
+impl<T: Config> Pallet<T> {
+	/// Escrow `amount_in` and queue the swap for settlement at the end of the block.
+	pub fn do_submit_swap_intent(
+		who: T::AccountId,
+		asset_in: AssetIdOf<T>,
+		asset_out: AssetIdOf<T>,
+		amount_in: BalanceOf<T>,
+		amount_out_min: BalanceOf<T>,
+	) -> DispatchResult {
+		ensure!(amount_in > Zero::zero(), Error::<T>::InvalidAmount);
+
+		let pair = AssetPair::<T>::new(asset_in.clone(), asset_out.clone())?;
+		Self::ensure_pool_unlocked(&pair)?;
+		Self::execute_virtual_orders(&pair)?;
+		let pool_info = Pools::<T>::get(&pair).ok_or(Error::<T>::PoolNotFound)?;
+
+		// Intents are always settled against the pool, so it has to hold liquidity.
+		ensure!(
+			!pool_info.reserve_a.is_zero() && !pool_info.reserve_b.is_zero(),
+			Error::<T>::ReserveIsZero
+		);
+
+		T::Fungibles::transfer(asset_in.clone(), &who, &Self::account_id(), amount_in, Preserve)?;
+
+		SwapIntents::<T>::try_append(SwapIntent {
+			who: who.clone(),
+			asset_in: asset_in.clone(),
+			asset_out: asset_out.clone(),
+			amount_in,
+			amount_out_min,
+		})
+		.map_err(|_| Error::<T>::TooManySwapIntents)?;
+
+		Self::deposit_event(Event::SwapIntentSubmitted {
+			who,
+			asset_in,
+			asset_out,
+			amount_in,
+			amount_out_min,
+		});
+
+		Ok(())
+	}
+
+	/// Settle every intent queued during the block, pair by pair.
+	///
+	/// A pair whose settlement fails is rolled back and all of its intents are refunded, so one
+	/// broken pool never blocks the others.
+	pub(crate) fn settle_swap_intents() {
+		let intents = SwapIntents::<T>::take().into_inner();
+
+		let mut pairs: Vec<AssetPair<T>> = Vec::new();
+		for intent in intents.iter() {
+			if let Ok(pair) = AssetPair::<T>::new(intent.asset_in.clone(), intent.asset_out.clone()) {
+				if !pairs.contains(&pair) {
+					pairs.push(pair);
+				}
+			}
+		}
+
+		for pair in pairs {
+			let batch: Vec<SwapIntent<T>> = intents
+				.iter()
+				.filter(|intent| pair.contains(&intent.asset_in) && pair.contains(&intent.asset_out))
+				.cloned()
+				.collect();
+
+			if with_storage_layer(|| Self::settle_pair(&pair, batch.clone())).is_err() {
+				for intent in batch {
+					// Funds were escrowed on submission, so the refund can only fail if the
+					// pallet account was drained by other means. The escrow is then left for
+					// governance to recover.
+					let (who, asset, amount) =
+						(intent.who.clone(), intent.asset_in.clone(), intent.amount_in);
+					if let Err(error) = Self::refund_swap_intent(intent) {
+						log::error!(
+							target: LOG_TARGET,
+							"failed to refund {:?} of asset {:?} to {:?}: {:?}",
+							amount,
+							asset,
+							who,
+							error,
+						);
+						Self::deposit_event(Event::SwapIntentRefundFailed { who, asset, amount });
+					}
+				}
+			}
+		}
+	}
+
+	/// Settle the intents of a single pair at one clearing price.
+	///
+	/// Intents whose `amount_out_min` is not met at the clearing price are refunded and the price
+	/// is recomputed without them, until every remaining intent is satisfied.
+	fn settle_pair(pair: &AssetPair<T>, mut intents: Vec<SwapIntent<T>>) -> DispatchResult {
+		let mut pool_info = Pools::<T>::get(pair).ok_or(Error::<T>::PoolNotFound)?;
+		let mut refunds = Vec::new();
+
+		let (total_a, total_b, clearing, fills) = loop {
+			let (total_a, total_b) = Self::intent_totals(pair, &intents)?;
+			let clearing =
+				Self::clear_batch(pool_info.reserve_a, pool_info.reserve_b, total_a, total_b)?;
+
+			let mut fills = Vec::with_capacity(intents.len());
+			let mut unfilled = Vec::new();
+			for intent in intents {
+				let amount_out =
+					Self::intent_amount_out(pair, &intent, total_a, total_b, &clearing)?;
+				if amount_out >= intent.amount_out_min {
+					fills.push((intent, amount_out));
+				} else {
+					unfilled.push(intent);
+				}
+			}
+
+			if unfilled.is_empty() {
+				break (total_a, total_b, clearing, fills)
+			}
+
+			refunds.extend(unfilled);
+			intents = fills.into_iter().map(|(intent, _)| intent).collect();
+		};
+
+		pool_info.reserve_a = pool_info
+			.reserve_a
+			.checked_add(&clearing.pool_in_a)
+			.and_then(|reserve| reserve.checked_sub(&clearing.pool_out_a))
+			.ok_or(Error::<T>::Overflow)?;
+		pool_info.reserve_b = pool_info
+			.reserve_b
+			.checked_add(&clearing.pool_in_b)
+			.and_then(|reserve| reserve.checked_sub(&clearing.pool_out_b))
+			.ok_or(Error::<T>::Overflow)?;
+		Self::record_pool_trade(pair, &clearing, &pool_info);
+		Self::update_reserves(pair, pool_info);
+
+		for (intent, amount_out) in fills {
+			T::Fungibles::transfer(
+				intent.asset_out.clone(),
+				&Self::account_id(),
+				&intent.who,
+				amount_out,
+				Expendable,
+			)?;
+
+			Self::deposit_event(Event::SwapIntentFilled {
+				who: intent.who,
+				asset_in: intent.asset_in,
+				amount_in: intent.amount_in,
+				asset_out: intent.asset_out,
+				amount_out,
+			});
+		}
+
+		for intent in refunds {
+			Self::refund_swap_intent(intent)?;
+		}
+
+		if !total_a.is_zero() || !total_b.is_zero() {
+			Self::deposit_event(Event::BatchSettled {
+				pair: pair.clone(),
+				amount_a_in: total_a,
+				amount_b_in: total_b,
+				amount_a_out: clearing.paid_a,
+				amount_b_out: clearing.paid_b,
+			});
+		}
+
+		Ok(())
+	}
+
+	/// Record the imbalance `clearing` traded against the pool of `pair`, which it left with
+	/// `pool_info`, as a swap by the pallet account.
+	pub(crate) fn record_pool_trade(
+		pair: &AssetPair<T>,
+		clearing: &Clearing<BalanceOf<T>>,
+		pool_info: &PoolInfo<T>,
+	) {
+		let (asset_in, amount_in, asset_out, amount_out) = if !clearing.pool_in_a.is_zero() {
+			(pair.asset_a.clone(), clearing.pool_in_a, pair.asset_b.clone(), clearing.pool_out_b)
+		} else if !clearing.pool_in_b.is_zero() {
+			(pair.asset_b.clone(), clearing.pool_in_b, pair.asset_a.clone(), clearing.pool_out_a)
+		} else {
+			return
+		};
+
+		Self::record_trade(pair, asset_in.clone(), amount_in, amount_out, Self::pool_fee(amount_in));
+		Self::deposit_swap_events(
+			Self::account_id(),
+			pair.clone(),
+			asset_in,
+			amount_in,
+			asset_out,
+			amount_out,
+			pool_info,
+		);
+	}
+
+	/// Return the escrowed input of an intent to its owner.
+	fn refund_swap_intent(intent: SwapIntent<T>) -> DispatchResult {
+		T::Fungibles::transfer(
+			intent.asset_in.clone(),
+			&Self::account_id(),
+			&intent.who,
+			intent.amount_in,
+			Expendable,
+		)?;
+
+		Self::deposit_event(Event::SwapIntentRefunded {
+			who: intent.who,
+			asset_in: intent.asset_in,
+			amount_in: intent.amount_in,
+		});
+
+		Ok(())
+	}
+
+	/// Sum the inputs of the intents selling asset a and of those selling asset b.
+	fn intent_totals(
+		pair: &AssetPair<T>,
+		intents: &[SwapIntent<T>],
+	) -> Result<(BalanceOf<T>, BalanceOf<T>), Error<T>> {
+		let mut total_a: BalanceOf<T> = Zero::zero();
+		let mut total_b: BalanceOf<T> = Zero::zero();
+
+		for intent in intents {
+			if intent.asset_in == pair.asset_a {
+				total_a = total_a.checked_add(&intent.amount_in).ok_or(Error::<T>::Overflow)?;
+			} else {
+				total_b = total_b.checked_add(&intent.amount_in).ok_or(Error::<T>::Overflow)?;
+			}
+		}
+
+		Ok((total_a, total_b))
+	}
+
+	/// Pro-rata share of its side's payout that an intent receives.
+	fn intent_amount_out(
+		pair: &AssetPair<T>,
+		intent: &SwapIntent<T>,
+		total_a: BalanceOf<T>,
+		total_b: BalanceOf<T>,
+		clearing: &Clearing<BalanceOf<T>>,
+	) -> Result<BalanceOf<T>, Error<T>> {
+		if intent.asset_in == pair.asset_a {
+			Self::mul_div(intent.amount_in, clearing.paid_b, total_a)
+		} else {
+			Self::mul_div(intent.amount_in, clearing.paid_a, total_b)
+		}
+	}
+
+	/// Cross `total_a` of asset a against `total_b` of asset b, trading only the imbalance
+	/// against a pool holding `reserve_a` and `reserve_b`.
+	///
+	/// The side that is larger at the pool's spot price sells part of its input into the pool,
+	/// and the rest is matched against the other side at the pool trade's average price. Both
+	/// sides therefore trade at one uniform price.
+	pub(crate) fn clear_batch(
+		reserve_a: BalanceOf<T>,
+		reserve_b: BalanceOf<T>,
+		total_a: BalanceOf<T>,
+		total_b: BalanceOf<T>,
+	) -> Result<Clearing<BalanceOf<T>>, Error<T>> {
+		let zero: BalanceOf<T> = Zero::zero();
+
+		if Self::to_u256(total_a)? * Self::to_u256(reserve_b)? >=
+			Self::to_u256(total_b)? * Self::to_u256(reserve_a)?
+		{
+			let (into_pool, out_of_pool) =
+				Self::net_pool_trade(total_a, total_b, reserve_a, reserve_b)?;
+
+			Ok(Clearing {
+				pool_in_a: into_pool,
+				pool_in_b: zero,
+				pool_out_a: zero,
+				pool_out_b: out_of_pool,
+				paid_a: total_a.checked_sub(&into_pool).ok_or(Error::<T>::Overflow)?,
+				paid_b: total_b.checked_add(&out_of_pool).ok_or(Error::<T>::Overflow)?,
+			})
+		} else {
+			let (into_pool, out_of_pool) =
+				Self::net_pool_trade(total_b, total_a, reserve_b, reserve_a)?;
+
+			Ok(Clearing {
+				pool_in_a: zero,
+				pool_in_b: into_pool,
+				pool_out_a: out_of_pool,
+				pool_out_b: zero,
+				paid_a: total_a.checked_add(&out_of_pool).ok_or(Error::<T>::Overflow)?,
+				paid_b: total_b.checked_sub(&into_pool).ok_or(Error::<T>::Overflow)?,
+			})
+		}
+	}
+
+	/// Find how much of `total_in` the net selling side trades against the pool.
+	///
+	/// Returns the largest `x` for which the pool's average price for `x` is still at least the
+	/// price of crossing the remaining `total_in - x` against `total_opposite`, i.e.
+	/// `amount_out(x) * (total_in - x) >= total_opposite * x`, along with `amount_out(x)`.
+	fn net_pool_trade(
+		total_in: BalanceOf<T>,
+		total_opposite: BalanceOf<T>,
+		reserve_in: BalanceOf<T>,
+		reserve_out: BalanceOf<T>,
+	) -> Result<(BalanceOf<T>, BalanceOf<T>), Error<T>> {
+		let pool_not_worse = |x: BalanceOf<T>| -> bool {
+			Self::get_amount_out(x, reserve_in, reserve_out)
+				.and_then(|out| {
+					Ok(Self::to_u256(out)? * Self::to_u256(total_in - x)? >=
+						Self::to_u256(total_opposite)? * Self::to_u256(x)?)
+				})
+				.unwrap_or(false)
+		};
+
+		// The condition holds on `[0, x*]` and fails beyond it, so binary search for `x*`.
+		let two: BalanceOf<T> = 2u32.into();
+		let mut low: BalanceOf<T> = Zero::zero();
+		let mut high = total_in;
+		while low < high {
+			let mid = high - (high - low) / two;
+			if pool_not_worse(mid) {
+				low = mid;
+			} else {
+				high = mid - One::one();
+			}
+		}
+
+		if low.is_zero() {
+			return Ok((Zero::zero(), Zero::zero()))
+		}
+
+		let out = Self::get_amount_out(low, reserve_in, reserve_out)?;
+		Ok((low, out))
+	}
+}
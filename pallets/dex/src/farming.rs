@@ -0,0 +1,446 @@
+//! Liquidity mining.
+//!
+//! Governance can attach a farm to a pool, which emits `reward_per_block` of a reward asset to
+//! everyone staking the pool's LP tokens, pro rata to their stake. Emissions are tracked with a
+//! reward-per-share accumulator, so a staker's rewards are
+//! `stake * (reward_per_share_now - reward_per_share_at_last_update)`.
+//!
+//! Staked LP tokens and the reward assets to emit are both held by the farm's own account, which
+//! governance has to fund with the reward asset.
+//!
+//! LP tokens can also be staked locked for up to `MaxLockBlocks`, in exchange for extra reward
+//! shares growing linearly with the lock duration, up to `MaxLockBoost` of the amount locked. The
+//! extra shares are removed and the tokens become free to unstake once the lock expires.
+//!
+//! Expired positions are unlocked automatically in `on_initialize`, up to `MaxLockExpiries` per
+//! block, with positions past that limit queued in the next blocks. The owner can also unlock an
+//! expired position itself, should the automatic unlock not have happened yet or have failed.
+
+use crate::*;
+use frame_support::{
+	pallet_prelude::*,
+	sp_std::vec::Vec,
+	storage::with_storage_layer,
+	traits::{
+		fungibles::Mutate,
+		tokens::Preservation::{Expendable, Preserve},
+	},
+};
+use frame_system::pallet_prelude::BlockNumberFor;
+use sp_runtime::{
+	traits::{AccountIdConversion, CheckedAdd, CheckedSub, One, Saturating, Zero},
+	FixedPointNumber, FixedU128, Perbill, Rounding, SaturatedConversion,
+};
+
+/// Identifier of a locked LP position.
+pub type LockId = u32;
+
+/// A farm emitting rewards to the stakers of a pool's LP tokens.
+#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebugNoBound, MaxEncodedLen, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+pub struct Farm<T: Config> {
+	/// Asset the rewards are paid in.
+	pub reward_asset: AssetIdOf<T>,
+	/// Amount of `reward_asset` emitted every block.
+	pub reward_per_block: BalanceOf<T>,
+	/// Rewards emitted so far per reward share.
+	pub reward_per_share: FixedU128,
+	/// Total LP tokens staked in the farm.
+	pub total_staked: BalanceOf<T>,
+	/// Total reward shares: the staked LP tokens plus the boosts of locked positions.
+	pub total_shares: BalanceOf<T>,
+	/// Block up to which rewards have been emitted.
+	pub last_update: BlockNumberFor<T>,
+}
+
+/// LP tokens staked by an account in a farm.
+#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebugNoBound, MaxEncodedLen, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+pub struct FarmStake<T: Config> {
+	/// Staked LP tokens, locked or not.
+	pub amount: BalanceOf<T>,
+	/// Part of `amount` in locked positions, which cannot be unstaked yet.
+	pub locked: BalanceOf<T>,
+	/// Reward shares: `amount` plus the boosts of the locked positions.
+	pub shares: BalanceOf<T>,
+	/// Value of the farm's `reward_per_share` when rewards were last accrued to this stake.
+	pub reward_per_share_paid: FixedU128,
+	/// Rewards accrued but not claimed yet.
+	pub pending_rewards: BalanceOf<T>,
+}
+
+/// LP tokens staked in a farm until `expiry`, earning rewards on `amount + boost` shares.
+#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebugNoBound, MaxEncodedLen, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+pub struct LockedPosition<T: Config> {
+	pub pair: AssetPair<T>,
+	pub amount: BalanceOf<T>,
+	/// Extra reward shares granted for the lock.
+	pub boost: BalanceOf<T>,
+	/// Block at which the position unlocks.
+	pub expiry: BlockNumberFor<T>,
+}
+
+impl<T: Config> Pallet<T> {
+	/// Start emitting `reward_per_block` of `reward_asset` to the stakers of the pool's LP tokens.
+	pub fn do_create_farm(
+		asset_a: AssetIdOf<T>,
+		asset_b: AssetIdOf<T>,
+		reward_asset: AssetIdOf<T>,
+		reward_per_block: BalanceOf<T>,
+	) -> DispatchResult {
+		let pair = AssetPair::<T>::new(asset_a, asset_b)?;
+		ensure!(Pools::<T>::contains_key(&pair), Error::<T>::PoolNotFound);
+		ensure!(!Farms::<T>::contains_key(&pair), Error::<T>::FarmExists);
+		ensure!(Self::asset_exists(&reward_asset), Error::<T>::AssetNotExists);
+
+		Farms::<T>::insert(
+			&pair,
+			Farm {
+				reward_asset: reward_asset.clone(),
+				reward_per_block,
+				reward_per_share: Zero::zero(),
+				total_staked: Zero::zero(),
+				total_shares: Zero::zero(),
+				last_update: frame_system::Pallet::<T>::block_number(),
+			},
+		);
+
+		Self::deposit_event(Event::FarmCreated { pair, reward_asset, reward_per_block });
+
+		Ok(())
+	}
+
+	/// Change the emission rate of a farm, from the current block on.
+	pub fn do_set_farm_reward_rate(
+		asset_a: AssetIdOf<T>,
+		asset_b: AssetIdOf<T>,
+		reward_per_block: BalanceOf<T>,
+	) -> DispatchResult {
+		let pair = AssetPair::<T>::new(asset_a, asset_b)?;
+
+		Farms::<T>::try_mutate(&pair, |maybe_farm| -> DispatchResult {
+			let farm = maybe_farm.as_mut().ok_or(Error::<T>::FarmNotFound)?;
+			Self::update_farm(farm);
+			farm.reward_per_block = reward_per_block;
+			Ok(())
+		})?;
+
+		Self::deposit_event(Event::FarmRewardRateSet { pair, reward_per_block });
+
+		Ok(())
+	}
+
+	/// Stake `amount` of the pool's LP tokens in its farm.
+	pub fn do_stake(
+		who: T::AccountId,
+		asset_a: AssetIdOf<T>,
+		asset_b: AssetIdOf<T>,
+		amount: BalanceOf<T>,
+	) -> DispatchResult {
+		let pair = AssetPair::<T>::new(asset_a, asset_b)?;
+		Self::add_stake(&who, &pair, amount, Zero::zero(), Zero::zero())?;
+
+		Self::deposit_event(Event::Staked { who, pair, amount });
+
+		Ok(())
+	}
+
+	/// Stake `amount` of the pool's LP tokens locked for `lock_blocks` blocks, earning extra
+	/// reward shares until the lock expires.
+	pub fn do_stake_locked(
+		who: T::AccountId,
+		asset_a: AssetIdOf<T>,
+		asset_b: AssetIdOf<T>,
+		amount: BalanceOf<T>,
+		lock_blocks: BlockNumberFor<T>,
+	) -> Result<LockId, DispatchError> {
+		let max_lock_blocks = T::MaxLockBlocks::get();
+		ensure!(
+			!lock_blocks.is_zero() && lock_blocks <= max_lock_blocks,
+			Error::<T>::InvalidLockDuration
+		);
+
+		let pair = AssetPair::<T>::new(asset_a, asset_b)?;
+		let boost = Perbill::from_rational(
+			lock_blocks.saturated_into::<u128>(),
+			max_lock_blocks.saturated_into::<u128>(),
+		)
+		.mul_floor(T::MaxLockBoost::get().mul_floor(amount));
+		Self::add_stake(&who, &pair, amount, amount, boost)?;
+
+		let expiry = frame_system::Pallet::<T>::block_number().saturating_add(lock_blocks);
+		let lock_id = NextLockId::<T>::get();
+		NextLockId::<T>::put(lock_id.checked_add(1).ok_or(Error::<T>::Overflow)?);
+
+		Self::queue_lock_expiry(expiry, (who.clone(), lock_id))?;
+		LockedPositions::<T>::insert(&who, lock_id, LockedPosition { pair: pair.clone(), amount, boost, expiry });
+
+		Self::deposit_event(Event::LpLocked { who, pair, lock_id, amount, boost, expiry });
+
+		Ok(lock_id)
+	}
+
+	/// Locked positions of `who`, by lock id.
+	pub fn locked_positions(who: &T::AccountId) -> Vec<(LockId, LockedPosition<T>)> {
+		LockedPositions::<T>::iter_prefix(who).collect()
+	}
+
+	/// Queue a locked position to be unlocked at `expiry`, or in the first of the
+	/// `MaxLockExpiries` blocks after it with room left when that block is full.
+	fn queue_lock_expiry(expiry: BlockNumberFor<T>, lock: (T::AccountId, LockId)) -> DispatchResult {
+		let mut block = expiry;
+		for _ in 0..=T::MaxLockExpiries::get() {
+			if LockExpiries::<T>::decode_len(block).unwrap_or(0) < T::MaxLockExpiries::get() as usize {
+				return LockExpiries::<T>::try_append(block, lock)
+					.map_err(|_| Error::<T>::TooManyLockExpiries.into())
+			}
+			block = block.saturating_add(One::one());
+		}
+
+		Err(Error::<T>::TooManyLockExpiries.into())
+	}
+
+	/// Unlock an expired position of `who` on their request.
+	pub fn do_unlock(who: T::AccountId, lock_id: LockId) -> DispatchResult {
+		let position = LockedPositions::<T>::get(&who, lock_id).ok_or(Error::<T>::StakeNotFound)?;
+		ensure!(
+			position.expiry <= frame_system::Pallet::<T>::block_number(),
+			Error::<T>::LockNotExpired
+		);
+
+		Self::unlock_position(&who, lock_id)
+	}
+
+	/// Unlock the positions queued for block `now`, returning the weight used. A position that
+	/// fails to unlock is left untouched, for its owner to unlock with `unlock`.
+	pub(crate) fn unlock_expired_positions(now: BlockNumberFor<T>) -> Weight {
+		let expired = LockExpiries::<T>::take(now);
+		let count = expired.len() as u64;
+
+		for (who, lock_id) in expired {
+			let _ = with_storage_layer(|| Self::unlock_position(&who, lock_id));
+		}
+
+		T::DbWeight::get().reads_writes(1 + 4 * count, 1 + 4 * count)
+	}
+
+	/// Remove the boost of a locked position and let its LP tokens be unstaked.
+	fn unlock_position(who: &T::AccountId, lock_id: LockId) -> DispatchResult {
+		let position = LockedPositions::<T>::take(who, lock_id).ok_or(Error::<T>::StakeNotFound)?;
+		let pair = position.pair;
+
+		let mut farm = Farms::<T>::get(&pair).ok_or(Error::<T>::FarmNotFound)?;
+		let mut stake = FarmStakes::<T>::get(&pair, who).ok_or(Error::<T>::StakeNotFound)?;
+		Self::update_farm(&mut farm);
+		Self::accrue_stake_rewards(&farm, &mut stake)?;
+
+		stake.locked = stake.locked.saturating_sub(position.amount);
+		stake.shares = stake.shares.saturating_sub(position.boost);
+		farm.total_shares = farm.total_shares.saturating_sub(position.boost);
+
+		FarmStakes::<T>::insert(&pair, who, stake);
+		Farms::<T>::insert(&pair, farm);
+
+		Self::deposit_event(Event::LpUnlocked { who: who.clone(), pair, lock_id, amount: position.amount });
+
+		Ok(())
+	}
+
+	/// Move `amount` LP tokens of `who` into the farm of `pair`, `locked` of them in a locked
+	/// position granting `boost` extra reward shares.
+	fn add_stake(
+		who: &T::AccountId,
+		pair: &AssetPair<T>,
+		amount: BalanceOf<T>,
+		locked: BalanceOf<T>,
+		boost: BalanceOf<T>,
+	) -> DispatchResult {
+		ensure!(!amount.is_zero(), Error::<T>::InvalidAmount);
+
+		let pool_info = Pools::<T>::get(pair).ok_or(Error::<T>::PoolNotFound)?;
+		let mut farm = Farms::<T>::get(pair).ok_or(Error::<T>::FarmNotFound)?;
+		Self::update_farm(&mut farm);
+
+		let mut stake = FarmStakes::<T>::get(pair, who).unwrap_or(FarmStake {
+			amount: Zero::zero(),
+			locked: Zero::zero(),
+			shares: Zero::zero(),
+			reward_per_share_paid: farm.reward_per_share,
+			pending_rewards: Zero::zero(),
+		});
+		Self::accrue_stake_rewards(&farm, &mut stake)?;
+
+		T::PoolAssets::transfer(pool_info.lp_asset, who, &Self::farm_account(pair), amount, Expendable)?;
+
+		let shares = amount.checked_add(&boost).ok_or(Error::<T>::Overflow)?;
+		stake.amount = stake.amount.checked_add(&amount).ok_or(Error::<T>::Overflow)?;
+		stake.locked = stake.locked.checked_add(&locked).ok_or(Error::<T>::Overflow)?;
+		stake.shares = stake.shares.checked_add(&shares).ok_or(Error::<T>::Overflow)?;
+		farm.total_staked = farm.total_staked.checked_add(&amount).ok_or(Error::<T>::Overflow)?;
+		farm.total_shares = farm.total_shares.checked_add(&shares).ok_or(Error::<T>::Overflow)?;
+
+		FarmStakes::<T>::insert(pair, who, stake);
+		Farms::<T>::insert(pair, farm);
+
+		Ok(())
+	}
+
+	/// Unstake `amount` of LP tokens, keeping the rewards accrued so far for a later claim.
+	pub fn do_unstake(
+		who: T::AccountId,
+		asset_a: AssetIdOf<T>,
+		asset_b: AssetIdOf<T>,
+		amount: BalanceOf<T>,
+	) -> DispatchResult {
+		ensure!(!amount.is_zero(), Error::<T>::InvalidAmount);
+
+		let pair = AssetPair::<T>::new(asset_a, asset_b)?;
+		let pool_info = Pools::<T>::get(&pair).ok_or(Error::<T>::PoolNotFound)?;
+		let mut farm = Farms::<T>::get(&pair).ok_or(Error::<T>::FarmNotFound)?;
+		let mut stake = FarmStakes::<T>::get(&pair, &who).ok_or(Error::<T>::StakeNotFound)?;
+		Self::update_farm(&mut farm);
+		Self::accrue_stake_rewards(&farm, &mut stake)?;
+
+		let unlocked = stake.amount.saturating_sub(stake.locked);
+		ensure!(amount <= unlocked, Error::<T>::InsufficientStake);
+
+		stake.amount = stake.amount.checked_sub(&amount).ok_or(Error::<T>::Overflow)?;
+		stake.shares = stake.shares.checked_sub(&amount).ok_or(Error::<T>::Overflow)?;
+		farm.total_staked = farm.total_staked.checked_sub(&amount).ok_or(Error::<T>::Overflow)?;
+		farm.total_shares = farm.total_shares.checked_sub(&amount).ok_or(Error::<T>::Overflow)?;
+
+		T::PoolAssets::transfer(pool_info.lp_asset, &Self::farm_account(&pair), &who, amount, Expendable)?;
+
+		Self::store_stake(&pair, &who, stake);
+		Farms::<T>::insert(&pair, farm);
+
+		Self::deposit_event(Event::Unstaked { who, pair, amount });
+
+		Ok(())
+	}
+
+	/// Pay out the rewards accrued by a stake.
+	pub fn do_claim_rewards(
+		who: T::AccountId,
+		asset_a: AssetIdOf<T>,
+		asset_b: AssetIdOf<T>,
+	) -> Result<BalanceOf<T>, DispatchError> {
+		let pair = AssetPair::<T>::new(asset_a, asset_b)?;
+		let mut farm = Farms::<T>::get(&pair).ok_or(Error::<T>::FarmNotFound)?;
+		let mut stake = FarmStakes::<T>::get(&pair, &who).ok_or(Error::<T>::StakeNotFound)?;
+		Self::update_farm(&mut farm);
+		Self::accrue_stake_rewards(&farm, &mut stake)?;
+
+		let amount = stake.pending_rewards;
+		stake.pending_rewards = Zero::zero();
+
+		if !amount.is_zero() {
+			T::Fungibles::transfer(
+				farm.reward_asset.clone(),
+				&Self::farm_account(&pair),
+				&who,
+				amount,
+				Preserve,
+			)?;
+		}
+
+		let reward_asset = farm.reward_asset.clone();
+		Self::store_stake(&pair, &who, stake);
+		Farms::<T>::insert(&pair, farm);
+
+		Self::deposit_event(Event::RewardsClaimed { who, pair, reward_asset, amount });
+
+		Ok(amount)
+	}
+
+	/// Withdraw all unlocked LP tokens without accruing rewards, forfeiting any pending rewards.
+	///
+	/// Only touches the stake itself, so it keeps working even if reward accounting fails. Locked
+	/// positions stay in the farm until they expire.
+	pub fn do_emergency_withdraw(
+		who: T::AccountId,
+		asset_a: AssetIdOf<T>,
+		asset_b: AssetIdOf<T>,
+	) -> DispatchResult {
+		let pair = AssetPair::<T>::new(asset_a, asset_b)?;
+		let pool_info = Pools::<T>::get(&pair).ok_or(Error::<T>::PoolNotFound)?;
+		let mut stake = FarmStakes::<T>::take(&pair, &who).ok_or(Error::<T>::StakeNotFound)?;
+		let amount = stake.amount.saturating_sub(stake.locked);
+		ensure!(!amount.is_zero(), Error::<T>::InsufficientStake);
+
+		let reward_per_share = Farms::<T>::mutate(&pair, |maybe_farm| {
+			maybe_farm.as_mut().map(|farm| {
+				// Rewards emitted so far belong to the shares that earned them, this stake's
+				// included, so they are accounted before the shares are withdrawn.
+				Self::update_farm(farm);
+				farm.total_staked = farm.total_staked.saturating_sub(amount);
+				farm.total_shares = farm.total_shares.saturating_sub(amount);
+				farm.reward_per_share
+			})
+		});
+
+		if !stake.locked.is_zero() {
+			stake.amount = stake.locked;
+			stake.shares = stake.shares.saturating_sub(amount);
+			stake.pending_rewards = Zero::zero();
+			stake.reward_per_share_paid = reward_per_share.unwrap_or(stake.reward_per_share_paid);
+			FarmStakes::<T>::insert(&pair, &who, stake);
+		}
+
+		T::PoolAssets::transfer(pool_info.lp_asset, &Self::farm_account(&pair), &who, amount, Expendable)?;
+
+		Self::deposit_event(Event::EmergencyWithdrawn { who, pair, amount });
+
+		Ok(())
+	}
+
+	/// Account holding the staked LP tokens and the reward assets of a pool's farm.
+	pub fn farm_account(pair: &AssetPair<T>) -> T::AccountId {
+		T::PalletId::get().into_sub_account_truncating((b"farm", pair))
+	}
+
+	/// Emit the farm's rewards for every block since its last update.
+	fn update_farm(farm: &mut Farm<T>) {
+		let now = frame_system::Pallet::<T>::block_number();
+		if now <= farm.last_update {
+			return
+		}
+
+		if !farm.total_shares.is_zero() {
+			let blocks = now.saturating_sub(farm.last_update).saturated_into::<u128>();
+			let emitted = farm.reward_per_block.saturated_into::<u128>().saturating_mul(blocks);
+			farm.reward_per_share = farm.reward_per_share.saturating_add(FixedU128::from_rational_with_rounding(
+				emitted,
+				farm.total_shares.saturated_into::<u128>(),
+				Rounding::Down,
+			));
+		}
+
+		farm.last_update = now;
+	}
+
+	/// Move the rewards a stake earned since its last update into its pending rewards.
+	fn accrue_stake_rewards(farm: &Farm<T>, stake: &mut FarmStake<T>) -> Result<(), Error<T>> {
+		let earned: BalanceOf<T> = farm
+			.reward_per_share
+			.saturating_sub(stake.reward_per_share_paid)
+			.saturating_mul_int(stake.shares.saturated_into::<u128>())
+			.try_into()
+			.map_err(|_| Error::<T>::Overflow)?;
+
+		stake.pending_rewards = stake.pending_rewards.checked_add(&earned).ok_or(Error::<T>::Overflow)?;
+		stake.reward_per_share_paid = farm.reward_per_share;
+
+		Ok(())
+	}
+
+	/// Store a stake, removing it once nothing is staked or owed anymore.
+	fn store_stake(pair: &AssetPair<T>, who: &T::AccountId, stake: FarmStake<T>) {
+		if stake.amount.is_zero() && stake.pending_rewards.is_zero() {
+			FarmStakes::<T>::remove(pair, who);
+		} else {
+			FarmStakes::<T>::insert(pair, who, stake);
+		}
+	}
+}
@@ -0,0 +1,219 @@
+//! Flash swaps and flash loans.
+//!
+//! A flash swap sends the requested output to the caller first and only collects the input once
+//! a call of the caller's choosing has been dispatched, so the borrowed reserves can be put to use
+//! (e.g. an arbitrage on another pool) within the same extrinsic. The pool is locked while the
+//! inner call runs, and the constant product invariant is checked once the input is collected,
+//! against the amounts that actually left and reached the pallet account. Any failure reverts the
+//! whole extrinsic.
+//!
+//! A flash loan lends a single asset out of a pool's reserve and collects it back with a fee,
+//! which stays in the reserve for the pool's liquidity providers.
+
+use crate::*;
+use frame_support::{
+	pallet_prelude::*,
+	storage::with_storage_layer,
+	traits::{
+		fungibles::{Inspect, Mutate},
+		tokens::Preservation::Preserve,
+	},
+};
+use frame_system::RawOrigin;
+use sp_core::U256;
+use sp_runtime::{
+	traits::{CheckedAdd, CheckedSub, Dispatchable, One, Saturating, Zero},
+	PerThing,
+};
+
+impl<T: Config> Pallet<T> {
+	/// Send `amount_out` of `asset_out` to `who`, dispatch `call` as `who` and then collect the
+	/// input needed to restore the pool's invariant, at most `amount_in_max` of `asset_in`.
+	pub fn do_flash_swap(
+		who: T::AccountId,
+		asset_out: AssetIdOf<T>,
+		asset_in: AssetIdOf<T>,
+		amount_out: BalanceOf<T>,
+		amount_in_max: BalanceOf<T>,
+		call: <T as Config>::RuntimeCall,
+	) -> Result<BalanceOf<T>, DispatchError> {
+		ensure!(amount_out > Zero::zero(), Error::<T>::InvalidAmount);
+		ensure!(amount_in_max > Zero::zero(), Error::<T>::InvalidAmount);
+
+		let pair = AssetPair::<T>::new(asset_in.clone(), asset_out.clone())?;
+		Self::ensure_pool_unlocked(&pair)?;
+		Self::execute_virtual_orders(&pair)?;
+		let mut pool_info = Pools::<T>::get(&pair).ok_or(Error::<T>::PoolNotFound)?;
+
+		let (reserve_in, reserve_out) = if asset_in == pair.asset_a {
+			(pool_info.reserve_a, pool_info.reserve_b)
+		} else {
+			(pool_info.reserve_b, pool_info.reserve_a)
+		};
+
+		let amount_in = Self::get_flash_amount_in(amount_out, reserve_in, reserve_out)?;
+		ensure!(amount_in <= amount_in_max, Error::<T>::ProvidedMaximumNotSufficientForSwap);
+
+		// The pallet account holds every pool, so only its balance changes across our own transfers
+		// can be attributed to this pool; the inner call may move the same assets through others.
+		let account = Self::account_id();
+		let held_out = T::Fungibles::balance(asset_out.clone(), &account);
+		T::Fungibles::transfer(asset_out.clone(), &account, &who, amount_out, Preserve)?;
+		let sent = held_out.saturating_sub(T::Fungibles::balance(asset_out.clone(), &account));
+
+		// The pool's reserves are out of balance until the input is collected, so no other
+		// operation may touch it while the inner call runs.
+		FlashLocked::<T>::insert(&pair, ());
+		let result = call.dispatch(RawOrigin::Signed(who.clone()).into());
+		FlashLocked::<T>::remove(&pair);
+		result.map_err(|e| e.error)?;
+
+		let held_in = T::Fungibles::balance(asset_in.clone(), &account);
+		T::Fungibles::transfer(asset_in.clone(), &who, &account, amount_in, Preserve)?;
+		let received = T::Fungibles::balance(asset_in.clone(), &account).saturating_sub(held_in);
+
+		let new_reserve_in = reserve_in.checked_add(&received).ok_or(Error::<T>::Overflow)?;
+		let new_reserve_out = reserve_out.checked_sub(&sent).ok_or(Error::<T>::Overflow)?;
+
+		// Only the input net of the pool fee counts towards the invariant.
+		let adjusted_reserve_in = new_reserve_in
+			.checked_sub(&Self::pool_fee(received))
+			.ok_or(Error::<T>::Overflow)?;
+		ensure!(
+			Self::to_u256(adjusted_reserve_in)? * Self::to_u256(new_reserve_out)? >=
+				Self::to_u256(reserve_in)? * Self::to_u256(reserve_out)?,
+			Error::<T>::FlashSwapInvariantViolated
+		);
+
+		if asset_in == pair.asset_a {
+			pool_info.reserve_a = new_reserve_in;
+			pool_info.reserve_b = new_reserve_out;
+		} else {
+			pool_info.reserve_a = new_reserve_out;
+			pool_info.reserve_b = new_reserve_in;
+		}
+		Self::update_reserves(&pair, pool_info);
+		Self::record_trade(&pair, asset_in.clone(), received, sent, Self::pool_fee(received));
+
+		Self::deposit_event(Event::FlashSwapped {
+			who,
+			asset_out,
+			amount_out: sent,
+			asset_in,
+			amount_in: received,
+		});
+
+		Ok(received)
+	}
+
+	/// Smallest input that keeps `reserve_in * reserve_out` once `amount_out` has left the pool
+	/// and the pool fee has been taken from the input.
+	pub fn get_flash_amount_in(
+		amount_out: BalanceOf<T>,
+		reserve_in: BalanceOf<T>,
+		reserve_out: BalanceOf<T>,
+	) -> Result<BalanceOf<T>, Error<T>> {
+		ensure!(!reserve_in.is_zero() && !reserve_out.is_zero(), Error::<T>::ReserveIsZero);
+		ensure!(amount_out < reserve_out, Error::<T>::InsufficientAmount);
+
+		let fee_percent = T::PoolFeePercent::get();
+		ensure!(fee_percent < 100, Error::<T>::Overflow);
+
+		// amount_in * (100 - fee) / 100 >= reserve_in * amount_out / (reserve_out - amount_out)
+		let numerator = Self::to_u256(reserve_in)?
+			.checked_mul(Self::to_u256(amount_out)?)
+			.and_then(|n| n.checked_mul(U256::from(100u8)))
+			.ok_or(Error::<T>::Overflow)?;
+		let denominator = Self::to_u256(reserve_out - amount_out)?
+			.checked_mul(U256::from(100 - fee_percent))
+			.ok_or(Error::<T>::Overflow)?;
+
+		// Round up, plus one unit to absorb the rounding of the fee itself.
+		let amount_in = Self::from_u256(numerator / denominator)?;
+		amount_in
+			.checked_add(&One::one())
+			.and_then(|amount| amount.checked_add(&One::one()))
+			.ok_or(Error::<T>::Overflow)
+	}
+
+	/// Lend `amount` of `asset` out of the reserve of the pool of `asset` and `paired_asset`, run
+	/// `use_loan` and collect `amount` plus the flash loan fee back from `who`.
+	///
+	/// Runs in its own storage layer, so a failed loan leaves no trace. Returns the fee paid.
+	pub fn do_flash_loan<F: FnOnce() -> DispatchResult>(
+		who: T::AccountId,
+		asset: AssetIdOf<T>,
+		paired_asset: AssetIdOf<T>,
+		amount: BalanceOf<T>,
+		use_loan: F,
+	) -> Result<BalanceOf<T>, DispatchError> {
+		ensure!(amount > Zero::zero(), Error::<T>::InvalidAmount);
+
+		with_storage_layer(|| {
+			let pair = AssetPair::<T>::new(asset.clone(), paired_asset)?;
+			Self::ensure_pool_unlocked(&pair)?;
+			Self::execute_virtual_orders(&pair)?;
+			let mut pool_info = Pools::<T>::get(&pair).ok_or(Error::<T>::PoolNotFound)?;
+
+			let reserve = if asset == pair.asset_a { pool_info.reserve_a } else { pool_info.reserve_b };
+			ensure!(amount < reserve, Error::<T>::InsufficientLiquidity);
+
+			let fee = Self::flash_loan_fee(amount);
+			let repayment = amount.checked_add(&fee).ok_or(Error::<T>::Overflow)?;
+
+			T::Fungibles::transfer(asset.clone(), &Self::account_id(), &who, amount, Preserve)?;
+
+			FlashLocked::<T>::insert(&pair, ());
+			let result = use_loan();
+			FlashLocked::<T>::remove(&pair);
+			result?;
+
+			T::Fungibles::transfer(asset.clone(), &who, &Self::account_id(), repayment, Preserve)?;
+
+			// The fee stays in the reserve, accruing to the pool's liquidity providers.
+			if asset == pair.asset_a {
+				pool_info.reserve_a = pool_info.reserve_a.checked_add(&fee).ok_or(Error::<T>::Overflow)?;
+			} else {
+				pool_info.reserve_b = pool_info.reserve_b.checked_add(&fee).ok_or(Error::<T>::Overflow)?;
+			}
+			Self::update_reserves(&pair, pool_info);
+
+			Self::deposit_event(Event::FlashLoaned { who, asset, amount, fee });
+
+			Ok(fee)
+		})
+	}
+
+	/// Weight of a flash swap or flash loan: `call_weight` for the inner call, plus the pool lock,
+	/// the reserve update and the transfers out of and back into the pool.
+	pub fn flash_weight(call_weight: Weight) -> Weight {
+		T::DbWeight::get().reads_writes(8, 8).saturating_add(call_weight)
+	}
+
+	/// Fee charged for a flash loan of `amount`, rounded up.
+	pub fn flash_loan_fee(amount: BalanceOf<T>) -> BalanceOf<T> {
+		T::FlashLoanFee::get().mul_ceil(amount)
+	}
+
+	/// Fail if `pair` is in the middle of a flash swap or flash loan.
+	pub(crate) fn ensure_pool_unlocked(pair: &AssetPair<T>) -> Result<(), Error<T>> {
+		ensure!(!FlashLocked::<T>::contains_key(pair), Error::<T>::PoolLocked);
+		Ok(())
+	}
+}
+
+impl<T: Config> FlashLoan<T::AccountId, AssetIdOf<T>, BalanceOf<T>> for Pallet<T> {
+	fn flash_loan_fee(amount: BalanceOf<T>) -> BalanceOf<T> {
+		Self::flash_loan_fee(amount)
+	}
+
+	fn flash_loan<F: FnOnce() -> DispatchResult>(
+		borrower: &T::AccountId,
+		asset: AssetIdOf<T>,
+		paired_asset: AssetIdOf<T>,
+		amount: BalanceOf<T>,
+		use_loan: F,
+	) -> Result<BalanceOf<T>, DispatchError> {
+		Self::do_flash_loan(borrower.clone(), asset, paired_asset, amount, use_loan)
+	}
+}
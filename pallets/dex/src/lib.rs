@@ -9,15 +9,62 @@ pub use pallet::*;
 #[cfg(test)]
 mod mock;
 
+#[cfg(test)]
+mod mock_enum;
+
 #[cfg(test)]
 mod tests;
 
+#[cfg(test)]
+mod proptests;
+
 #[cfg(feature = "runtime-benchmarks")]
 mod benchmarking;
 
+mod batch;
+pub use batch::SwapIntent;
+
+mod twamm;
+pub use twamm::{LongTermOrder, LongTermOrderId, OrderExpiry, TwammPool};
+
+mod flash;
+
+pub mod traits;
+pub use traits::{FlashLoan, LpAssetIdGenerator, OrdPairOrdering, PairOrdering, RequestedLpAssetId, Swap};
+
+mod payment;
+pub use payment::DexFeeAdapter;
+
+mod farming;
+pub use farming::{Farm, FarmStake, LockId, LockedPosition};
+
+mod treasury;
+
+mod stats;
+pub use stats::StatsBucket;
+
+mod routing;
+pub use routing::{split_greedily, MaxPathLen, PathOf, Route, SplitRoutesOf};
+
+mod simulation;
+pub use simulation::SwapSimulation;
+
+mod positions;
+pub use positions::LpPosition;
+
+pub mod migrations;
+
+#[cfg(any(feature = "try-runtime", test))]
+mod try_state;
+
 use frame_support::traits::fungible;
 use frame_support::sp_runtime::traits::AccountIdConversion;
 use frame_support::traits::fungibles::Inspect;
+use sp_core::{U256, U512};
+use sp_runtime::DispatchError;
+
+/// Log target of the pallet.
+pub(crate) const LOG_TARGET: &str = "runtime::dex";
 
 pub type AssetIdOf<T> = <<T as Config>::Fungibles as fungibles::Inspect<
 	<T as frame_system::Config>::AccountId,
@@ -38,23 +85,29 @@ use crate::*;
 	use frame_support::{
 		pallet_prelude::{*}, traits::{
 			fungible::{self}, fungibles::{self}, tokens::{
-				Fortitude::{Polite}, Precision::{Exact}, Preservation::{Expendable, Preserve}
+				Fortitude::{Force, Polite}, Precision::{Exact}, Preservation::{Expendable, Preserve}
 			},
 		}, Blake2_128Concat, PalletId
 	};
 	use frame_system::pallet_prelude::*;
-	use sp_runtime::{traits::{One, Zero}, Percent};
+	use sp_runtime::{traits::{One, Zero}, FixedPointNumber, FixedU128, Perbill, Percent, Permill};
 	use frame_support::traits::fungibles::Create;
 	use frame_support::traits::fungibles::Mutate as FsMutate;
 	use frame_support::traits::fungible::Mutate as FMutate;
-	use sp_runtime::traits::IntegerSquareRoot;
+	use frame_support::traits::fungible::hold::Mutate as HoldMutate;
 	use sp_runtime::traits::CheckedAdd;
 	use sp_runtime::traits::CheckedSub;
-	use sp_runtime::traits::CheckedMul;
-	use sp_runtime::traits::CheckedDiv;
 	use sp_runtime::Saturating;
+	use sp_runtime::traits::Dispatchable;
+	use frame_support::dispatch::{GetDispatchInfo, PostDispatchInfo};
+	use frame_support::sp_std::boxed::Box;
+	use frame_support::sp_std::vec::Vec;
+
+	/// The current storage version.
+	const STORAGE_VERSION: StorageVersion = StorageVersion::new(2);
 
 	#[pallet::pallet]
+	#[pallet::storage_version(STORAGE_VERSION)]
 	pub struct Pallet<T>(_);
 
 	/// Configure the pallet by specifying the parameters and types on which it depends.
@@ -63,22 +116,45 @@ use crate::*;
 		/// Because this pallet emits events, it depends on the runtime's definition of an event.
 		type RuntimeEvent: From<Event<Self>> + IsType<<Self as frame_system::Config>::RuntimeEvent>;
 
+		/// The overarching call type, dispatched by flash swaps on behalf of the borrower.
+		type RuntimeCall: Parameter
+			+ Dispatchable<RuntimeOrigin = Self::RuntimeOrigin, PostInfo = PostDispatchInfo>
+			+ GetDispatchInfo
+			+ From<frame_system::Call<Self>>;
+
+		/// The overarching hold reason.
+		type RuntimeHoldReason: From<HoldReason>;
+
 		/// Type to access the Balances Pallet.
 		type NativeBalance: fungible::Inspect<Self::AccountId>
 			+ fungible::Mutate<Self::AccountId>
-			+ fungible::hold::Inspect<Self::AccountId>
+			+ fungible::hold::Inspect<Self::AccountId, Reason = Self::RuntimeHoldReason>
 			+ fungible::hold::Mutate<Self::AccountId>
 			+ fungible::freeze::Inspect<Self::AccountId>
 			+ fungible::freeze::Mutate<Self::AccountId>;
 
+		/// Orders the two assets of every pair.
+		type PairOrdering: PairOrdering<AssetIdOf<Self>>;
+
+		/// Generates the id of the LP token of a new pool.
+		type LpAssetIds: LpAssetIdGenerator<AssetIdOf<Self>>;
+
 		/// Type to access the Assets Pallet.
-		type Fungibles: fungibles::Inspect<Self::AccountId, Balance = BalanceOf<Self>, AssetId = u32>
+		type Fungibles: fungibles::Inspect<Self::AccountId, Balance = BalanceOf<Self>>
 			+ fungibles::Mutate<Self::AccountId>
 			+ fungibles::Create<Self::AccountId>;
 
+		/// Backend of the LP tokens, kept apart from `Fungibles` so that LP tokens cannot collide
+		/// with, or be mistaken for, traded assets.
+		type PoolAssets: fungibles::Inspect<Self::AccountId, Balance = BalanceOf<Self>, AssetId = AssetIdOf<Self>>
+			+ fungibles::Mutate<Self::AccountId>
+			+ fungibles::Create<Self::AccountId>
+			+ fungibles::Destroy<Self::AccountId>;
+
 		#[pallet::constant]
 		type PalletId: Get<PalletId>;
 
+		/// Deposit held from the creator of a pool until the pool is destroyed.
 		#[pallet::constant]
 		type PoolCreateFee: Get<BalanceOf<Self>>;
 
@@ -90,23 +166,110 @@ use crate::*;
 
 		#[pallet::constant]
 		type PoolFeePercent: Get<u8>;
+
+		/// Maximum number of swap intents that can be queued for batch settlement in one block.
+		#[pallet::constant]
+		type MaxSwapIntents: Get<u32>;
+
+		/// Maximum number of blocks a long-term order can be spread over.
+		#[pallet::constant]
+		type MaxOrderBlocks: Get<BlockNumberFor<Self>>;
+
+		/// Fee charged on flash loans, added to the reserve the loan was taken from.
+		#[pallet::constant]
+		type FlashLoanFee: Get<Permill>;
+
+		/// Asset that represents the native token in pools, backed 1:1 by native tokens held by the pallet.
+		#[pallet::constant]
+		type NativeAssetId: Get<AssetIdOf<Self>>;
+
+		/// Origin allowed to create farms and set their reward rates.
+		type FarmingOrigin: EnsureOrigin<Self::RuntimeOrigin>;
+
+		/// Longest duration LP tokens can be locked in a farm for.
+		#[pallet::constant]
+		type MaxLockBlocks: Get<BlockNumberFor<Self>>;
+
+		/// Extra reward shares, relative to the amount locked, granted for a lock of `MaxLockBlocks`.
+		#[pallet::constant]
+		type MaxLockBoost: Get<Perbill>;
+
+		/// Maximum number of locked positions that can expire in the same block.
+		#[pallet::constant]
+		type MaxLockExpiries: Get<u32>;
+
+		/// Origin allowed to add and remove liquidity on behalf of the treasury.
+		type TreasuryOrigin: EnsureOrigin<Self::RuntimeOrigin>;
+
+		/// Account providing protocol-owned liquidity and holding its LP tokens.
+		#[pallet::constant]
+		type TreasuryAccount: Get<Self::AccountId>;
+
+		/// Origin allowed to create pools, returning the account paying the creation fee.
+		type PoolCreationOrigin: EnsureOrigin<Self::RuntimeOrigin, Success = Self::AccountId>;
+
+		/// Origin allowed to manage the asset allow-list and the status of pools.
+		type AssetListingOrigin: EnsureOrigin<Self::RuntimeOrigin>;
+
+		/// Whether pools with assets off the allow-list can be created, flagged as unverified.
+		#[pallet::constant]
+		type AllowUnlistedPools: Get<bool>;
+
+		/// Whether the legacy `Swaped`, `LiquidityAdded` and `LiquidityRemoved` events are still
+		/// emitted alongside `SwapExecuted`, `LiquidityProvided` and `LiquidityWithdrawn`. Keep it
+		/// set until every event consumer has moved to the new events.
+		#[pallet::constant]
+		type LegacyEvents: Get<bool>;
+
+		/// Number of blocks aggregated in each bucket of pool statistics.
+		#[pallet::constant]
+		type StatsWindow: Get<BlockNumberFor<Self>>;
+
+		/// Number of the most recent buckets of statistics kept for each pool.
+		#[pallet::constant]
+		type MaxStatsBuckets: Get<u32>;
+
+		/// Most pools a swap path may go through.
+		#[pallet::constant]
+		type MaxHops: Get<u32>;
+
+		/// Most paths a single trade may be split across.
+		#[pallet::constant]
+		type MaxSplitRoutes: Get<u32>;
+
+		/// Most paths between two assets the route search lists, keeping its cost bounded in
+		/// densely connected pools.
+		#[pallet::constant]
+		type MaxRoutes: Get<u32>;
+
+		/// Most pools whose virtual orders `on_initialize` executes in a block. The others catch up
+		/// in the following blocks, or when they are next touched.
+		#[pallet::constant]
+		type MaxVirtualOrderPools: Get<u32>;
+	}
+
+	/// Reasons for the pallet to hold native funds.
+	#[pallet::composite_enum]
+	pub enum HoldReason {
+		/// Deposit paid by the creator of a pool, released when the pool is destroyed.
+		PoolCreation,
 	}
 
-	/// A struct representing a pair of ordered assets
-	#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebugNoBound, MaxEncodedLen, TypeInfo, Ord, PartialOrd)]
+	/// A struct representing a pair of assets, in the order given by `Config::PairOrdering`.
+	#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebugNoBound, MaxEncodedLen, TypeInfo)]
 	#[scale_info(skip_type_params(T))]
 	pub struct AssetPair<T: Config> {
 		/// The first asset in the pair.
-		asset_a: AssetIdOf<T>,
+		pub asset_a: AssetIdOf<T>,
 		/// The second asset in the pair.
-		asset_b: AssetIdOf<T>,
+		pub asset_b: AssetIdOf<T>,
 	}
 
 	impl<T: Config> AssetPair<T> {
 		pub fn new(asset_a: AssetIdOf<T>, asset_b: AssetIdOf<T>) -> Result<Self, Error<T>> {
 			ensure!(asset_a != asset_b, Error::<T>::IdenticalAssets);
 			
-			let (ordered_asset_a, ordered_asset_b) = if asset_a < asset_b {
+			let (ordered_asset_a, ordered_asset_b) = if T::PairOrdering::precedes(&asset_a, &asset_b) {
 				(asset_a, asset_b)
 			} else {
 				(asset_b, asset_a)
@@ -119,16 +282,31 @@ use crate::*;
 				}
 			)
 		}
+
+		/// Check whether `asset` is one of the two assets of the pair.
+		pub fn contains(&self, asset: &AssetIdOf<T>) -> bool {
+			&self.asset_a == asset || &self.asset_b == asset
+		}
 	}
 
 	/// A struct representing information about a liquidity pool.
 	/// Contains the identifier of the associated liquidity pool (LP) token.
-	#[derive(Clone, Copy, Encode, Decode, Eq, PartialEq, RuntimeDebugNoBound, MaxEncodedLen, TypeInfo)]
+	#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebugNoBound, MaxEncodedLen, TypeInfo)]
 	#[scale_info(skip_type_params(T))]
 	pub struct PoolInfo<T: Config> {
 		pub lp_asset: AssetIdOf<T>,
 		pub reserve_a: BalanceOf<T>,
 		pub reserve_b: BalanceOf<T>,
+		pub status: PoolStatus,
+	}
+
+	/// Whether a pool was vetted by governance.
+	#[derive(Clone, Copy, Encode, Decode, Eq, PartialEq, RuntimeDebug, MaxEncodedLen, TypeInfo)]
+	pub enum PoolStatus {
+		/// Both assets were on the allow-list when the pool was created, or governance verified it.
+		Verified,
+		/// At least one asset is not on the allow-list; trade at your own risk.
+		Unverified,
 	}
 
 	impl<T: Config> PoolInfo<T> {
@@ -138,7 +316,8 @@ use crate::*;
 
 			Ok(
 				PoolInfo {
-					lp_asset: self.lp_asset,
+					lp_asset: self.lp_asset.clone(),
+					status: self.status,
 					reserve_a,
 					reserve_b
 				}
@@ -151,7 +330,8 @@ use crate::*;
 
 			Ok(
 				PoolInfo {
-					lp_asset: self.lp_asset,
+					lp_asset: self.lp_asset.clone(),
+					status: self.status,
 					reserve_a,
 					reserve_b
 				}
@@ -164,7 +344,8 @@ use crate::*;
 
 			Ok(
 				PoolInfo {
-					lp_asset: self.lp_asset,
+					lp_asset: self.lp_asset.clone(),
+					status: self.status,
 					reserve_a,
 					reserve_b
 				}
@@ -177,6 +358,99 @@ use crate::*;
 	#[pallet::storage]
 	pub type Pools<T: Config> = StorageMap<_, Blake2_128Concat, AssetPair<T>, PoolInfo<T>>;
 
+	/// Swap intents submitted in the current block, settled together in `on_finalize`.
+	#[pallet::storage]
+	pub type SwapIntents<T: Config> =
+		StorageValue<_, BoundedVec<SwapIntent<T>, T::MaxSwapIntents>, ValueQuery>;
+
+	/// Sale rates and earnings accumulators of the long-term orders selling into each pool.
+	#[pallet::storage]
+	pub type TwammPools<T: Config> = StorageMap<_, Blake2_128Concat, AssetPair<T>, TwammPool<T>>;
+
+	/// Last pool whose virtual orders `on_initialize` executed, when pools were left for the
+	/// following blocks.
+	#[pallet::storage]
+	pub type VirtualOrdersCursor<T: Config> = StorageValue<_, AssetPair<T>>;
+
+	/// Long-term order sale rates ending at a block, per pool.
+	#[pallet::storage]
+	pub type OrderExpiries<T: Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		AssetPair<T>,
+		Twox64Concat,
+		BlockNumberFor<T>,
+		OrderExpiry<T>,
+		ValueQuery,
+	>;
+
+	/// Open long-term orders by id.
+	#[pallet::storage]
+	pub type LongTermOrders<T: Config> =
+		StorageMap<_, Twox64Concat, LongTermOrderId, LongTermOrder<T>>;
+
+	/// Id assigned to the next long-term order.
+	#[pallet::storage]
+	pub type NextLongTermOrderId<T: Config> = StorageValue<_, LongTermOrderId, ValueQuery>;
+
+	/// Creator and amount of the deposit held for each pool.
+	#[pallet::storage]
+	pub type PoolDeposits<T: Config> =
+		StorageMap<_, Blake2_128Concat, AssetPair<T>, (T::AccountId, BalanceOf<T>)>;
+
+	/// Assets vetted by governance, whose pools are created verified.
+	#[pallet::storage]
+	pub type AllowedAssets<T: Config> = StorageMap<_, Blake2_128Concat, AssetIdOf<T>, ()>;
+
+	/// Pools in the middle of a flash swap or flash loan, which cannot be used until it completes.
+	#[pallet::storage]
+	pub type FlashLocked<T: Config> = StorageMap<_, Blake2_128Concat, AssetPair<T>, ()>;
+
+	/// Liquidity mining farms, by the pool whose LP tokens they reward.
+	#[pallet::storage]
+	pub type Farms<T: Config> = StorageMap<_, Blake2_128Concat, AssetPair<T>, Farm<T>>;
+
+	/// LP tokens staked in each farm, by staker.
+	#[pallet::storage]
+	pub type FarmStakes<T: Config> = StorageDoubleMap<
+		_,
+		Blake2_128Concat,
+		AssetPair<T>,
+		Blake2_128Concat,
+		T::AccountId,
+		FarmStake<T>,
+	>;
+
+	/// Locked LP positions, by owner and lock id.
+	#[pallet::storage]
+	pub type LockedPositions<T: Config> =
+		StorageDoubleMap<_, Blake2_128Concat, T::AccountId, Twox64Concat, LockId, LockedPosition<T>>;
+
+	/// Locked positions unlocking at a block. A position is queued at its expiry, or in one of the
+	/// following blocks when too many positions already expire then.
+	#[pallet::storage]
+	pub type LockExpiries<T: Config> = StorageMap<
+		_,
+		Twox64Concat,
+		BlockNumberFor<T>,
+		BoundedVec<(T::AccountId, LockId), T::MaxLockExpiries>,
+		ValueQuery,
+	>;
+
+	/// Id assigned to the next locked position.
+	#[pallet::storage]
+	pub type NextLockId<T: Config> = StorageValue<_, LockId, ValueQuery>;
+
+	/// Trading statistics of each pool, oldest bucket first.
+	#[pallet::storage]
+	pub type PoolStats<T: Config> = StorageMap<
+		_,
+		Blake2_128Concat,
+		AssetPair<T>,
+		BoundedVec<StatsBucket<BlockNumberFor<T>, BalanceOf<T>>, T::MaxStatsBuckets>,
+		ValueQuery,
+	>;
+
 	// Pallets use events to inform users when important changes are made.
 	// https://docs.substrate.io/main-docs/build/events-errors/
 	#[pallet::event]
@@ -185,14 +459,173 @@ use crate::*;
 		/// Event emitted when a new liquidity pool is created.
 		PoolCreated { creator: T::AccountId, pair: AssetPair<T>, lp_asset: AssetIdOf<T> },
 
-		/// Event emitted when liquidity is added to a pool.
+		/// Event emitted when liquidity is added to a pool, while `LegacyEvents` is set.
+		/// Superseded by `LiquidityProvided`.
 		LiquidityAdded { sender: T::AccountId, pair: AssetPair<T>, lp_asset: AssetIdOf<T>, liqudity_provided_a: BalanceOf<T>, liqudity_provided_b: BalanceOf<T>, minted: BalanceOf<T> },
 		
-		/// Event emitted when liquidity is removed from a pool.
+		/// Event emitted when liquidity is removed from a pool, while `LegacyEvents` is set.
+		/// Superseded by `LiquidityWithdrawn`.
 		LiquidityRemoved { sender: T::AccountId, pair: AssetPair<T>, lp_asset: AssetIdOf<T>, liqudity_removed_a: BalanceOf<T>, liqudity_removed_b: BalanceOf<T>, burned: BalanceOf<T>, removal_fee: BalanceOf<T> },
 		
-		/// Event emitted when assets are swapped in a trade.
-		Swaped { sender: T::AccountId, asset_in: AssetIdOf<T>, amount_in: BalanceOf<T>, asset_out: AssetIdOf<T>, amount_out: BalanceOf<T> } 
+		/// Event emitted when assets are swapped in a trade, while `LegacyEvents` is set.
+		/// Superseded by `SwapExecuted`.
+		Swaped { sender: T::AccountId, asset_in: AssetIdOf<T>, amount_in: BalanceOf<T>, asset_out: AssetIdOf<T>, amount_out: BalanceOf<T> },
+
+		/// Event emitted when a swap intent is queued for batch settlement.
+		SwapIntentSubmitted { who: T::AccountId, asset_in: AssetIdOf<T>, asset_out: AssetIdOf<T>, amount_in: BalanceOf<T>, amount_out_min: BalanceOf<T> },
+
+		/// Event emitted when a swap intent is filled at the batch clearing price.
+		SwapIntentFilled { who: T::AccountId, asset_in: AssetIdOf<T>, amount_in: BalanceOf<T>, asset_out: AssetIdOf<T>, amount_out: BalanceOf<T> },
+
+		/// Event emitted when a swap intent is refunded because its minimum could not be met.
+		SwapIntentRefunded { who: T::AccountId, asset_in: AssetIdOf<T>, amount_in: BalanceOf<T> },
+
+		/// Event emitted when the intents of a pair are settled, with the totals of each side.
+		BatchSettled { pair: AssetPair<T>, amount_a_in: BalanceOf<T>, amount_b_in: BalanceOf<T>, amount_a_out: BalanceOf<T>, amount_b_out: BalanceOf<T> },
+
+		/// Event emitted when a long-term order starts selling `sale_rate` every block until `expiry`.
+		LongTermOrderSubmitted { order_id: LongTermOrderId, who: T::AccountId, asset_in: AssetIdOf<T>, asset_out: AssetIdOf<T>, sale_rate: BalanceOf<T>, expiry: BlockNumberFor<T> },
+
+		/// Event emitted when the proceeds of a long-term order are withdrawn.
+		LongTermProceedsWithdrawn { order_id: LongTermOrderId, who: T::AccountId, asset_out: AssetIdOf<T>, amount: BalanceOf<T>, completed: bool },
+
+		/// Event emitted when a long-term order is cancelled before its expiry.
+		LongTermOrderCancelled { order_id: LongTermOrderId, who: T::AccountId, unsold: BalanceOf<T>, proceeds: BalanceOf<T> },
+
+		/// Event emitted when a flash swap is repaid.
+		FlashSwapped { who: T::AccountId, asset_out: AssetIdOf<T>, amount_out: BalanceOf<T>, asset_in: AssetIdOf<T>, amount_in: BalanceOf<T> },
+
+		/// Event emitted when a flash loan is repaid with its fee.
+		FlashLoaned { who: T::AccountId, asset: AssetIdOf<T>, amount: BalanceOf<T>, fee: BalanceOf<T> },
+
+		/// Event emitted when native tokens are wrapped into the native asset.
+		NativeWrapped { who: T::AccountId, amount: BalanceOf<T> },
+
+		/// Event emitted when the native asset is unwrapped back into native tokens.
+		NativeUnwrapped { who: T::AccountId, amount: BalanceOf<T> },
+
+		/// Event emitted when a farm starts rewarding the stakers of a pool's LP tokens.
+		FarmCreated { pair: AssetPair<T>, reward_asset: AssetIdOf<T>, reward_per_block: BalanceOf<T> },
+
+		/// Event emitted when the reward rate of a farm is changed.
+		FarmRewardRateSet { pair: AssetPair<T>, reward_per_block: BalanceOf<T> },
+
+		/// Event emitted when LP tokens are staked in a farm.
+		Staked { who: T::AccountId, pair: AssetPair<T>, amount: BalanceOf<T> },
+
+		/// Event emitted when LP tokens are unstaked from a farm.
+		Unstaked { who: T::AccountId, pair: AssetPair<T>, amount: BalanceOf<T> },
+
+		/// Event emitted when farming rewards are claimed.
+		RewardsClaimed { who: T::AccountId, pair: AssetPair<T>, reward_asset: AssetIdOf<T>, amount: BalanceOf<T> },
+
+		/// Event emitted when a stake is withdrawn without its rewards.
+		EmergencyWithdrawn { who: T::AccountId, pair: AssetPair<T>, amount: BalanceOf<T> },
+
+		/// Event emitted when LP tokens are locked in a farm for boosted rewards.
+		LpLocked {
+			who: T::AccountId,
+			pair: AssetPair<T>,
+			lock_id: LockId,
+			amount: BalanceOf<T>,
+			boost: BalanceOf<T>,
+			expiry: BlockNumberFor<T>,
+		},
+
+		/// Event emitted when a locked position expires and its LP tokens can be unstaked.
+		LpUnlocked { who: T::AccountId, pair: AssetPair<T>, lock_id: LockId, amount: BalanceOf<T> },
+
+		/// Event emitted when the treasury adds liquidity to a pool.
+		TreasuryLiquidityAdded {
+			pair: AssetPair<T>,
+			amount_a: BalanceOf<T>,
+			amount_b: BalanceOf<T>,
+			minted: BalanceOf<T>,
+		},
+
+		/// Event emitted when the treasury removes liquidity from a pool.
+		TreasuryLiquidityRemoved {
+			pair: AssetPair<T>,
+			amount_a: BalanceOf<T>,
+			amount_b: BalanceOf<T>,
+			burned: BalanceOf<T>,
+		},
+
+		/// Event emitted when an asset is added to the allow-list.
+		AssetAllowed { asset: AssetIdOf<T> },
+
+		/// Event emitted when an asset is removed from the allow-list.
+		AssetDisallowed { asset: AssetIdOf<T> },
+
+		/// Event emitted when governance changes the status of a pool.
+		PoolStatusSet { pair: AssetPair<T>, status: PoolStatus },
+
+		/// Event emitted when an empty pool is destroyed and its creation deposit released.
+		PoolDestroyed { pair: AssetPair<T>, deposit_released: BalanceOf<T> },
+
+		/// Event emitted when governance slashes the creation deposit of a pool.
+		PoolDepositSlashed { pair: AssetPair<T>, creator: T::AccountId, amount: BalanceOf<T> },
+
+		/// Event emitted when assets are swapped in a pool, with the pool fee taken from the input
+		/// and the reserves after the trade.
+		SwapExecuted {
+			sender: T::AccountId,
+			pair: AssetPair<T>,
+			asset_in: AssetIdOf<T>,
+			amount_in: BalanceOf<T>,
+			asset_out: AssetIdOf<T>,
+			amount_out: BalanceOf<T>,
+			fee: BalanceOf<T>,
+			reserve_a: BalanceOf<T>,
+			reserve_b: BalanceOf<T>,
+		},
+
+		/// Event emitted when liquidity is added to a pool, with the reserves after the deposit.
+		LiquidityProvided {
+			sender: T::AccountId,
+			pair: AssetPair<T>,
+			lp_asset: AssetIdOf<T>,
+			amount_a: BalanceOf<T>,
+			amount_b: BalanceOf<T>,
+			minted: BalanceOf<T>,
+			reserve_a: BalanceOf<T>,
+			reserve_b: BalanceOf<T>,
+		},
+
+		/// Event emitted when liquidity is removed from a pool, with the removal fee charged in LP
+		/// tokens and the reserves after the withdrawal.
+		LiquidityWithdrawn {
+			sender: T::AccountId,
+			pair: AssetPair<T>,
+			lp_asset: AssetIdOf<T>,
+			amount_a: BalanceOf<T>,
+			amount_b: BalanceOf<T>,
+			burned: BalanceOf<T>,
+			fee: BalanceOf<T>,
+			reserve_a: BalanceOf<T>,
+			reserve_b: BalanceOf<T>,
+		},
+
+		/// Event emitted whenever the reserves of a pool change, with the new reserves.
+		ReservesUpdated { pair: AssetPair<T>, reserve_a: BalanceOf<T>, reserve_b: BalanceOf<T> },
+
+		/// Event emitted when a trade split across several paths was executed, with its totals.
+		SplitSwapExecuted {
+			sender: T::AccountId,
+			asset_in: AssetIdOf<T>,
+			amount_in: BalanceOf<T>,
+			asset_out: AssetIdOf<T>,
+			amount_out: BalanceOf<T>,
+			routes: u32,
+		},
+
+		/// Event emitted when the virtual orders of a pool failed to execute. They are retried the
+		/// next time the pool is touched.
+		VirtualOrdersFailed { pair: AssetPair<T>, error: DispatchError },
+
+		/// Event emitted when the escrow of a swap intent could not be refunded after its batch
+		/// failed to settle. The amount is left in the pallet account.
+		SwapIntentRefundFailed { who: T::AccountId, asset: AssetIdOf<T>, amount: BalanceOf<T> },
 	}
 
 	#[pallet::error]
@@ -247,6 +680,96 @@ use crate::*;
 
 		/// Indicates that the provided maximum is not sufficient for a swap.
 		ProvidedMaximumNotSufficientForSwap,
+
+		/// Indicates that the block already holds the maximum number of swap intents.
+		TooManySwapIntents,
+
+		/// Indicates a long-term order duration of zero or above `MaxOrderBlocks`.
+		InvalidOrderDuration,
+
+		/// Indicates that a long-term order was not found.
+		LongTermOrderNotFound,
+
+		/// Indicates that the caller does not own the long-term order.
+		NotOrderOwner,
+
+		/// Indicates that the long-term order has already run to its expiry.
+		LongTermOrderExpired,
+
+		/// Indicates that the pool is in the middle of a flash swap or flash loan.
+		PoolLocked,
+
+		/// Indicates that a flash swap did not restore the pool's invariant.
+		FlashSwapInvariantViolated,
+
+		/// Indicates an attempt to create a farm that already exists.
+		FarmExists,
+
+		/// Indicates that a requested farm was not found.
+		FarmNotFound,
+
+		/// Indicates that the account has no stake in the farm.
+		StakeNotFound,
+
+		/// Indicates an attempt to unstake more than is staked and unlocked.
+		InsufficientStake,
+
+		/// Indicates that a lock duration is zero or longer than `MaxLockBlocks`.
+		InvalidLockDuration,
+
+		/// Indicates that too many locked positions already expire in the same block and in the
+		/// `MaxLockExpiries` blocks after it.
+		TooManyLockExpiries,
+
+		/// Indicates an attempt to unlock a locked position before its expiry.
+		LockNotExpired,
+
+		/// Indicates that a pool asset is not on the allow-list.
+		AssetNotAllowed,
+
+		/// Indicates that only the creator of a pool can destroy it.
+		NotPoolCreator,
+
+		/// Indicates an attempt to destroy a pool that still has liquidity.
+		PoolNotEmpty,
+
+		/// Indicates an attempt to destroy a pool that still has a farm or long-term orders.
+		PoolInUse,
+
+		/// Indicates that a pool has no creation deposit held.
+		DepositNotFound,
+
+		/// Indicates a swap path through no pool or more than `MaxHops` pools, or visiting an asset
+		/// twice.
+		InvalidPath,
+
+		/// Indicates a split trade with no route, more than `MaxSplitRoutes` routes, or routes that
+		/// do not all go from the same asset to the same asset.
+		InvalidSplit,
+	}
+
+	#[pallet::hooks]
+	impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+		fn on_initialize(n: BlockNumberFor<T>) -> Weight {
+			let virtual_orders_weight = Self::execute_all_virtual_orders();
+			let unlock_weight = Self::unlock_expired_positions(n);
+
+			// Account for settling a full block of swap intents in `on_finalize`.
+			let max_intents = T::MaxSwapIntents::get() as u64;
+			T::DbWeight::get()
+				.reads_writes(1 + 2 * max_intents, 1 + 3 * max_intents)
+				.saturating_add(virtual_orders_weight)
+				.saturating_add(unlock_weight)
+		}
+
+		fn on_finalize(_n: BlockNumberFor<T>) {
+			Self::settle_swap_intents();
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn try_state(_n: BlockNumberFor<T>) -> Result<(), sp_runtime::TryRuntimeError> {
+			Self::do_try_state()
+		}
 	}
 
 	// Dispatchable functions allows users to interact with the pallet and invoke state changes.
@@ -256,63 +779,402 @@ use crate::*;
 	impl<T: Config> Pallet<T> {
 		/// Create a new liquidity pool with the specified assets and LP token.
 		/// This function performs various checks before creating the pool and minting the LP token.
+		/// The id of the LP token is generated by `Config::LpAssetIds` from `lp_asset`.
 		#[pallet::call_index(0)]
 		#[pallet::weight(Weight::default())]
-		pub fn create_pool(
+		pub fn create_pool(
+			origin: OriginFor<T>,
+			asset_a: AssetIdOf<T>,
+			asset_b: AssetIdOf<T>,
+			lp_asset: AssetIdOf<T>
+		) -> DispatchResult {
+			let creator = T::PoolCreationOrigin::ensure_origin(origin)?;
+
+			// Check if assets not identical
+			ensure!(asset_a != asset_b, Error::<T>::IdenticalAssets);
+
+			// Check if asset_a not exists
+			ensure!(Self::asset_exists(&asset_a), Error::<T>::AssetNotExists);
+			// Check if asset_b not exists
+			ensure!(Self::asset_exists(&asset_b), Error::<T>::AssetNotExists);
+
+			let pair = AssetPair::<T>::new(asset_a.clone(), asset_b.clone())?;
+
+			// Check it pair not exists yet
+			ensure!(!Pools::<T>::contains_key(&pair), Error::<T>::PoolExists);
+
+			// The runtime decides the LP token id, possibly from the one requested.
+			let lp_asset = T::LpAssetIds::lp_asset_id(&pair.asset_a, &pair.asset_b, lp_asset);
+			ensure!(!Self::lp_asset_exists(&lp_asset), Error::<T>::AssetIdAreadyTaken);
+
+			// Pools of unlisted assets are only allowed, flagged as unverified, if configured so.
+			let listed = AllowedAssets::<T>::contains_key(&asset_a) &&
+				AllowedAssets::<T>::contains_key(&asset_b);
+			let status = if listed {
+				PoolStatus::Verified
+			} else {
+				ensure!(T::AllowUnlistedPools::get(), Error::<T>::AssetNotAllowed);
+				PoolStatus::Unverified
+			};
+
+			// Hold the creation fee as a deposit, released when the pool is destroyed.
+			let deposit = T::PoolCreateFee::get();
+			T::NativeBalance::hold(&HoldReason::PoolCreation.into(), &creator, deposit)?;
+			PoolDeposits::<T>::insert(&pair, (creator.clone(), deposit));
+			
+			// Create a new LP token with a existential deposit of 1.
+			T::PoolAssets::create(
+				lp_asset.clone(),
+				Self::account_id(),
+				true,
+				T::MinBalance::get()
+			)?;
+
+			// Create a PoolInfo struct with LP asset information.
+			let pool_info = PoolInfo::<T> {
+				lp_asset: lp_asset.clone(),
+				reserve_a: Zero::zero(),
+				reserve_b: Zero::zero(),
+				status,
+			};
+			
+			// Insert the new pool information into the Pools storage.
+			Pools::<T>::insert(pair.clone(), pool_info);
+
+			// Emit a PoolCreated event to notify clients about the new pool creation.
+			Self::deposit_event(Event::PoolCreated { creator, pair, lp_asset });
+
+			Ok(())
+		}
+
+		/// Add liquidity to an existing pool by providing assets A and B.
+		/// This function calculates the optimal amount of assets to contribute based on the current reserves.
+		#[pallet::call_index(1)]
+		#[pallet::weight(Weight::default())]
+		pub fn add_liquidity(
+			origin: OriginFor<T>,
+			asset_a: AssetIdOf<T>,
+			asset_b: AssetIdOf<T>,
+			amount_a_desired: BalanceOf<T>,
+			amount_b_desired: BalanceOf<T>,
+			amount_a_min: BalanceOf<T>,
+			amount_b_min: BalanceOf<T>,
+			mint_to: T::AccountId,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_add_liquidity(
+				sender,
+				asset_a,
+				asset_b,
+				amount_a_desired,
+				amount_b_desired,
+				amount_a_min,
+				amount_b_min,
+				mint_to,
+			)?;
+
+			Ok(())
+		}
+
+		/// Remove liquidity from an existing pool by burning LP tokens.
+		/// This function calculates the amount of assets A and B to be received in return for burned LP tokens.
+		#[pallet::call_index(3)]
+		#[pallet::weight(Weight::default())]
+		pub fn remove_liquidity(
+			origin: OriginFor<T>,
+			asset_a: AssetIdOf<T>,
+			asset_b: AssetIdOf<T>,
+			lp_token_burn: BalanceOf<T>,
+			amount_a_min_receive: BalanceOf<T>,
+			amount_b_min_receive: BalanceOf<T>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_remove_liquidity(
+				sender,
+				asset_a,
+				asset_b,
+				lp_token_burn,
+				amount_a_min_receive,
+				amount_b_min_receive,
+			)?;
+
+			Ok(())
+		}
+
+		/// Execute a swap where the sender provides a specific amount of input asset to receive at least a minimum amount of output asset.
+		/// This function performs the swap and calculates the resulting amount based on the current reserves.
+		#[pallet::call_index(4)]
+		#[pallet::weight(Weight::default())]
+		pub fn swap_exact_asset_for_asset(
+			origin: OriginFor<T>,
+			exact_in: AssetIdOf<T>,
+			asset_out: AssetIdOf<T>,
+			amount_in: BalanceOf<T>,
+			amount_out_min: BalanceOf<T>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_swap_exact_asset_for_asset(
+				sender,
+				exact_in,
+				asset_out,
+				amount_in,
+				amount_out_min,
+			)?;
+
+			Ok(())
+		}
+
+		/// Execute a swap where the sender provides a maximum amount of input asset to receive a specific amount of output asset.
+		/// This function performs the swap and calculates the resulting amount based on the current reserves.
+		#[pallet::call_index(5)]
+		#[pallet::weight(Weight::default())]
+		pub fn swap_asset_for_exact_asset(
+			origin: OriginFor<T>,
+			asset_in: AssetIdOf<T>,
+			exact_out: AssetIdOf<T>,
+			amount_out: BalanceOf<T>,
+			amount_in_max: BalanceOf<T>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_swap_asset_for_exact_asset(
+				sender,
+				asset_in,
+				exact_out,
+				amount_out,
+				amount_in_max,
+			)?;
+
+			Ok(())
+		}
+
+		/// Submit a swap for batch settlement at the end of the block.
+		/// The input is escrowed now. All intents of a pair fill at the block's uniform clearing price,
+		/// and an intent that would receive less than `amount_out_min` is refunded instead.
+		#[pallet::call_index(6)]
+		#[pallet::weight(Weight::default())]
+		pub fn submit_swap_intent(
+			origin: OriginFor<T>,
+			asset_in: AssetIdOf<T>,
+			asset_out: AssetIdOf<T>,
+			amount_in: BalanceOf<T>,
+			amount_out_min: BalanceOf<T>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_submit_swap_intent(
+				sender,
+				asset_in,
+				asset_out,
+				amount_in,
+				amount_out_min,
+			)
+		}
+
+		/// Sell `amount_in` of `asset_in` for `asset_out` evenly over the next `number_of_blocks` blocks.
+		/// The order is executed against the pool as a virtual order, and its proceeds accrue until withdrawn.
+		#[pallet::call_index(7)]
+		#[pallet::weight(Weight::default())]
+		pub fn submit_long_term_order(
+			origin: OriginFor<T>,
+			asset_in: AssetIdOf<T>,
+			asset_out: AssetIdOf<T>,
+			amount_in: BalanceOf<T>,
+			number_of_blocks: BlockNumberFor<T>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_submit_long_term_order(
+				sender,
+				asset_in,
+				asset_out,
+				amount_in,
+				number_of_blocks,
+			)?;
+
+			Ok(())
+		}
+
+		/// Withdraw the proceeds a long-term order has earned so far.
+		/// Once the order has run to its expiry, this also closes it.
+		#[pallet::call_index(8)]
+		#[pallet::weight(Weight::default())]
+		pub fn withdraw_long_term_proceeds(
+			origin: OriginFor<T>,
+			order_id: LongTermOrderId,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_withdraw_long_term_proceeds(sender, order_id)?;
+
+			Ok(())
+		}
+
+		/// Cancel a long-term order before its expiry.
+		/// The unsold input is refunded together with the proceeds earned so far.
+		#[pallet::call_index(9)]
+		#[pallet::weight(Weight::default())]
+		pub fn cancel_long_term_order(
+			origin: OriginFor<T>,
+			order_id: LongTermOrderId,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_cancel_long_term_order(sender, order_id)
+		}
+
+		/// Receive `amount_out` of `asset_out` up front, dispatch `call` as the sender, then pay back
+		/// at most `amount_in_max` of `asset_in`, enough to restore the pool's invariant with fee.
+		/// The whole extrinsic is reverted if the repayment cannot be made.
+		#[pallet::call_index(10)]
+		#[pallet::weight(Pallet::<T>::flash_weight(call.get_dispatch_info().weight))]
+		pub fn flash_swap(
+			origin: OriginFor<T>,
+			asset_out: AssetIdOf<T>,
+			asset_in: AssetIdOf<T>,
+			amount_out: BalanceOf<T>,
+			amount_in_max: BalanceOf<T>,
+			call: Box<<T as Config>::RuntimeCall>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_flash_swap(sender, asset_out, asset_in, amount_out, amount_in_max, *call)?;
+
+			Ok(())
+		}
+
+		/// Borrow `amount` of `asset` from the pool of `asset` and `paired_asset`, dispatch `call`
+		/// as the sender, then repay `amount` plus the flash loan fee.
+		/// The whole extrinsic is reverted if the repayment cannot be made.
+		#[pallet::call_index(11)]
+		#[pallet::weight(Pallet::<T>::flash_weight(call.get_dispatch_info().weight))]
+		pub fn flash_loan(
+			origin: OriginFor<T>,
+			asset: AssetIdOf<T>,
+			paired_asset: AssetIdOf<T>,
+			amount: BalanceOf<T>,
+			call: Box<<T as Config>::RuntimeCall>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			let origin: T::RuntimeOrigin = frame_system::RawOrigin::Signed(sender.clone()).into();
+			Self::do_flash_loan(sender, asset, paired_asset, amount, || {
+				call.dispatch(origin).map(|_| ()).map_err(|e| e.error)
+			})?;
+
+			Ok(())
+		}
+
+		/// Lock `amount` of native tokens and receive as much of the wrapped native asset, which can be traded in pools.
+		#[pallet::call_index(12)]
+		#[pallet::weight(Weight::default())]
+		pub fn wrap_native(origin: OriginFor<T>, amount: BalanceOf<T>) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_wrap_native(sender, amount)
+		}
+
+		/// Burn `amount` of the wrapped native asset and receive as many native tokens back.
+		#[pallet::call_index(13)]
+		#[pallet::weight(Weight::default())]
+		pub fn unwrap_native(origin: OriginFor<T>, amount: BalanceOf<T>) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_unwrap_native(sender, amount)
+		}
+
+		/// Create a farm emitting `reward_per_block` of `reward_asset` to the stakers of the pool's LP tokens.
+		/// The farm account has to be funded with the reward asset separately.
+		#[pallet::call_index(14)]
+		#[pallet::weight(Weight::default())]
+		pub fn create_farm(
+			origin: OriginFor<T>,
+			asset_a: AssetIdOf<T>,
+			asset_b: AssetIdOf<T>,
+			reward_asset: AssetIdOf<T>,
+			reward_per_block: BalanceOf<T>,
+		) -> DispatchResult {
+			T::FarmingOrigin::ensure_origin(origin)?;
+			Self::do_create_farm(asset_a, asset_b, reward_asset, reward_per_block)
+		}
+
+		/// Change the amount of reward asset a farm emits every block.
+		#[pallet::call_index(15)]
+		#[pallet::weight(Weight::default())]
+		pub fn set_farm_reward_rate(
+			origin: OriginFor<T>,
+			asset_a: AssetIdOf<T>,
+			asset_b: AssetIdOf<T>,
+			reward_per_block: BalanceOf<T>,
+		) -> DispatchResult {
+			T::FarmingOrigin::ensure_origin(origin)?;
+			Self::do_set_farm_reward_rate(asset_a, asset_b, reward_per_block)
+		}
+
+		/// Stake LP tokens of a pool in its farm to earn rewards.
+		#[pallet::call_index(16)]
+		#[pallet::weight(Weight::default())]
+		pub fn stake(
+			origin: OriginFor<T>,
+			asset_a: AssetIdOf<T>,
+			asset_b: AssetIdOf<T>,
+			amount: BalanceOf<T>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_stake(sender, asset_a, asset_b, amount)
+		}
+
+		/// Unstake LP tokens from a farm. Rewards accrued so far can still be claimed.
+		#[pallet::call_index(17)]
+		#[pallet::weight(Weight::default())]
+		pub fn unstake(
 			origin: OriginFor<T>,
 			asset_a: AssetIdOf<T>,
 			asset_b: AssetIdOf<T>,
-			lp_asset: AssetIdOf<T>
+			amount: BalanceOf<T>,
 		) -> DispatchResult {
-			let creator = ensure_signed(origin)?;
-
-			// Check if assets not identical
-			ensure!(asset_a != asset_b, Error::<T>::IdenticalAssets);
-
-			// Check if asset_a not exists
-			ensure!(Self::asset_exists(&asset_a), Error::<T>::AssetNotExists);
-			// Check if asset_b not exists
-			ensure!(Self::asset_exists(&asset_b), Error::<T>::AssetNotExists);
-
-			let pair = AssetPair::<T>::new(asset_a, asset_b)?;
-
-			// Check it pair not exists yet
-			ensure!(!Pools::<T>::contains_key(&pair), Error::<T>::PoolExists);
+			let sender = ensure_signed(origin)?;
+			Self::do_unstake(sender, asset_a, asset_b, amount)
+		}
 
-			ensure!(!Self::asset_exists(&lp_asset), Error::<T>::AssetIdAreadyTaken);
+		/// Claim the rewards accrued by the sender's stake in a farm.
+		#[pallet::call_index(18)]
+		#[pallet::weight(Weight::default())]
+		pub fn claim_rewards(
+			origin: OriginFor<T>,
+			asset_a: AssetIdOf<T>,
+			asset_b: AssetIdOf<T>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_claim_rewards(sender, asset_a, asset_b)?;
 
-			// Pay the creation pool fees
-			T::NativeBalance::transfer(&creator, &Self::account_id(), T::PoolCreateFee::get(), Preserve)?;
-			
-			// Create a new LP token with a existential deposit of 1.
-			T::Fungibles::create(
-				lp_asset,
-				Self::account_id(),
-				true,
-				T::MinBalance::get()
-			)?;
+			Ok(())
+		}
 
-			// Create a PoolInfo struct with LP asset information.
-			let pool_info = PoolInfo::<T> {
-				lp_asset: lp_asset.clone(),
-				reserve_a: Zero::zero(),
-				reserve_b: Zero::zero(),
-			};
-			
-			// Insert the new pool information into the Pools storage.
-			Pools::<T>::insert(pair.clone(), pool_info);
+		/// Withdraw all unlocked LP tokens staked in a farm, giving up any unclaimed rewards.
+		#[pallet::call_index(19)]
+		#[pallet::weight(Weight::default())]
+		pub fn emergency_withdraw(
+			origin: OriginFor<T>,
+			asset_a: AssetIdOf<T>,
+			asset_b: AssetIdOf<T>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_emergency_withdraw(sender, asset_a, asset_b)
+		}
 
-			// Emit a PoolCreated event to notify clients about the new pool creation.
-			Self::deposit_event(Event::PoolCreated { creator, pair, lp_asset });
+		/// Stake LP tokens in a farm locked for `lock_blocks` blocks, for boosted rewards.
+		/// The tokens stay staked once the lock expires, and can then be unstaked.
+		#[pallet::call_index(20)]
+		#[pallet::weight(Weight::default())]
+		pub fn stake_locked(
+			origin: OriginFor<T>,
+			asset_a: AssetIdOf<T>,
+			asset_b: AssetIdOf<T>,
+			amount: BalanceOf<T>,
+			lock_blocks: BlockNumberFor<T>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_stake_locked(sender, asset_a, asset_b, amount, lock_blocks)?;
 
 			Ok(())
 		}
 
-		/// Add liquidity to an existing pool by providing assets A and B.
-		/// This function calculates the optimal amount of assets to contribute based on the current reserves.
-		#[pallet::call_index(1)]
+		/// Add liquidity from the treasury account, which receives the LP tokens.
+		#[pallet::call_index(21)]
 		#[pallet::weight(Weight::default())]
-		pub fn add_liquidity(
+		pub fn treasury_add_liquidity(
 			origin: OriginFor<T>,
 			asset_a: AssetIdOf<T>,
 			asset_b: AssetIdOf<T>,
@@ -320,26 +1182,22 @@ use crate::*;
 			amount_b_desired: BalanceOf<T>,
 			amount_a_min: BalanceOf<T>,
 			amount_b_min: BalanceOf<T>,
-			mint_to: T::AccountId,
 		) -> DispatchResult {
-			let sender = ensure_signed(origin)?;
-			Self::do_add_liquidity(
-				sender,
+			T::TreasuryOrigin::ensure_origin(origin)?;
+			Self::do_treasury_add_liquidity(
 				asset_a,
 				asset_b,
 				amount_a_desired,
 				amount_b_desired,
 				amount_a_min,
 				amount_b_min,
-				mint_to,
 			)
 		}
 
-		/// Remove liquidity from an existing pool by burning LP tokens.
-		/// This function calculates the amount of assets A and B to be received in return for burned LP tokens.
-		#[pallet::call_index(3)]
+		/// Remove liquidity by burning LP tokens of the treasury account, which receives the assets.
+		#[pallet::call_index(22)]
 		#[pallet::weight(Weight::default())]
-		pub fn remove_liquidity(
+		pub fn treasury_remove_liquidity(
 			origin: OriginFor<T>,
 			asset_a: AssetIdOf<T>,
 			asset_b: AssetIdOf<T>,
@@ -347,9 +1205,8 @@ use crate::*;
 			amount_a_min_receive: BalanceOf<T>,
 			amount_b_min_receive: BalanceOf<T>,
 		) -> DispatchResult {
-			let sender = ensure_signed(origin)?;
-			Self::do_remove_liquidity(
-				sender,
+			T::TreasuryOrigin::ensure_origin(origin)?;
+			Self::do_treasury_remove_liquidity(
 				asset_a,
 				asset_b,
 				lp_token_burn,
@@ -358,51 +1215,184 @@ use crate::*;
 			)
 		}
 
-		/// Execute a swap where the sender provides a specific amount of input asset to receive at least a minimum amount of output asset.
-		/// This function performs the swap and calculates the resulting amount based on the current reserves.
-		#[pallet::call_index(4)]
+		/// Add an asset to the allow-list, so that pools between listed assets are created verified.
+		#[pallet::call_index(23)]
 		#[pallet::weight(Weight::default())]
-		pub fn swap_exact_asset_for_asset(
+		pub fn allow_asset(origin: OriginFor<T>, asset: AssetIdOf<T>) -> DispatchResult {
+			T::AssetListingOrigin::ensure_origin(origin)?;
+			ensure!(Self::asset_exists(&asset), Error::<T>::AssetNotExists);
+
+			AllowedAssets::<T>::insert(&asset, ());
+			Self::deposit_event(Event::AssetAllowed { asset });
+
+			Ok(())
+		}
+
+		/// Remove an asset from the allow-list. Existing pools keep their status.
+		#[pallet::call_index(24)]
+		#[pallet::weight(Weight::default())]
+		pub fn disallow_asset(origin: OriginFor<T>, asset: AssetIdOf<T>) -> DispatchResult {
+			T::AssetListingOrigin::ensure_origin(origin)?;
+
+			AllowedAssets::<T>::remove(&asset);
+			Self::deposit_event(Event::AssetDisallowed { asset });
+
+			Ok(())
+		}
+
+		/// Set whether a pool is verified.
+		#[pallet::call_index(25)]
+		#[pallet::weight(Weight::default())]
+		pub fn set_pool_status(
 			origin: OriginFor<T>,
-			exact_in: AssetIdOf<T>,
-			asset_out: AssetIdOf<T>,
+			asset_a: AssetIdOf<T>,
+			asset_b: AssetIdOf<T>,
+			status: PoolStatus,
+		) -> DispatchResult {
+			T::AssetListingOrigin::ensure_origin(origin)?;
+
+			let pair = AssetPair::<T>::new(asset_a, asset_b)?;
+			Pools::<T>::try_mutate(&pair, |maybe_pool| -> DispatchResult {
+				let pool_info = maybe_pool.as_mut().ok_or(Error::<T>::PoolNotFound)?;
+				pool_info.status = status;
+				Ok(())
+			})?;
+
+			Self::deposit_event(Event::PoolStatusSet { pair, status });
+
+			Ok(())
+		}
+
+		/// Destroy a pool without liquidity, farm or long-term orders, along with its LP token,
+		/// releasing its creation deposit. Only the creator of the pool can destroy it, unless no
+		/// deposit is held for it, as for pools created before deposits or whose deposit was slashed.
+		#[pallet::call_index(26)]
+		#[pallet::weight(Weight::default())]
+		pub fn destroy_pool(
+			origin: OriginFor<T>,
+			asset_a: AssetIdOf<T>,
+			asset_b: AssetIdOf<T>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+
+			let pair = AssetPair::<T>::new(asset_a, asset_b)?;
+			Self::ensure_pool_unlocked(&pair)?;
+			let pool_info = Pools::<T>::get(&pair).ok_or(Error::<T>::PoolNotFound)?;
+			let deposit = PoolDeposits::<T>::get(&pair);
+			if let Some((creator, _)) = &deposit {
+				ensure!(sender == *creator, Error::<T>::NotPoolCreator);
+			}
+			ensure!(Self::lp_total_issuance(pool_info.lp_asset.clone()).is_zero(), Error::<T>::PoolNotEmpty);
+			// Stakers' unclaimed rewards and orders' unwithdrawn proceeds still refer to the pool.
+			ensure!(
+				!Farms::<T>::contains_key(&pair) &&
+					!TwammPools::<T>::contains_key(&pair) &&
+					OrderExpiries::<T>::iter_prefix(&pair).next().is_none(),
+				Error::<T>::PoolInUse
+			);
+
+			let deposit_released = match deposit {
+				Some((creator, deposit)) => {
+					T::NativeBalance::release(&HoldReason::PoolCreation.into(), &creator, deposit, Exact)?;
+					PoolDeposits::<T>::remove(&pair);
+					deposit
+				},
+				None => Zero::zero(),
+			};
+			Self::destroy_lp_asset(pool_info.lp_asset)?;
+			Pools::<T>::remove(&pair);
+			PoolStats::<T>::remove(&pair);
+
+			Self::deposit_event(Event::PoolDestroyed { pair, deposit_released });
+
+			Ok(())
+		}
+
+		/// Burn the creation deposit of a pool, e.g. one set up for a scam token.
+		#[pallet::call_index(27)]
+		#[pallet::weight(Weight::default())]
+		pub fn slash_pool_deposit(
+			origin: OriginFor<T>,
+			asset_a: AssetIdOf<T>,
+			asset_b: AssetIdOf<T>,
+		) -> DispatchResult {
+			T::AssetListingOrigin::ensure_origin(origin)?;
+
+			let pair = AssetPair::<T>::new(asset_a, asset_b)?;
+			let (creator, deposit) = PoolDeposits::<T>::take(&pair).ok_or(Error::<T>::DepositNotFound)?;
+			let amount = T::NativeBalance::burn_held(
+				&HoldReason::PoolCreation.into(),
+				&creator,
+				deposit,
+				Exact,
+				Force,
+			)?;
+
+			Self::deposit_event(Event::PoolDepositSlashed { pair, creator, amount });
+
+			Ok(())
+		}
+
+		/// Sell exactly `amount_in` of the first asset of `path` for at least `amount_out_min` of
+		/// its last asset, swapping through the pool of each consecutive pair of assets of `path`.
+		#[pallet::call_index(28)]
+		#[pallet::weight(Pallet::<T>::path_weight(path))]
+		pub fn swap_exact_in_along_path(
+			origin: OriginFor<T>,
+			path: PathOf<T>,
 			amount_in: BalanceOf<T>,
 			amount_out_min: BalanceOf<T>,
 		) -> DispatchResult {
 			let sender = ensure_signed(origin)?;
-			Self::do_swap_exact_asset_for_asset(
-				sender,
-				exact_in,
-				asset_out,
-				amount_in,
-				amount_out_min,
-			)?;
+
+			Self::do_swap_exact_in_along_path(sender, path.into_inner(), amount_in, amount_out_min)?;
 
 			Ok(())
 		}
 
-		/// Execute a swap where the sender provides a maximum amount of input asset to receive a specific amount of output asset.
-		/// This function performs the swap and calculates the resulting amount based on the current reserves.
-		#[pallet::call_index(5)]
-		#[pallet::weight(Weight::default())]
-		pub fn swap_asset_for_exact_asset(
+		/// Buy exactly `amount_out` of the last asset of `path` with at most `amount_in_max` of its
+		/// first asset, swapping through the pool of each consecutive pair of assets of `path`.
+		#[pallet::call_index(29)]
+		#[pallet::weight(Pallet::<T>::path_weight(path))]
+		pub fn swap_exact_out_along_path(
 			origin: OriginFor<T>,
-			asset_in: AssetIdOf<T>,
-			exact_out: AssetIdOf<T>,
+			path: PathOf<T>,
 			amount_out: BalanceOf<T>,
 			amount_in_max: BalanceOf<T>,
 		) -> DispatchResult {
 			let sender = ensure_signed(origin)?;
-			Self::do_swap_asset_for_exact_asset(
-				sender,
-				asset_in,
-				exact_out,
-				amount_out,
-				amount_in_max,
-			)?;
+
+			Self::do_swap_exact_out_along_path(sender, path.into_inner(), amount_out, amount_in_max)?;
 
 			Ok(())
 		}
+
+		/// Sell the amount of each of `routes` along its path, all at once, for at least
+		/// `amount_out_min` of the last asset in total. Splitting a large trade across parallel
+		/// paths lowers its price impact.
+		#[pallet::call_index(30)]
+		#[pallet::weight(Pallet::<T>::split_weight(routes))]
+		pub fn swap_exact_in_along_paths(
+			origin: OriginFor<T>,
+			routes: SplitRoutesOf<T>,
+			amount_out_min: BalanceOf<T>,
+		) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+
+			let routes =
+				routes.into_iter().map(|(path, amount)| (path.into_inner(), amount)).collect();
+			Self::do_swap_exact_in_along_paths(sender, routes, amount_out_min)?;
+
+			Ok(())
+		}
+
+		/// Unlock one of the sender's expired locked positions that was not unlocked at expiry.
+		#[pallet::call_index(31)]
+		#[pallet::weight(Weight::default())]
+		pub fn unlock(origin: OriginFor<T>, lock_id: LockId) -> DispatchResult {
+			let sender = ensure_signed(origin)?;
+			Self::do_unlock(sender, lock_id)
+		}
 	}
 
 	impl<T: Config> Pallet<T> {
@@ -415,15 +1405,17 @@ use crate::*;
 			amount_a_min: BalanceOf<T>,
 			amount_b_min: BalanceOf<T>,
 			mint_to: T::AccountId,
-		) -> Result<(), DispatchError> {
+		) -> Result<(BalanceOf<T>, BalanceOf<T>, BalanceOf<T>), DispatchError> {
 			ensure!(asset_a != asset_b, Error::<T>::IdenticalAssets);
 
-			let pair = AssetPair::<T>::new(asset_a, asset_b)?;
+			let pair = AssetPair::<T>::new(asset_a.clone(), asset_b.clone())?;
 
+			Self::ensure_pool_unlocked(&pair)?;
+			Self::execute_virtual_orders(&pair)?;
 			let pool_info = Pools::<T>::get(&pair).ok_or(Error::<T>::PoolNotFound)?;
 
 			// Ensure that the LP token associated with the pool exists.
-			ensure!(Self::asset_exists(&pool_info.lp_asset), Error::<T>::LiquidityPoolTokenNotExists);
+			ensure!(Self::lp_asset_exists(&pool_info.lp_asset), Error::<T>::LiquidityPoolTokenNotExists);
 
 			ensure!(amount_a_desired > Zero::zero() && amount_b_desired > Zero::zero(), Error::<T>::InvalidDesiredAmount);
 			
@@ -463,14 +1455,14 @@ use crate::*;
 			T::Fungibles::transfer(asset_a, &sender, &Self::account_id(), amount_a, Preserve)?;
 			T::Fungibles::transfer(asset_b, &sender, &Self::account_id(), amount_b, Preserve)?;
 
-			let total_supply = Self::total_issuance(pool_info.lp_asset.clone());
+			let total_supply = Self::lp_total_issuance(pool_info.lp_asset.clone());
 
 			// Calculate the amount of LP tokens to mint based on the provided assets.
 			let lp_asset_amount: BalanceOf<T>;
 			if total_supply.is_zero() {
 				lp_asset_amount = Self::get_lp_amount_for_zero_supply(amount_a, amount_b)?;
 				
-				T::Fungibles::mint_into(
+				T::PoolAssets::mint_into(
 					pool_info.lp_asset.clone(),
 					&Self::account_id(),
 					T::MinLiquidity::get(),
@@ -482,26 +1474,60 @@ use crate::*;
 			}
 
 			// Mint the calculated amount of LP tokens to the specified account.
-			T::Fungibles::mint_into(pool_info.lp_asset.clone(), &mint_to, lp_asset_amount)?;
+			T::PoolAssets::mint_into(pool_info.lp_asset.clone(), &mint_to, lp_asset_amount)?;
 
 			// Update reserves
 			let pool_info = pool_info.add_reserves(&amount_a, &amount_b)?;
-			Pools::<T>::set(&pair, Some(pool_info.clone()));
+			Self::update_reserves(&pair, pool_info.clone());
+
+			if T::LegacyEvents::get() {
+				Self::deposit_event(Event::<T>::LiquidityAdded {
+					sender: sender.clone(),
+					pair: pair.clone(),
+					liqudity_provided_a: amount_a,
+					liqudity_provided_b: amount_b,
+					lp_asset: pool_info.lp_asset.clone(),
+					minted: lp_asset_amount
+				});
+			}
 
-			Self::deposit_event(Event::<T>::LiquidityAdded {
+			Self::deposit_event(Event::<T>::LiquidityProvided {
 				sender,
 				pair,
-				liqudity_provided_a: amount_a,
-				liqudity_provided_b: amount_b,
-				lp_asset: pool_info.lp_asset, 
-				minted: lp_asset_amount
+				lp_asset: pool_info.lp_asset,
+				amount_a,
+				amount_b,
+				minted: lp_asset_amount,
+				reserve_a: pool_info.reserve_a,
+				reserve_b: pool_info.reserve_b,
 			});
 
-			Ok(())
+			Ok((amount_a, amount_b, lp_asset_amount))
 		}
 
-		pub fn update_reserves() {
+		/// Removal fee, in LP tokens, and amounts of the pool's assets paid out for burning
+		/// `lp_amount` of its LP tokens at the reserves of `pool_info`.
+		pub(crate) fn withdrawal_amounts(
+			pool_info: &PoolInfo<T>,
+			lp_amount: BalanceOf<T>,
+		) -> Result<(BalanceOf<T>, BalanceOf<T>, BalanceOf<T>), Error<T>> {
+			let total_supply = Self::lp_total_issuance(pool_info.lp_asset.clone());
+			let fee = Self::pool_fee(lp_amount);
+			let lp_redeem_amount = lp_amount.saturating_sub(fee);
+
+			let amount_a = Self::mul_div(lp_redeem_amount, pool_info.reserve_a, total_supply)?;
+			let amount_b = Self::mul_div(lp_redeem_amount, pool_info.reserve_b, total_supply)?;
+			Ok((fee, amount_a, amount_b))
+		}
 
+		/// Store the new reserves of `pair` and announce them with `ReservesUpdated`.
+		pub fn update_reserves(pair: &AssetPair<T>, pool_info: PoolInfo<T>) {
+			Self::deposit_event(Event::ReservesUpdated {
+				pair: pair.clone(),
+				reserve_a: pool_info.reserve_a,
+				reserve_b: pool_info.reserve_b,
+			});
+			Pools::<T>::insert(pair, pool_info);
 		}
 
 		pub fn do_remove_liquidity(
@@ -511,26 +1537,23 @@ use crate::*;
 			lp_token_burn: BalanceOf<T>,
 			amount_a_min_receive: BalanceOf<T>,
 			amount_b_min_receive: BalanceOf<T>,
-		) -> Result<(), DispatchError> {
+		) -> Result<(BalanceOf<T>, BalanceOf<T>), DispatchError> {
 			ensure!(asset_a != asset_b, Error::<T>::IdenticalAssets);
 
 			ensure!(lp_token_burn > Zero::zero(), Error::<T>::InvalidLiquidityAmount);
 
-			let pair  = AssetPair::<T>::new(asset_a, asset_b)?;
+			let pair  = AssetPair::<T>::new(asset_a.clone(), asset_b.clone())?;
+			Self::ensure_pool_unlocked(&pair)?;
+			// Liquidity can be withdrawn even while the pool fails to execute its virtual orders.
+			Self::try_execute_virtual_orders(&pair);
 			let pool_info = Pools::<T>::get(pair.clone())
 				.ok_or(Error::<T>::PoolNotFound)?;
 			
 			let reserve_a = pool_info.reserve_a;
 			let reserve_b = pool_info.reserve_b;
 
-			let total_supply = Self::total_issuance(pool_info.lp_asset.clone());
-			let removal_fee_amount = Self::pool_fee(lp_token_burn);
-
-			let lp_redeem_amount = lp_token_burn.saturating_sub(removal_fee_amount);
-
 			// Calculate the amounts of assets A and B to be received in return for the burned LP tokens.
-			let amount_a = Self::mul_div(lp_redeem_amount, reserve_a, total_supply)?;
-			let amount_b = Self::mul_div(lp_redeem_amount, reserve_b, total_supply)?;
+			let (removal_fee_amount, amount_a, amount_b) = Self::withdrawal_amounts(&pool_info, lp_token_burn)?;
 
 			ensure!(
 				!amount_a.is_zero() && amount_a >= amount_a_min_receive,
@@ -554,27 +1577,41 @@ use crate::*;
 			);
 
 			// Burn the specified amount of LP tokens from the sender's account.
-			T::Fungibles::burn_from(pool_info.lp_asset.clone(), &sender, lp_token_burn, Exact, Polite)?;
+			T::PoolAssets::burn_from(pool_info.lp_asset.clone(), &sender, lp_token_burn, Exact, Polite)?;
 
 			// Update reserves
 			let pool_info = pool_info.sub_reserves(&amount_a, &amount_b)?;
-			Pools::<T>::set(&pair, Some(pool_info.clone()));
+			Self::update_reserves(&pair, pool_info.clone());
 
 			// Transfer the received amounts of assets A and B back to the sender's account.
 			T::Fungibles::transfer(asset_a, &Self::account_id(), &sender, amount_a, Expendable)?;
 			T::Fungibles::transfer(asset_b, &Self::account_id(), &sender, amount_b, Expendable)?;
 
-			Self::deposit_event(Event::LiquidityRemoved {
-				sender, 
-				pair, 
-				lp_asset: pool_info.lp_asset, 
-				liqudity_removed_a: amount_a,
-				liqudity_removed_b: amount_b,
+			if T::LegacyEvents::get() {
+				Self::deposit_event(Event::LiquidityRemoved {
+					sender: sender.clone(),
+					pair: pair.clone(),
+					lp_asset: pool_info.lp_asset.clone(),
+					liqudity_removed_a: amount_a,
+					liqudity_removed_b: amount_b,
+					burned: lp_token_burn,
+					removal_fee: removal_fee_amount
+				});
+			}
+
+			Self::deposit_event(Event::LiquidityWithdrawn {
+				sender,
+				pair,
+				lp_asset: pool_info.lp_asset,
+				amount_a,
+				amount_b,
 				burned: lp_token_burn,
-				removal_fee: removal_fee_amount
+				fee: removal_fee_amount,
+				reserve_a: pool_info.reserve_a,
+				reserve_b: pool_info.reserve_b,
 			});
 
-			Ok(())
+			Ok((amount_a, amount_b))
 		}
 		
 		/// Internal helper function for executing a swap where the sender provides a specific amount of input asset to receive at least a minimum amount of output asset.
@@ -589,7 +1626,9 @@ use crate::*;
 			ensure!(amount_in > Zero::zero(), Error::<T>::InvalidAmount);
 			ensure!(amount_out_min > Zero::zero(), Error::<T>::InvalidAmount);
 
-			let pair = AssetPair::<T>::new(exact_in, asset_out)?;
+			let pair = AssetPair::<T>::new(exact_in.clone(), asset_out.clone())?;
+			Self::ensure_pool_unlocked(&pair)?;
+			Self::execute_virtual_orders(&pair)?;
 			let mut pool_info = Pools::<T>::get(&pair).ok_or(Error::<T>::PoolNotFound)?;
 
 			let reserve_in = pool_info.reserve_a;
@@ -597,7 +1636,7 @@ use crate::*;
 
 			let amount_out: BalanceOf<T>;
 			// Calculate the amount of output asset based on the provided input amount and reserves.
-			if exact_in < asset_out {
+			if exact_in == pair.asset_a {
 				amount_out = Self::get_amount_out(amount_in.clone(), reserve_in, reserve_out)?;
 				pool_info.reserve_a = pool_info.reserve_a.checked_add(&amount_in).ok_or(Error::<T>::Overflow)?;
 				pool_info.reserve_b = pool_info.reserve_b.checked_sub(&amount_out).ok_or(Error::<T>::Overflow)?;
@@ -616,23 +1655,16 @@ use crate::*;
 			T::Fungibles::transfer(asset_out.clone(), &Self::account_id(), &sender, amount_out.clone(), Preserve)?;
 
 			// Update reserves
-			Pools::<T>::set(&pair, Some(pool_info.clone()));
+			Self::update_reserves(&pair, pool_info.clone());
+			Self::record_trade(&pair, exact_in.clone(), amount_in, amount_out, Self::pool_fee(amount_in));
 
-			Self::deposit_event(
-				Event::Swaped {
-					sender,
-					asset_in: exact_in,
-					amount_in,
-					asset_out,
-					amount_out,
-				}
-			);
+			Self::deposit_swap_events(sender, pair, exact_in, amount_in, asset_out, amount_out, &pool_info);
 
 			Ok(amount_out)
 		}
 
 		/// Internal helper function for executing a swap where the sender provides a maximum amount of input asset to receive a specific amount of output asset.
-		/// This function performs the swap and calculates the resulting amount based on the current reserves.
+		/// This function performs the swap and returns the amount of input asset it took, based on the current reserves.
 		pub fn do_swap_asset_for_exact_asset(
 			sender: T::AccountId,
 			asset_in: AssetIdOf<T>,
@@ -643,7 +1675,9 @@ use crate::*;
 			ensure!(amount_out > Zero::zero(), Error::<T>::InvalidAmount);
 			ensure!(amount_in_max > Zero::zero(), Error::<T>::InvalidAmount);
 
-			let pair = AssetPair::<T>::new(asset_in, exact_out)?;
+			let pair = AssetPair::<T>::new(asset_in.clone(), exact_out.clone())?;
+			Self::ensure_pool_unlocked(&pair)?;
+			Self::execute_virtual_orders(&pair)?;
 			let mut pool_info = Pools::<T>::get(&pair).ok_or(Error::<T>::PoolNotFound)?;
 
 			let reserve_in = pool_info.reserve_a;
@@ -651,7 +1685,7 @@ use crate::*;
 
 			let amount_in: BalanceOf<T>;
 			// Calculate the amount of input asset based on the desired output amount and reserves.
-			if asset_in < exact_out {
+			if asset_in == pair.asset_a {
 				amount_in = Self::get_amount_in(amount_out.clone(), reserve_in, reserve_out)?;
 				pool_info.reserve_a = pool_info.reserve_a.checked_add(&amount_in).ok_or(Error::<T>::Overflow)?;
 				pool_info.reserve_b = pool_info.reserve_b.checked_sub(&amount_out).ok_or(Error::<T>::Overflow)?;
@@ -670,19 +1704,45 @@ use crate::*;
 			T::Fungibles::transfer(exact_out.clone(), &Self::account_id(), &sender, amount_out.clone(), Preserve)?;
 
 			// Update reserves
-			Pools::<T>::set(&pair, Some(pool_info.clone()));
+			Self::update_reserves(&pair, pool_info.clone());
+			Self::record_trade(&pair, asset_in.clone(), amount_in, amount_out, Self::pool_fee(amount_in));
+
+			Self::deposit_swap_events(sender, pair, asset_in, amount_in, exact_out, amount_out, &pool_info);
 
-			Self::deposit_event(
-				Event::Swaped {
-					sender,
-					asset_in,
+			Ok(amount_in)
+		}
+
+		/// Announce a swap through `pair`, which left the pool with the reserves of `pool_info`.
+		pub(crate) fn deposit_swap_events(
+			sender: T::AccountId,
+			pair: AssetPair<T>,
+			asset_in: AssetIdOf<T>,
+			amount_in: BalanceOf<T>,
+			asset_out: AssetIdOf<T>,
+			amount_out: BalanceOf<T>,
+			pool_info: &PoolInfo<T>,
+		) {
+			if T::LegacyEvents::get() {
+				Self::deposit_event(Event::Swaped {
+					sender: sender.clone(),
+					asset_in: asset_in.clone(),
 					amount_in,
-					asset_out: exact_out,
+					asset_out: asset_out.clone(),
 					amount_out,
-				}
-			);
+				});
+			}
 
-			Ok(amount_out)
+			Self::deposit_event(Event::SwapExecuted {
+				sender,
+				pair,
+				asset_in,
+				amount_in,
+				asset_out,
+				amount_out,
+				fee: Self::pool_fee(amount_in),
+				reserve_a: pool_info.reserve_a,
+				reserve_b: pool_info.reserve_b,
+			});
 		}
 
 		/// Calculate the amount of output asset that will be received for a given amount of input asset in a swap.
@@ -699,19 +1759,16 @@ use crate::*;
 				.checked_sub(&Self::pool_fee(amount_in))
                 .ok_or(Error::<T>::Overflow)?;
 
-			// Calculate the numerator.
-            let numerator =
-                amount_in_with_fee.checked_mul(&reserve_out).ok_or(Error::<T>::Overflow)?;
+			// Calculate the numerator, in 256 bits so that large reserves cannot overflow it.
+			let numerator = Self::to_u256(amount_in_with_fee)? * Self::to_u256(reserve_out)?;
 
 			// Calculate the denominator.
-            let denominator = reserve_in
-                .checked_add(&amount_in_with_fee)
-                .ok_or(Error::<T>::Overflow)?;
+			let denominator = Self::to_u256(reserve_in)? + Self::to_u256(amount_in_with_fee)?;
 
 			// Calculate the result using the formula: result = numerator / denominator.
-            let result = numerator.checked_div(&denominator).ok_or(Error::<T>::Overflow)?;
+			let result = numerator.checked_div(denominator).ok_or(Error::<T>::Overflow)?;
 
-            result.try_into().map_err(|_| Error::<T>::Overflow)
+			Self::from_u256(result)
 		}
 
 		/// Calculate the amount of input asset required to receive a specific amount of output asset in a swap.
@@ -725,27 +1782,25 @@ use crate::*;
 
 			ensure!(amount_out < reserve_out, Error::<T>::InsufficientAmount);
 
-			// Calculate the numerator.
-			let numerator = reserve_in
-				.checked_mul(&amount_out)
-				.ok_or(Error::<T>::Overflow)?;
+			let fee_percent = T::PoolFeePercent::get();
+			ensure!(fee_percent < 100, Error::<T>::Overflow);
 
-			// Calculate the denominator.
-			let denominator = reserve_out
-				.checked_sub(&Self::pool_fee(amount_out))
-				.ok_or(Error::<T>::Overflow)?;
+			// Calculate the numerator, in 512 bits so that large reserves cannot overflow it.
+			let numerator =
+				U512::from(Self::to_u256(reserve_in)? * Self::to_u256(amount_out)?) * U512::from(100u8);
+
+			// Calculate the denominator, the output reserve left net of the pool fee.
+			let denominator = Self::to_u256(reserve_out - amount_out)? * U256::from(100 - fee_percent);
 
 			// Calculate the result using the formula: result = (numerator / denominator) + 1.
-			let result = numerator
-				.checked_div(&denominator)
-				.ok_or(Error::<T>::Overflow)?
-				.checked_add(&One::one())
-				.ok_or(Error::<T>::Overflow)?;
+			let result = numerator.checked_div(U512::from(denominator)).ok_or(Error::<T>::Overflow)?;
+			let result = U256::try_from(result).map_err(|_| Error::<T>::Overflow)?;
 
-			result.try_into().map_err(|_| Error::<T>::Overflow)
+			Self::from_u256(result)?.checked_add(&One::one()).ok_or(Error::<T>::Overflow)
 		}
 
 		/// Calculate the estimated output amount for swapping a given `amount` of `asset_a` to `asset_b`.
+		/// This is the spot quote, ignoring fees and price impact; see `simulate_swap_exact_in`.
 		pub fn calc_price_exact_asset_for_asset(
 			asset_a: AssetIdOf<T>,
 			asset_b: AssetIdOf<T>,
@@ -765,6 +1820,7 @@ use crate::*;
 		}
 
 		/// Calculate the estimated input amount for swapping a given `amount` of `asset_b` for `asset_a`.
+		/// This is the spot quote, ignoring fees and price impact; see `simulate_swap_exact_out`.
 		pub fn calc_price_asset_for_exact_asset(
 			asset_a: AssetIdOf<T>,
 			asset_b: AssetIdOf<T>,
@@ -817,6 +1873,25 @@ use crate::*;
 		pub fn total_issuance(asset_id: AssetIdOf<T>) -> BalanceOf<T> {
 			T::Fungibles::total_issuance(asset_id)
 		}
+
+		/// Check if an LP token with the given identifier exists in the pool assets system.
+		pub fn lp_asset_exists(lp_asset: &AssetIdOf<T>) -> bool {
+			T::PoolAssets::asset_exists(lp_asset.clone())
+		}
+
+		/// Get the total issuance of an LP token in the pool assets system.
+		pub fn lp_total_issuance(lp_asset: AssetIdOf<T>) -> BalanceOf<T> {
+			T::PoolAssets::total_issuance(lp_asset)
+		}
+
+		/// Destroy an LP token with nothing left in circulation, freeing its identifier.
+		pub(crate) fn destroy_lp_asset(lp_asset: AssetIdOf<T>) -> DispatchResult {
+			T::PoolAssets::start_destroy(lp_asset.clone(), None)?;
+			// Without LP tokens left, only emptied accounts and approvals remain to be cleared.
+			T::PoolAssets::destroy_accounts(lp_asset.clone(), u32::MAX)?;
+			T::PoolAssets::destroy_approvals(lp_asset.clone(), u32::MAX)?;
+			T::PoolAssets::finish_destroy(lp_asset)
+		}
 	
 		/// Get the reducible balance of a specific `asset` owned by the given `owner`.
 		pub fn get_balance(owner: T::AccountId, asset: AssetIdOf<T>) -> BalanceOf<T> {
@@ -834,14 +1909,40 @@ use crate::*;
 			Self::mul_div(amount, reserve_b, reserve_a)
 		}
 
-		fn mul_div(a: BalanceOf<T>, b: BalanceOf<T>, c: BalanceOf<T>) -> Result<BalanceOf<T>, Error<T>> {
-			let result = a
-				.checked_mul(&b)
+		/// Calculate `a * b / c` with a 256-bit intermediate, so the product cannot overflow.
+		pub(crate) fn mul_div(a: BalanceOf<T>, b: BalanceOf<T>, c: BalanceOf<T>) -> Result<BalanceOf<T>, Error<T>> {
+			let result = Self::to_u256(a)?
+				.checked_mul(Self::to_u256(b)?)
 				.ok_or(Error::<T>::Overflow)?
-				.checked_div(&c)
+				.checked_div(Self::to_u256(c)?)
 				.ok_or(Error::<T>::Overflow)?;
 
-			result.try_into().map_err(|_| Error::<T>::Overflow)
+			Self::from_u256(result)
+		}
+
+		/// Widen a balance to `U256`, failing if it does not fit in a `u128`, so that the product
+		/// of two widened balances cannot overflow.
+		pub(crate) fn to_u256(amount: BalanceOf<T>) -> Result<U256, Error<T>> {
+			let amount: u128 = amount.try_into().map_err(|_| Error::<T>::Overflow)?;
+			Ok(U256::from(amount))
+		}
+
+		/// Narrow a `U256` back to a balance, failing if it does not fit.
+		pub(crate) fn from_u256(amount: U256) -> Result<BalanceOf<T>, Error<T>> {
+			let amount = u128::try_from(amount).map_err(|_| Error::<T>::Overflow)?;
+			amount.try_into().map_err(|_| Error::<T>::Overflow)
+		}
+
+		/// `numerator / denominator` as a fixed-point price, saturating at the largest one.
+		pub(crate) fn price(numerator: BalanceOf<T>, denominator: BalanceOf<T>) -> FixedU128 {
+			Self::to_u256(numerator)
+				.ok()
+				.zip(Self::to_u256(denominator).ok())
+				.and_then(|(numerator, denominator)| {
+					numerator.saturating_mul(U256::from(FixedU128::accuracy())).checked_div(denominator)
+				})
+				.and_then(|price| u128::try_from(price).ok())
+				.map_or(FixedU128::from_inner(u128::MAX), FixedU128::from_inner)
 		}
 
 		/// Calculate the amount of liquidity pool (LP) tokens to be minted when the total supply is zero.
@@ -851,15 +1952,82 @@ use crate::*;
 			amount_b: BalanceOf<T>
 		) -> Result<BalanceOf<T>, Error<T>> {
 			// Calculate the result using the formula: sqrt(amount_a * amount_b) - MinLiquidity.
-			let result = amount_a
-				.checked_mul(&amount_b)
-				.ok_or(Error::<T>::Overflow)?
-				.integer_sqrt()
-				.checked_sub(&T::MinLiquidity::get().into())
-				.ok_or(Error::<T>::InsufficientLiquidity)?;
-			
-			// Try to convert the result to the balance type and handle any potential errors.
-			result.try_into().map_err(|_| Error::<T>::Overflow)
+			// The square root of a product of two balances always fits back in a balance.
+			let root = Self::from_u256((Self::to_u256(amount_a)? * Self::to_u256(amount_b)?).integer_sqrt())?;
+			root.checked_sub(&T::MinLiquidity::get().into()).ok_or(Error::<T>::InsufficientLiquidity)
 		}
     }
 }
+
+impl<T: Config> Pallet<T> {
+	/// Reserves of the pool of `asset_in` and `asset_out`, as `(reserve_in, reserve_out)`.
+	fn oriented_reserves(
+		asset_in: AssetIdOf<T>,
+		asset_out: AssetIdOf<T>,
+	) -> Result<(BalanceOf<T>, BalanceOf<T>), DispatchError> {
+		let pair = AssetPair::<T>::new(asset_in.clone(), asset_out)?;
+		let pool_info = Pools::<T>::get(&pair).ok_or(Error::<T>::PoolNotFound)?;
+		let (reserve_a, reserve_b) = (pool_info.reserve_a, pool_info.reserve_b);
+		if asset_in == pair.asset_a {
+			Ok((reserve_a, reserve_b))
+		} else {
+			Ok((reserve_b, reserve_a))
+		}
+	}
+}
+
+impl<T: Config> Swap<T::AccountId, AssetIdOf<T>, BalanceOf<T>> for Pallet<T> {
+	fn quote_exact_in(
+		asset_in: AssetIdOf<T>,
+		asset_out: AssetIdOf<T>,
+		amount_in: BalanceOf<T>,
+	) -> Option<BalanceOf<T>> {
+		let (reserve_in, reserve_out) = Self::oriented_reserves(asset_in, asset_out).ok()?;
+		Self::get_amount_out(amount_in, reserve_in, reserve_out).ok()
+	}
+
+	fn quote_exact_out(
+		asset_in: AssetIdOf<T>,
+		asset_out: AssetIdOf<T>,
+		amount_out: BalanceOf<T>,
+	) -> Option<BalanceOf<T>> {
+		let (reserve_in, reserve_out) = Self::oriented_reserves(asset_in, asset_out).ok()?;
+		Self::get_amount_in(amount_out, reserve_in, reserve_out).ok()
+	}
+
+	fn swap_exact_in(
+		who: &T::AccountId,
+		asset_in: AssetIdOf<T>,
+		asset_out: AssetIdOf<T>,
+		amount_in: BalanceOf<T>,
+		amount_out_min: BalanceOf<T>,
+	) -> Result<BalanceOf<T>, DispatchError> {
+		frame_support::storage::with_storage_layer(|| {
+			Self::do_swap_exact_asset_for_asset(
+				who.clone(),
+				asset_in,
+				asset_out,
+				amount_in,
+				amount_out_min,
+			)
+		})
+	}
+
+	fn swap_exact_out(
+		who: &T::AccountId,
+		asset_in: AssetIdOf<T>,
+		asset_out: AssetIdOf<T>,
+		amount_out: BalanceOf<T>,
+		amount_in_max: BalanceOf<T>,
+	) -> Result<BalanceOf<T>, DispatchError> {
+		frame_support::storage::with_storage_layer(|| {
+			Self::do_swap_asset_for_exact_asset(
+				who.clone(),
+				asset_in,
+				asset_out,
+				amount_out,
+				amount_in_max,
+			)
+		})
+	}
+}
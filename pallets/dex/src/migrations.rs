@@ -0,0 +1,285 @@
+//! Storage migrations.
+//!
+//! Each `vN` module migrates the pallet's storage from version `N - 1` to `N`, and keeps the
+//! storage layout of version `N - 1` it migrates from. Migrations only run when the on-chain
+//! storage version is the one they expect, so they are safe to leave in a runtime's migrations.
+
+use crate::*;
+use frame_support::{pallet_prelude::*, traits::OnRuntimeUpgrade};
+#[cfg(feature = "try-runtime")]
+use frame_support::sp_std::vec::Vec;
+#[cfg(feature = "try-runtime")]
+use sp_runtime::TryRuntimeError;
+
+/// Storage layout of version 0.
+pub mod v0 {
+	use super::*;
+
+	/// Pool information before pools had a status.
+	#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebugNoBound)]
+	pub struct PoolInfo<T: Config> {
+		pub lp_asset: AssetIdOf<T>,
+		pub reserve_a: BalanceOf<T>,
+		pub reserve_b: BalanceOf<T>,
+	}
+
+	#[frame_support::storage_alias]
+	pub type Pools<T: Config> = StorageMap<Pallet<T>, Blake2_128Concat, AssetPair<T>, PoolInfo<T>>;
+}
+
+/// Version 1 adds `PoolInfo::status`.
+pub mod v1 {
+	use super::*;
+
+	/// Mark every existing pool verified. The allow-list starts out empty with this version, so
+	/// deriving statuses from it would flag every pool as unverified; pools created before it
+	/// existed keep trading as they did, and governance can still flag any of them afterwards.
+	pub struct MigrateToV1<T>(PhantomData<T>);
+
+	impl<T: Config> OnRuntimeUpgrade for MigrateToV1<T> {
+		fn on_runtime_upgrade() -> Weight {
+			let on_chain_version = Pallet::<T>::on_chain_storage_version();
+			if on_chain_version != 0 {
+				log::info!(
+					target: LOG_TARGET,
+					"skipping migration to v1, on-chain storage version is {:?}",
+					on_chain_version
+				);
+				return T::DbWeight::get().reads(1)
+			}
+
+			let mut translated = 0u64;
+			Pools::<T>::translate::<v0::PoolInfo<T>, _>(|_pair, old| {
+				translated += 1;
+
+				Some(PoolInfo {
+					lp_asset: old.lp_asset,
+					reserve_a: old.reserve_a,
+					reserve_b: old.reserve_b,
+					status: PoolStatus::Verified,
+				})
+			});
+
+			StorageVersion::new(1).put::<Pallet<T>>();
+			log::info!(target: LOG_TARGET, "migrated {} pools to v1", translated);
+
+			T::DbWeight::get().reads_writes(1 + translated, 1 + translated)
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn pre_upgrade() -> Result<Vec<u8>, TryRuntimeError> {
+			let pools = v0::Pools::<T>::iter().count() as u32;
+			Ok(pools.encode())
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn post_upgrade(state: Vec<u8>) -> Result<(), TryRuntimeError> {
+			let pools_before = u32::decode(&mut &state[..])
+				.map_err(|_| TryRuntimeError::Other("failed to decode the pool count"))?;
+
+			ensure!(
+				Pallet::<T>::on_chain_storage_version() == 1,
+				TryRuntimeError::Other("storage version not bumped to 1")
+			);
+			let mut pools_after = 0u32;
+			for pool_info in Pools::<T>::iter_values() {
+				ensure!(
+					pool_info.status == PoolStatus::Verified,
+					TryRuntimeError::Other("migrated pool not verified")
+				);
+				pools_after += 1;
+			}
+			ensure!(pools_after == pools_before, TryRuntimeError::Other("pools lost in the migration"));
+
+			Ok(())
+		}
+	}
+}
+
+/// Version 2 moves the LP tokens of existing pools from `Fungibles` to `PoolAssets`.
+pub mod v2 {
+	use super::*;
+	use frame_support::{
+		sp_std::vec::Vec,
+		storage::{storage_prefix, with_storage_layer, KeyPrefixIterator},
+		traits::{
+			fungibles::{self, Create, Destroy, Inspect, Mutate},
+			tokens::{Fortitude::Force, Precision::Exact},
+			PalletInfoAccess,
+		},
+		ReversibleStorageHasher, StorageHasher,
+	};
+	use sp_runtime::traits::Zero;
+
+	/// Recreate the LP token of every pool in `PoolAssets`, with the same id and balances, and
+	/// destroy it in `OldAssets`, the `pallet_assets` instance that held LP tokens until this
+	/// version. A pool whose LP token fails to move is logged and left as it was.
+	///
+	/// The whole move happens in the upgrade block, so it is only done for at most `MaxHolders` LP
+	/// token holders in total; with more, the migration is skipped and `pre_upgrade` fails.
+	pub struct MigrateToV2<T, OldAssets, MaxHolders>(PhantomData<(T, OldAssets, MaxHolders)>);
+
+	impl<T, OldAssets, MaxHolders> MigrateToV2<T, OldAssets, MaxHolders>
+	where
+		T: Config,
+		OldAssets: PalletInfoAccess
+			+ fungibles::Inspect<T::AccountId, AssetId = AssetIdOf<T>, Balance = BalanceOf<T>>
+			+ fungibles::Mutate<T::AccountId>
+			+ fungibles::Destroy<T::AccountId>,
+		MaxHolders: Get<u32>,
+	{
+		/// Up to `limit` accounts holding `asset` in `OldAssets`, read from the keys of its
+		/// `Account` map.
+		fn holders(asset: &AssetIdOf<T>, limit: usize) -> Vec<T::AccountId> {
+			let mut prefix = storage_prefix(OldAssets::name().as_bytes(), b"Account").to_vec();
+			prefix.extend(asset.using_encoded(Blake2_128Concat::hash));
+			KeyPrefixIterator::new(prefix.clone(), prefix, |raw_key| {
+				T::AccountId::decode(&mut Blake2_128Concat::reverse(raw_key))
+			})
+			.take(limit)
+			.collect()
+		}
+
+		/// The LP assets still in `OldAssets` with their holders, or `None` if they have more than
+		/// `MaxHolders` holders in total. Also returns the number of storage reads done.
+		fn lp_assets_to_move() -> (Option<Vec<(AssetIdOf<T>, Vec<T::AccountId>)>>, u64) {
+			let max_holders = MaxHolders::get() as usize;
+			let (mut lp_assets, mut holders, mut reads) = (Vec::new(), 0usize, 0u64);
+			for pool_info in Pools::<T>::iter_values() {
+				reads += 2;
+				if !OldAssets::asset_exists(pool_info.lp_asset.clone()) {
+					continue
+				}
+
+				// One holder past the bound is enough to tell it is exceeded.
+				let lp_holders =
+					Self::holders(&pool_info.lp_asset, max_holders.saturating_sub(holders) + 1);
+				reads += lp_holders.len() as u64;
+				holders += lp_holders.len();
+				if holders > max_holders {
+					return (None, reads)
+				}
+				lp_assets.push((pool_info.lp_asset, lp_holders));
+			}
+			(Some(lp_assets), reads)
+		}
+
+		/// Move `lp_asset` and the balances of `holders` over.
+		fn migrate_lp_asset(lp_asset: AssetIdOf<T>, holders: &[T::AccountId]) -> DispatchResult {
+			T::PoolAssets::create(
+				lp_asset.clone(),
+				Pallet::<T>::account_id(),
+				true,
+				T::MinBalance::get(),
+			)?;
+			for who in holders {
+				let balance = OldAssets::balance(lp_asset.clone(), who);
+				if balance.is_zero() {
+					continue
+				}
+				T::PoolAssets::mint_into(lp_asset.clone(), who, balance)?;
+				OldAssets::burn_from(lp_asset.clone(), who, balance, Exact, Force)?;
+			}
+
+			OldAssets::start_destroy(lp_asset.clone(), None)?;
+			OldAssets::destroy_accounts(lp_asset.clone(), u32::MAX)?;
+			OldAssets::destroy_approvals(lp_asset.clone(), u32::MAX)?;
+			OldAssets::finish_destroy(lp_asset)?;
+
+			Ok(())
+		}
+	}
+
+	impl<T, OldAssets, MaxHolders> OnRuntimeUpgrade for MigrateToV2<T, OldAssets, MaxHolders>
+	where
+		T: Config,
+		OldAssets: PalletInfoAccess
+			+ fungibles::Inspect<T::AccountId, AssetId = AssetIdOf<T>, Balance = BalanceOf<T>>
+			+ fungibles::Mutate<T::AccountId>
+			+ fungibles::Destroy<T::AccountId>,
+		MaxHolders: Get<u32>,
+	{
+		fn on_runtime_upgrade() -> Weight {
+			let on_chain_version = Pallet::<T>::on_chain_storage_version();
+			if on_chain_version != 1 {
+				log::info!(
+					target: LOG_TARGET,
+					"skipping migration to v2, on-chain storage version is {:?}",
+					on_chain_version
+				);
+				return T::DbWeight::get().reads(1)
+			}
+
+			let (lp_assets, lp_asset_reads) = Self::lp_assets_to_move();
+			let (mut reads, mut writes) = (1 + lp_asset_reads, 0u64);
+			let Some(lp_assets) = lp_assets else {
+				log::error!(
+					target: LOG_TARGET,
+					"skipping migration to v2, LP assets have more than {} holders",
+					MaxHolders::get()
+				);
+				return T::DbWeight::get().reads_writes(reads, writes)
+			};
+
+			let pools = lp_assets.len();
+			let mut migrated = 0u32;
+			for (lp_asset, holders) in lp_assets {
+				// Creating the asset in `PoolAssets` and destroying it in `OldAssets` read and write
+				// the asset, its approvals and metadata; each holder's balance is read, minted and
+				// burnt in both backends. Failed moves are rolled back, but did the same work.
+				reads += 5 + 5 * holders.len() as u64;
+				writes += 6 + 4 * holders.len() as u64;
+				match with_storage_layer(|| Self::migrate_lp_asset(lp_asset.clone(), &holders)) {
+					Ok(()) => migrated += 1,
+					Err(error) => log::error!(
+						target: LOG_TARGET,
+						"failed to move LP asset {:?} to the pool assets: {:?}",
+						lp_asset,
+						error
+					),
+				}
+			}
+
+			StorageVersion::new(2).put::<Pallet<T>>();
+			writes += 1;
+			log::info!(target: LOG_TARGET, "moved {} of {} LP assets to v2", migrated, pools);
+
+			T::DbWeight::get().reads_writes(reads, writes)
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn pre_upgrade() -> Result<Vec<u8>, TryRuntimeError> {
+			let (lp_assets, _) = Self::lp_assets_to_move();
+			let lp_assets = lp_assets
+				.ok_or(TryRuntimeError::Other("too many LP holders to move in one block"))?;
+			let issuance: Vec<(AssetIdOf<T>, BalanceOf<T>)> = lp_assets
+				.into_iter()
+				.map(|(lp_asset, _)| (lp_asset.clone(), OldAssets::total_issuance(lp_asset)))
+				.collect();
+			Ok(issuance.encode())
+		}
+
+		#[cfg(feature = "try-runtime")]
+		fn post_upgrade(state: Vec<u8>) -> Result<(), TryRuntimeError> {
+			let issuance = Vec::<(AssetIdOf<T>, BalanceOf<T>)>::decode(&mut &state[..])
+				.map_err(|_| TryRuntimeError::Other("failed to decode the LP asset issuance"))?;
+
+			ensure!(
+				Pallet::<T>::on_chain_storage_version() == 2,
+				TryRuntimeError::Other("storage version not bumped to 2")
+			);
+			for (lp_asset, total_issuance) in issuance {
+				ensure!(
+					!OldAssets::asset_exists(lp_asset.clone()),
+					TryRuntimeError::Other("LP asset left in the old assets")
+				);
+				ensure!(
+					T::PoolAssets::total_issuance(lp_asset) == total_issuance,
+					TryRuntimeError::Other("LP asset issuance changed in the migration")
+				);
+			}
+
+			Ok(())
+		}
+	}
+}
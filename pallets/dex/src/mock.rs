@@ -1,10 +1,10 @@
 use crate as pallet_dex;
-use frame_support::{ord_parameter_types, parameter_types, traits::{AsEnsureOriginWithArg, ConstU128, ConstU16, ConstU32, ConstU64}, PalletId};
+use frame_support::{ord_parameter_types, parameter_types, traits::{AsEnsureOriginWithArg, ConstU128, ConstU16, ConstU32, ConstU64, NeverEnsureOrigin}, weights::IdentityFee, PalletId};
 use frame_system::{EnsureRoot, EnsureSigned};
 use sp_core::{ConstU8, H256};
 use sp_runtime::{
 	traits::{AccountIdConversion, BlakeTwo256, IdentityLookup},
-	BuildStorage,
+	BuildStorage, Perbill, Permill,
 };
 
 type Block = frame_system::mocking::MockBlock<Test>;
@@ -17,6 +17,9 @@ frame_support::construct_runtime!(
 		System: frame_system,
 		Balances: pallet_balances,
 		Assets: pallet_assets,
+		PoolAssets: pallet_assets::<Instance1>,
+		TransactionPayment: pallet_transaction_payment,
+		AssetTxPayment: pallet_asset_tx_payment,
 		Dex: pallet_dex,
 	}
 );
@@ -25,7 +28,7 @@ impl frame_system::Config for Test {
 	type BaseCallFilter = frame_support::traits::Everything;
 	type BlockWeights = ();
 	type BlockLength = ();
-	type DbWeight = ();
+	type DbWeight = frame_support::weights::constants::RocksDbWeight;
 	type RuntimeOrigin = RuntimeOrigin;
 	type RuntimeCall = RuntimeCall;
 	type Nonce = u64;
@@ -57,7 +60,7 @@ impl pallet_balances::Config for Test {
 	type MaxLocks = ConstU32<10>;
 	type MaxReserves = ();
 	type ReserveIdentifier = [u8; 8];
-	type RuntimeHoldReason = ();
+	type RuntimeHoldReason = RuntimeHoldReason;
 	type FreezeIdentifier = ();
 	type MaxHolds = ConstU32<10>;
 	type MaxFreezes = ConstU32<10>;
@@ -86,8 +89,38 @@ impl pallet_assets::Config for Test {
 	type BenchmarkHelper = ();
 }
 
+// LP tokens are only created by the pallet, with no deposits.
+impl pallet_assets::Config<pallet_assets::Instance1> for Test {
+	type RuntimeEvent = RuntimeEvent;
+	type Balance = Balance;
+	type AssetId = u32;
+	type AssetIdParameter = codec::Compact<u32>;
+	type Currency = Balances;
+	type CreateOrigin = AsEnsureOriginWithArg<NeverEnsureOrigin<u64>>;
+	type ForceOrigin = EnsureRoot<Self::AccountId>;
+	type AssetDeposit = ConstU128<0>;
+	type AssetAccountDeposit = ConstU128<0>;
+	type MetadataDepositBase = ConstU128<0>;
+	type MetadataDepositPerByte = ConstU128<0>;
+	type ApprovalDeposit = ConstU128<0>;
+	type StringLimit = ConstU32<50>;
+	type Freezer = ();
+	type Extra = ();
+	type CallbackHandle = ();
+	type WeightInfo = ();
+	type RemoveItemsLimit = ConstU32<1000>;
+	#[cfg(feature = "runtime-benchmarks")]
+	type BenchmarkHelper = ();
+}
+
 parameter_types! {
 	pub const DexPalletId: PalletId = PalletId(*b"py/dodex");
+	pub const FlashLoanFee: Permill = Permill::from_percent(1);
+	pub const MaxLockBoost: Perbill = Perbill::from_percent(100);
+	pub const TreasuryAccount: u64 = 42;
+	pub static AllowUnlistedPools: bool = true;
+	pub static LegacyEvents: bool = true;
+	pub static MaxVirtualOrderPools: u32 = 16;
 }
 
 ord_parameter_types! {
@@ -98,13 +131,53 @@ ord_parameter_types! {
 
 impl pallet_dex::Config for Test {
 	type RuntimeEvent = RuntimeEvent;
+	type RuntimeCall = RuntimeCall;
+	type RuntimeHoldReason = RuntimeHoldReason;
 	type NativeBalance = Balances;
+	type PairOrdering = pallet_dex::OrdPairOrdering;
+	type LpAssetIds = pallet_dex::RequestedLpAssetId;
 	type Fungibles = Assets;
+	type PoolAssets = PoolAssets;
 	type PalletId = DexPalletId;
 	type PoolCreateFee = ConstU128<100>;
 	type MinLiquidity = ConstU128<10>;
 	type MinBalance = ConstU128<1>;
 	type PoolFeePercent = ConstU8<1>; 
+	type MaxSwapIntents = ConstU32<16>;
+	type MaxOrderBlocks = ConstU64<100>;
+	type FlashLoanFee = FlashLoanFee;
+	type NativeAssetId = ConstU32<0>;
+	type FarmingOrigin = EnsureRoot<u64>;
+	type MaxLockBlocks = ConstU64<100>;
+	type MaxLockBoost = MaxLockBoost;
+	type MaxLockExpiries = ConstU32<16>;
+	type TreasuryOrigin = EnsureRoot<u64>;
+	type TreasuryAccount = TreasuryAccount;
+	type PoolCreationOrigin = EnsureSigned<u64>;
+	type AssetListingOrigin = EnsureRoot<u64>;
+	type AllowUnlistedPools = AllowUnlistedPools;
+	type LegacyEvents = LegacyEvents;
+	type StatsWindow = ConstU64<10>;
+	type MaxStatsBuckets = ConstU32<3>;
+	type MaxHops = ConstU32<3>;
+	type MaxSplitRoutes = ConstU32<3>;
+	type MaxRoutes = ConstU32<8>;
+	type MaxVirtualOrderPools = MaxVirtualOrderPools;
+}
+
+impl pallet_transaction_payment::Config for Test {
+	type RuntimeEvent = RuntimeEvent;
+	type OnChargeTransaction = pallet_transaction_payment::CurrencyAdapter<Balances, ()>;
+	type OperationalFeeMultiplier = ConstU8<5>;
+	type WeightToFee = IdentityFee<Balance>;
+	type LengthToFee = IdentityFee<Balance>;
+	type FeeMultiplierUpdate = ();
+}
+
+impl pallet_asset_tx_payment::Config for Test {
+	type RuntimeEvent = RuntimeEvent;
+	type Fungibles = Assets;
+	type OnChargeAssetTransaction = pallet_dex::DexFeeAdapter<Test>;
 }
 
 // Build genesis storage according to the mock runtime.
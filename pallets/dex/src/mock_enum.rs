@@ -0,0 +1,200 @@
+//! A mock runtime whose asset ids are an enum without an `Ord` implementation, to check that the
+//! pallet only orders pairs through `Config::PairOrdering`.
+
+use crate as pallet_dex;
+use codec::{Decode, Encode, MaxEncodedLen};
+use frame_support::{parameter_types, traits::{AsEnsureOriginWithArg, ConstU128, ConstU16, ConstU32, ConstU64, NeverEnsureOrigin}, PalletId};
+use frame_system::{EnsureRoot, EnsureSigned};
+use scale_info::TypeInfo;
+use serde::{Deserialize, Serialize};
+use sp_core::{ConstU8, H256};
+use sp_runtime::{
+	traits::{BlakeTwo256, IdentityLookup},
+	BuildStorage, Perbill, Permill, RuntimeDebug,
+};
+
+type Block = frame_system::mocking::MockBlock<Test>;
+type Balance = u128;
+
+/// Asset ids telling the native asset, traded tokens and LP tokens apart.
+#[derive(
+	Clone, Copy, Encode, Decode, Eq, PartialEq, RuntimeDebug, MaxEncodedLen, TypeInfo, Serialize, Deserialize,
+)]
+pub enum AssetId {
+	Native,
+	Token(u32),
+	Lp(u32),
+}
+
+// Lets `pallet_assets` benchmarks create asset ids from numbers.
+impl From<u32> for AssetId {
+	fn from(id: u32) -> Self {
+		AssetId::Token(id)
+	}
+}
+
+/// Orders the native asset first, then tokens and LP tokens by their number.
+pub struct NativeFirst;
+
+impl pallet_dex::PairOrdering<AssetId> for NativeFirst {
+	fn precedes(asset_a: &AssetId, asset_b: &AssetId) -> bool {
+		let rank = |asset: &AssetId| match *asset {
+			AssetId::Native => (0, 0),
+			AssetId::Token(id) => (1, id),
+			AssetId::Lp(id) => (2, id),
+		};
+		rank(asset_a) < rank(asset_b)
+	}
+}
+
+frame_support::construct_runtime!(
+	pub enum Test
+	{
+		System: frame_system,
+		Balances: pallet_balances,
+		Assets: pallet_assets,
+		PoolAssets: pallet_assets::<Instance1>,
+		Dex: pallet_dex,
+	}
+);
+
+impl frame_system::Config for Test {
+	type BaseCallFilter = frame_support::traits::Everything;
+	type BlockWeights = ();
+	type BlockLength = ();
+	type DbWeight = ();
+	type RuntimeOrigin = RuntimeOrigin;
+	type RuntimeCall = RuntimeCall;
+	type Nonce = u64;
+	type Hash = H256;
+	type Hashing = BlakeTwo256;
+	type AccountId = u64;
+	type Lookup = IdentityLookup<Self::AccountId>;
+	type Block = Block;
+	type RuntimeEvent = RuntimeEvent;
+	type BlockHashCount = ConstU64<250>;
+	type Version = ();
+	type PalletInfo = PalletInfo;
+	type AccountData = pallet_balances::AccountData<Balance>;
+	type OnNewAccount = ();
+	type OnKilledAccount = ();
+	type SystemWeightInfo = ();
+	type SS58Prefix = ConstU16<42>;
+	type OnSetCode = ();
+	type MaxConsumers = ConstU32<16>;
+}
+
+impl pallet_balances::Config for Test {
+	type Balance = Balance;
+	type DustRemoval = ();
+	type RuntimeEvent = RuntimeEvent;
+	type ExistentialDeposit = ConstU128<1>;
+	type AccountStore = System;
+	type WeightInfo = ();
+	type MaxLocks = ConstU32<10>;
+	type MaxReserves = ();
+	type ReserveIdentifier = [u8; 8];
+	type RuntimeHoldReason = RuntimeHoldReason;
+	type FreezeIdentifier = ();
+	type MaxHolds = ConstU32<10>;
+	type MaxFreezes = ConstU32<10>;
+}
+
+impl pallet_assets::Config for Test {
+	type RuntimeEvent = RuntimeEvent;
+	type Balance = Balance;
+	type AssetId = AssetId;
+	type AssetIdParameter = AssetId;
+	type Currency = Balances;
+	type CreateOrigin = AsEnsureOriginWithArg<EnsureSigned<Self::AccountId>>;
+	type ForceOrigin = EnsureRoot<Self::AccountId>;
+	type AssetDeposit = ConstU128<100>;
+	type AssetAccountDeposit = ConstU128<1>;
+	type MetadataDepositBase = ConstU128<10>;
+	type MetadataDepositPerByte = ConstU128<1>;
+	type ApprovalDeposit = ConstU128<1>;
+	type StringLimit = ConstU32<50>;
+	type Freezer = ();
+	type Extra = ();
+	type CallbackHandle = ();
+	type WeightInfo = ();
+	type RemoveItemsLimit = ConstU32<1000>;
+	#[cfg(feature = "runtime-benchmarks")]
+	type BenchmarkHelper = ();
+}
+
+impl pallet_assets::Config<pallet_assets::Instance1> for Test {
+	type RuntimeEvent = RuntimeEvent;
+	type Balance = Balance;
+	type AssetId = AssetId;
+	type AssetIdParameter = AssetId;
+	type Currency = Balances;
+	type CreateOrigin = AsEnsureOriginWithArg<NeverEnsureOrigin<u64>>;
+	type ForceOrigin = EnsureRoot<Self::AccountId>;
+	type AssetDeposit = ConstU128<0>;
+	type AssetAccountDeposit = ConstU128<0>;
+	type MetadataDepositBase = ConstU128<0>;
+	type MetadataDepositPerByte = ConstU128<0>;
+	type ApprovalDeposit = ConstU128<0>;
+	type StringLimit = ConstU32<50>;
+	type Freezer = ();
+	type Extra = ();
+	type CallbackHandle = ();
+	type WeightInfo = ();
+	type RemoveItemsLimit = ConstU32<1000>;
+	#[cfg(feature = "runtime-benchmarks")]
+	type BenchmarkHelper = ();
+}
+
+parameter_types! {
+	pub const DexPalletId: PalletId = PalletId(*b"py/dodex");
+	pub const FlashLoanFee: Permill = Permill::from_percent(1);
+	pub const MaxLockBoost: Perbill = Perbill::from_percent(100);
+	pub const TreasuryAccount: u64 = 42;
+	pub const NativeAssetId: AssetId = AssetId::Native;
+}
+
+impl pallet_dex::Config for Test {
+	type RuntimeEvent = RuntimeEvent;
+	type RuntimeCall = RuntimeCall;
+	type RuntimeHoldReason = RuntimeHoldReason;
+	type NativeBalance = Balances;
+	type PairOrdering = NativeFirst;
+	type LpAssetIds = pallet_dex::RequestedLpAssetId;
+	type Fungibles = Assets;
+	type PoolAssets = PoolAssets;
+	type PalletId = DexPalletId;
+	type PoolCreateFee = ConstU128<100>;
+	type MinLiquidity = ConstU128<10>;
+	type MinBalance = ConstU128<1>;
+	type PoolFeePercent = ConstU8<1>;
+	type MaxSwapIntents = ConstU32<16>;
+	type MaxOrderBlocks = ConstU64<100>;
+	type FlashLoanFee = FlashLoanFee;
+	type NativeAssetId = NativeAssetId;
+	type FarmingOrigin = EnsureRoot<u64>;
+	type MaxLockBlocks = ConstU64<100>;
+	type MaxLockBoost = MaxLockBoost;
+	type MaxLockExpiries = ConstU32<16>;
+	type TreasuryOrigin = EnsureRoot<u64>;
+	type TreasuryAccount = TreasuryAccount;
+	type PoolCreationOrigin = EnsureSigned<u64>;
+	type AssetListingOrigin = EnsureRoot<u64>;
+	type AllowUnlistedPools = frame_support::traits::ConstBool<true>;
+	type LegacyEvents = frame_support::traits::ConstBool<false>;
+	type StatsWindow = ConstU64<10>;
+	type MaxStatsBuckets = ConstU32<3>;
+	type MaxHops = ConstU32<3>;
+	type MaxSplitRoutes = ConstU32<3>;
+	type MaxRoutes = ConstU32<8>;
+	type MaxVirtualOrderPools = ConstU32<16>;
+}
+
+// Build genesis storage according to the mock runtime.
+pub fn new_test_ext() -> sp_io::TestExternalities {
+	let t = frame_system::GenesisConfig::<Test>::default().build_storage().unwrap();
+
+	let mut ext = sp_io::TestExternalities::new(t);
+	ext.execute_with(|| System::set_block_number(1));
+	ext
+}
@@ -0,0 +1,182 @@
+//! Wrapped native token and transaction fee payment in any asset.
+//!
+//! Pools only hold assets of `Config::Fungibles`, so the native token is traded as the
+//! `Config::NativeAssetId` asset, minted 1:1 against native tokens held by the pallet account.
+//!
+//! [`DexFeeAdapter`] lets `pallet_asset_tx_payment` charge fees in any asset with a pool against the
+//! wrapped native token. The fee is withdrawn up front in the chosen asset at the pool's price.
+//! Once the actual fee is known, only the part needed for it is sold into the pool, and the
+//! wrapped native tokens bought are burnt together with their native backing, as native fees are
+//! burnt. The rest of the asset is refunded. Fees paid in the wrapped native token itself are
+//! burnt with their backing straight away.
+
+use crate::*;
+use frame_support::{
+	pallet_prelude::*,
+	storage::with_storage_layer,
+	traits::{
+		fungible::Mutate as FMutate,
+		fungibles::{Balanced, Credit, Mutate},
+		tokens::{
+			Fortitude::{Force, Polite},
+			Precision::Exact,
+			Preservation::{Expendable, Preserve},
+		},
+	},
+};
+use pallet_asset_tx_payment::OnChargeAssetTransaction;
+use sp_runtime::{
+	traits::{CheckedAdd, CheckedSub, DispatchInfoOf, PostDispatchInfoOf, Zero},
+	transaction_validity::{InvalidTransaction, TransactionValidityError},
+};
+
+impl<T: Config> Pallet<T> {
+	/// Lock `amount` of native tokens from `who` in the pallet account and mint them as much of the
+	/// wrapped native asset.
+	pub fn do_wrap_native(who: T::AccountId, amount: BalanceOf<T>) -> DispatchResult {
+		ensure!(!amount.is_zero(), Error::<T>::InvalidAmount);
+
+		<T::NativeBalance as FMutate<_>>::transfer(&who, &Self::account_id(), amount, Preserve)?;
+		T::Fungibles::mint_into(T::NativeAssetId::get(), &who, amount)?;
+
+		Self::deposit_event(Event::NativeWrapped { who, amount });
+
+		Ok(())
+	}
+
+	/// Burn `amount` of the wrapped native asset from `who` and release as many native tokens.
+	pub fn do_unwrap_native(who: T::AccountId, amount: BalanceOf<T>) -> DispatchResult {
+		ensure!(!amount.is_zero(), Error::<T>::InvalidAmount);
+
+		T::Fungibles::burn_from(T::NativeAssetId::get(), &who, amount, Exact, Polite)?;
+		<T::NativeBalance as FMutate<_>>::transfer(&Self::account_id(), &who, amount, Expendable)?;
+
+		Self::deposit_event(Event::NativeUnwrapped { who, amount });
+
+		Ok(())
+	}
+
+	/// Amount of `asset` worth `fee` native tokens at the pool's price.
+	pub fn fee_in_asset(asset: AssetIdOf<T>, fee: BalanceOf<T>) -> Option<BalanceOf<T>> {
+		let native_asset = T::NativeAssetId::get();
+		if asset == native_asset {
+			return Some(fee)
+		}
+
+		<Self as Swap<_, _, _>>::quote_exact_out(asset, native_asset, fee)
+	}
+
+	/// Sell `amount` of `asset`, already held by the pallet account, into its pool against the
+	/// wrapped native token, and burn the wrapped native tokens bought along with their backing.
+	fn sell_fee_for_native(
+		who: T::AccountId,
+		asset: AssetIdOf<T>,
+		amount: BalanceOf<T>,
+	) -> Result<BalanceOf<T>, DispatchError> {
+		let native_asset = T::NativeAssetId::get();
+		let pair = AssetPair::<T>::new(asset.clone(), native_asset.clone())?;
+		Self::execute_virtual_orders(&pair)?;
+		let mut pool_info = Pools::<T>::get(&pair).ok_or(Error::<T>::PoolNotFound)?;
+
+		let native_amount = if asset == pair.asset_a {
+			let amount_out = Self::get_amount_out(amount, pool_info.reserve_a, pool_info.reserve_b)?;
+			pool_info.reserve_a = pool_info.reserve_a.checked_add(&amount).ok_or(Error::<T>::Overflow)?;
+			pool_info.reserve_b = pool_info.reserve_b.checked_sub(&amount_out).ok_or(Error::<T>::Overflow)?;
+			amount_out
+		} else {
+			let amount_out = Self::get_amount_out(amount, pool_info.reserve_b, pool_info.reserve_a)?;
+			pool_info.reserve_a = pool_info.reserve_a.checked_sub(&amount_out).ok_or(Error::<T>::Overflow)?;
+			pool_info.reserve_b = pool_info.reserve_b.checked_add(&amount).ok_or(Error::<T>::Overflow)?;
+			amount_out
+		};
+		Self::update_reserves(&pair, pool_info.clone());
+		Self::record_trade(&pair, asset.clone(), amount, native_amount, Self::pool_fee(amount));
+
+		T::Fungibles::burn_from(native_asset.clone(), &Self::account_id(), native_amount, Exact, Force)?;
+		<T::NativeBalance as FMutate<_>>::burn_from(&Self::account_id(), native_amount, Exact, Force)?;
+
+		Self::deposit_swap_events(who, pair, asset, amount, native_asset, native_amount, &pool_info);
+
+		Ok(native_amount)
+	}
+}
+
+/// Pays transaction fees through `pallet_asset_tx_payment` in any asset that has a pool against the
+/// wrapped native token.
+pub struct DexFeeAdapter<T>(PhantomData<T>);
+
+impl<T> OnChargeAssetTransaction<T> for DexFeeAdapter<T>
+where
+	T: Config + pallet_asset_tx_payment::Config<Fungibles = <T as Config>::Fungibles>,
+	<T as Config>::Fungibles: Balanced<T::AccountId>,
+{
+	type Balance = BalanceOf<T>;
+	type AssetId = AssetIdOf<T>;
+	type LiquidityInfo = Credit<T::AccountId, <T as Config>::Fungibles>;
+
+	/// Withdraw the fee from `who` in `asset_id`, at the pool's current price.
+	fn withdraw_fee(
+		who: &T::AccountId,
+		_call: &<T as frame_system::Config>::RuntimeCall,
+		_dispatch_info: &DispatchInfoOf<<T as frame_system::Config>::RuntimeCall>,
+		asset_id: Self::AssetId,
+		fee: Self::Balance,
+		_tip: Self::Balance,
+	) -> Result<Self::LiquidityInfo, TransactionValidityError> {
+		let amount =
+			Pallet::<T>::fee_in_asset(asset_id.clone(), fee).ok_or(InvalidTransaction::Payment)?;
+
+		<T as Config>::Fungibles::withdraw(asset_id, who, amount, Exact, Preserve, Polite)
+			.map_err(|_| TransactionValidityError::from(InvalidTransaction::Payment))
+	}
+
+	/// Convert the part of the withdrawn asset needed for `corrected_fee` into native and burn it,
+	/// refunding the rest to `who`.
+	///
+	/// The call has already been dispatched, so this never fails: if the pool cannot take the fee,
+	/// the asset withdrawn for it is burnt instead.
+	fn correct_and_deposit_fee(
+		who: &T::AccountId,
+		_dispatch_info: &DispatchInfoOf<<T as frame_system::Config>::RuntimeCall>,
+		_post_info: &PostDispatchInfoOf<<T as frame_system::Config>::RuntimeCall>,
+		corrected_fee: Self::Balance,
+		_tip: Self::Balance,
+		paid: Self::LiquidityInfo,
+	) -> Result<(), TransactionValidityError> {
+		let asset_id = paid.asset();
+
+		// The call may have moved the pool, so never charge more than was withdrawn.
+		let used = Pallet::<T>::fee_in_asset(asset_id.clone(), corrected_fee)
+			.unwrap_or_else(|| paid.peek())
+			.min(paid.peek());
+		let (used, refund) = paid.split(used);
+
+		let _ = <T as Config>::Fungibles::resolve(who, refund);
+
+		let amount = used.peek();
+		if amount.is_zero() {
+			return Ok(())
+		}
+
+		let account = Pallet::<T>::account_id();
+		if asset_id == T::NativeAssetId::get() {
+			// Dropping the credit burns the wrapped tokens, so burn their backing with them.
+			drop(used);
+			let _ = <T::NativeBalance as FMutate<_>>::burn_from(&account, amount, Exact, Force);
+			return Ok(())
+		}
+
+		// Dropping the credit if it cannot be resolved burns it, as a failed sale below does.
+		if <T as Config>::Fungibles::resolve(&account, used).is_err() {
+			return Ok(())
+		}
+		let sold = with_storage_layer(|| {
+			Pallet::<T>::sell_fee_for_native(who.clone(), asset_id.clone(), amount)
+		});
+		if sold.is_err() {
+			let _ = <T as Config>::Fungibles::burn_from(asset_id, &account, amount, Exact, Force);
+		}
+
+		Ok(())
+	}
+}
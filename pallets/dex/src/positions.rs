@@ -0,0 +1,74 @@
+//! Valuation of liquidity positions.
+//!
+//! A position is valued at what withdrawing all of its LP tokens from the pool would pay out now,
+//! with the same math and removal fee as `remove_liquidity`. LP tokens staked in the pool's farm
+//! count towards the position, though they have to be unstaked before they can be withdrawn.
+
+use crate::*;
+use frame_support::{pallet_prelude::*, sp_std::vec::Vec, traits::fungibles::Inspect};
+use sp_runtime::{traits::Zero, Perbill, SaturatedConversion, Saturating};
+
+/// What an account's LP tokens of a pool are worth.
+#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo)]
+pub struct LpPosition<AssetId, Balance> {
+	/// First asset of the pool's pair.
+	pub asset_a: AssetId,
+	/// Second asset of the pool's pair.
+	pub asset_b: AssetId,
+	/// LP token of the pool.
+	pub lp_asset: AssetId,
+	/// LP tokens held by the account.
+	pub lp_balance: Balance,
+	/// LP tokens staked by the account in the pool's farm.
+	pub staked: Balance,
+	/// Share of the pool's LP tokens held or staked by the account.
+	pub share: Perbill,
+	/// Amount of `asset_a` paid out for withdrawing all of the LP tokens.
+	pub amount_a: Balance,
+	/// Amount of `asset_b` paid out for withdrawing all of the LP tokens.
+	pub amount_b: Balance,
+	/// LP tokens kept as removal fee on withdrawal.
+	pub fee: Balance,
+	/// Value of the removal fee in `asset_a`.
+	pub fee_a: Balance,
+	/// Value of the removal fee in `asset_b`.
+	pub fee_b: Balance,
+}
+
+impl<T: Config> Pallet<T> {
+	/// Positions of `who` in every pool it holds or stakes LP tokens of, at the current reserves.
+	pub fn lp_positions(who: &T::AccountId) -> Vec<LpPosition<AssetIdOf<T>, BalanceOf<T>>> {
+		Pools::<T>::iter()
+			.filter_map(|(pair, pool_info)| {
+				let lp_balance = T::PoolAssets::balance(pool_info.lp_asset.clone(), who);
+				let staked = FarmStakes::<T>::get(&pair, who).map_or(Zero::zero(), |stake| stake.amount);
+				let lp_amount = lp_balance.saturating_add(staked);
+				if lp_amount.is_zero() {
+					return None
+				}
+
+				let total_supply = Self::lp_total_issuance(pool_info.lp_asset.clone());
+				let (fee, amount_a, amount_b) = Self::withdrawal_amounts(&pool_info, lp_amount).ok()?;
+				let fee_a = Self::mul_div(fee, pool_info.reserve_a, total_supply).ok()?;
+				let fee_b = Self::mul_div(fee, pool_info.reserve_b, total_supply).ok()?;
+
+				Some(LpPosition {
+					asset_a: pair.asset_a,
+					asset_b: pair.asset_b,
+					lp_asset: pool_info.lp_asset,
+					lp_balance,
+					staked,
+					share: Perbill::from_rational(
+						lp_amount.saturated_into::<u128>(),
+						total_supply.saturated_into::<u128>(),
+					),
+					amount_a,
+					amount_b,
+					fee,
+					fee_a,
+					fee_b,
+				})
+			})
+			.collect()
+	}
+}
@@ -0,0 +1,159 @@
+//! Property tests of the AMM math.
+//!
+//! Random sequences of liquidity and swap operations are run against the mock runtime, checking
+//! after each one that swaps never decrease `k`, that the value of an LP token never decreases for
+//! passive holders, that failed calls leave no trace and that the pool accounting stays sound.
+
+use crate::mock::*;
+use frame_support::{
+	assert_ok,
+	traits::fungibles::{Inspect, Mutate},
+};
+use proptest::prelude::*;
+use sp_core::U256;
+use sp_runtime::DispatchResult;
+
+const PROVIDER: u64 = 1;
+const TRADER: u64 = 2;
+const ASSET_A: u32 = 1;
+const ASSET_B: u32 = 2;
+const LP_ASSET: u32 = 1234;
+
+/// An operation on the pool of `ASSET_A` and `ASSET_B`.
+#[derive(Clone, Debug)]
+enum Op {
+	AddLiquidity { amount_a: u128, amount_b: u128 },
+	/// Remove the given per mille of the provider's LP tokens.
+	RemoveLiquidity { per_mille: u128 },
+	SwapExactIn { a_to_b: bool, amount_in: u128 },
+	SwapExactOut { a_to_b: bool, amount_out: u128 },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+	prop_oneof![
+		(1..1_000_000u128, 1..1_000_000u128)
+			.prop_map(|(amount_a, amount_b)| Op::AddLiquidity { amount_a, amount_b }),
+		(1..=1_000u128).prop_map(|per_mille| Op::RemoveLiquidity { per_mille }),
+		(any::<bool>(), 1..1_000_000u128)
+			.prop_map(|(a_to_b, amount_in)| Op::SwapExactIn { a_to_b, amount_in }),
+		(any::<bool>(), 1..1_000_000u128)
+			.prop_map(|(a_to_b, amount_out)| Op::SwapExactOut { a_to_b, amount_out }),
+	]
+}
+
+/// Creates the pool and seeds it with `reserve_a` and `reserve_b`, leaving both accounts with
+/// plenty of both assets.
+fn setup(reserve_a: u128, reserve_b: u128) {
+	for asset in [ASSET_A, ASSET_B] {
+		assert_ok!(Assets::force_create(RuntimeOrigin::root(), asset.into(), PROVIDER, false, 1));
+	}
+	for who in [PROVIDER, TRADER] {
+		assert_ok!(Balances::force_set_balance(RuntimeOrigin::root(), who, 1_000_000u128));
+		for asset in [ASSET_A, ASSET_B] {
+			assert_ok!(Assets::mint_into(asset, &who, 1u128 << 100));
+		}
+	}
+
+	assert_ok!(Dex::create_pool(RuntimeOrigin::signed(PROVIDER), ASSET_A, ASSET_B, LP_ASSET));
+	assert_ok!(Dex::add_liquidity(
+		RuntimeOrigin::signed(PROVIDER),
+		ASSET_A,
+		ASSET_B,
+		reserve_a,
+		reserve_b,
+		1,
+		1,
+		PROVIDER,
+	));
+}
+
+fn apply(op: &Op) -> DispatchResult {
+	match *op {
+		Op::AddLiquidity { amount_a, amount_b } => Dex::add_liquidity(
+			RuntimeOrigin::signed(PROVIDER),
+			ASSET_A,
+			ASSET_B,
+			amount_a,
+			amount_b,
+			1,
+			1,
+			PROVIDER,
+		),
+		Op::RemoveLiquidity { per_mille } => {
+			let lp_tokens = PoolAssets::balance(LP_ASSET, PROVIDER) * per_mille / 1_000;
+			Dex::remove_liquidity(RuntimeOrigin::signed(PROVIDER), ASSET_A, ASSET_B, lp_tokens, 1, 1)
+		},
+		Op::SwapExactIn { a_to_b, amount_in } => {
+			let (asset_in, asset_out) = if a_to_b { (ASSET_A, ASSET_B) } else { (ASSET_B, ASSET_A) };
+			Dex::swap_exact_asset_for_asset(RuntimeOrigin::signed(TRADER), asset_in, asset_out, amount_in, 1)
+		},
+		Op::SwapExactOut { a_to_b, amount_out } => {
+			let (asset_in, asset_out) = if a_to_b { (ASSET_A, ASSET_B) } else { (ASSET_B, ASSET_A) };
+			Dex::swap_asset_for_exact_asset(
+				RuntimeOrigin::signed(TRADER),
+				asset_in,
+				asset_out,
+				amount_out,
+				u128::MAX,
+			)
+		},
+	}
+}
+
+/// Reserves of the pool and the supply of its LP token.
+fn pool_state() -> (u128, u128, u128) {
+	let (reserve_a, reserve_b) = Dex::get_reserves(ASSET_A, ASSET_B).unwrap();
+	(reserve_a, reserve_b, PoolAssets::total_issuance(LP_ASSET))
+}
+
+proptest! {
+	#![proptest_config(ProptestConfig::with_cases(64))]
+
+	#[test]
+	fn pool_operations_keep_invariants(
+		reserve_a in 1_000..1_000_000_000u128,
+		reserve_b in 1_000..1_000_000_000u128,
+		ops in prop::collection::vec(op(), 1..32),
+	) {
+		new_test_ext().execute_with(|| -> Result<(), TestCaseError> {
+			setup(reserve_a, reserve_b);
+
+			for op in ops {
+				let (old_a, old_b, old_supply) = pool_state();
+				let result = apply(&op);
+				let (new_a, new_b, new_supply) = pool_state();
+
+				if result.is_err() {
+					prop_assert_eq!(
+						(new_a, new_b, new_supply),
+						(old_a, old_b, old_supply),
+						"{:?} failed but moved the pool",
+						op
+					);
+					continue
+				}
+
+				let old_k = U256::from(old_a) * U256::from(old_b);
+				let new_k = U256::from(new_a) * U256::from(new_b);
+
+				if matches!(op, Op::SwapExactIn { .. } | Op::SwapExactOut { .. }) {
+					prop_assert!(new_k >= old_k, "{:?} decreased k", op);
+				}
+
+				// sqrt(k) / supply is the value of an LP token, compared squared to avoid the root.
+				if new_supply != 0 {
+					let old_supply = U256::from(old_supply);
+					let new_supply = U256::from(new_supply);
+					prop_assert!(
+						new_k * old_supply * old_supply >= old_k * new_supply * new_supply,
+						"{:?} decreased the value of LP tokens", op
+					);
+				}
+
+				prop_assert_eq!(Dex::do_try_state(), Ok(()));
+			}
+
+			Ok(())
+		})?;
+	}
+}
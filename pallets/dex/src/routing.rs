@@ -0,0 +1,440 @@
+//! Routing of swaps through several pools.
+//!
+//! A path is a list of assets, swapped through the pool of each consecutive pair of them. The best
+//! path between two assets is searched among the paths of at most `MaxHops` pools, each visiting an
+//! asset at most once, so no pool is traded twice along a path.
+//!
+//! Every pool is held by the pallet account, so a swap along a path only moves the first and last
+//! assets in and out of the sender's account; the intermediate ones never leave the pallet.
+//!
+//! A large trade can also be split across several paths between the same two assets. The paths are
+//! swapped along one after the other, each at the reserves left by the previous ones, and the trade
+//! only goes through if they receive enough in total. `split_greedily` searches such a split
+//! against any quote of it, so that it can run off-chain against the runtime API.
+
+use crate::*;
+use frame_support::{
+	pallet_prelude::*,
+	sp_std::{cmp::Reverse, vec::Vec},
+	storage::with_storage_layer,
+	traits::{fungibles::Mutate, tokens::Preservation::Preserve},
+};
+use sp_runtime::traits::{AtLeast32BitUnsigned, CheckedAdd, CheckedSub, Zero};
+
+/// Most assets a path may list: one more than `MaxHops`.
+pub struct MaxPathLen<T>(PhantomData<T>);
+
+impl<T: Config> Get<u32> for MaxPathLen<T> {
+	fn get() -> u32 {
+		T::MaxHops::get().saturating_add(1)
+	}
+}
+
+/// Path of at most `MaxHops` pools taken by the swap extrinsics.
+pub type PathOf<T> = BoundedVec<AssetIdOf<T>, MaxPathLen<T>>;
+
+/// At most `MaxSplitRoutes` paths, each with the amount sold along it.
+pub type SplitRoutesOf<T> = BoundedVec<(PathOf<T>, BalanceOf<T>), <T as Config>::MaxSplitRoutes>;
+
+/// A path through pools and the amounts a swap along it is expected to move.
+#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo)]
+pub struct Route<AssetId, Balance> {
+	/// Assets of the path, from the one sold to the one bought.
+	pub path: Vec<AssetId>,
+	/// Amount of each asset of `path` going into the next pool, or out of the last one.
+	pub amounts: Vec<Balance>,
+}
+
+/// Hand out `amount_in` across `paths` paths in `parts` equal slices, each to the path receiving
+/// the most for it on top of the slices already handed out, using at most `max_routes` of them.
+///
+/// `quote` gives the total received for an amount handed out to each path, or `None` if that split
+/// cannot be executed. Returns the amount handed out to each path and the total it receives, or
+/// `None` if there are no parts or a slice fits no path.
+pub fn split_greedily<Balance, E>(
+	paths: usize,
+	amount_in: Balance,
+	parts: u32,
+	max_routes: u32,
+	mut quote: impl FnMut(&[Balance]) -> Result<Option<Balance>, E>,
+) -> Result<Option<(Vec<Balance>, Balance)>, E>
+where
+	Balance: AtLeast32BitUnsigned + Copy,
+{
+	if parts == 0 {
+		return Ok(None)
+	}
+
+	let mut allocation: Vec<Balance> = (0..paths).map(|_| Zero::zero()).collect();
+	let mut amount_out = Balance::zero();
+	let slice = amount_in / parts.into();
+	for part in 0..parts {
+		// The last slice also takes what the others left over by rounding down.
+		let amount = if part + 1 == parts { amount_in - slice * (parts - 1).into() } else { slice };
+		if amount.is_zero() {
+			continue
+		}
+
+		let used = allocation.iter().filter(|amount| !amount.is_zero()).count();
+		let mut best: Option<(usize, Balance)> = None;
+		for (index, allocated) in allocation.iter().enumerate() {
+			if allocated.is_zero() && used >= max_routes as usize {
+				continue
+			}
+
+			let mut trial = allocation.clone();
+			trial[index] += amount;
+			let Some(total) = quote(&trial)? else { continue };
+			if best.map_or(true, |(_, best)| total > best) {
+				best = Some((index, total));
+			}
+		}
+
+		let Some((index, total)) = best else { return Ok(None) };
+		allocation[index] += amount;
+		amount_out = total;
+	}
+
+	Ok(Some((allocation, amount_out)))
+}
+
+impl<T: Config> Pallet<T> {
+	/// Paths of at most `MaxHops` pools from `asset_in` to `asset_out`, skipping locked pools. Only
+	/// the first `MaxRoutes` paths found are listed.
+	pub fn routes(asset_in: AssetIdOf<T>, asset_out: AssetIdOf<T>) -> Vec<Vec<AssetIdOf<T>>> {
+		let mut routes = Vec::new();
+		let max_routes = T::MaxRoutes::get() as usize;
+		if asset_in == asset_out || T::MaxHops::get() == 0 || max_routes == 0 {
+			return routes
+		}
+
+		let pairs: Vec<_> = Pools::<T>::iter_keys()
+			.filter(|pair| Self::ensure_pool_unlocked(pair).is_ok())
+			.map(|pair| (pair.asset_a, pair.asset_b))
+			.collect();
+
+		let mut path = Vec::from([asset_in]);
+		// Index in `pairs` of the next pool to try from each asset of `path`.
+		let mut next = Vec::from([0usize]);
+
+		while let Some(cursor) = next.last_mut() {
+			let current = &path[path.len() - 1];
+			let found = pairs.iter().enumerate().skip(*cursor).find_map(|(index, (a, b))| {
+				let neighbour = if a == current {
+					b
+				} else if b == current {
+					a
+				} else {
+					return None
+				};
+				(!path.contains(neighbour)).then(|| (index, neighbour.clone()))
+			});
+
+			let Some((index, neighbour)) = found else {
+				path.pop();
+				next.pop();
+				continue
+			};
+			*cursor = index + 1;
+
+			if neighbour == asset_out {
+				let mut route = path.clone();
+				route.push(neighbour);
+				routes.push(route);
+				if routes.len() >= max_routes {
+					break
+				}
+			} else if (path.len() as u32) < T::MaxHops::get() {
+				path.push(neighbour);
+				next.push(0);
+			}
+		}
+
+		routes
+	}
+
+	/// Path from `asset_in` to `asset_out` receiving the most for exactly `amount_in`, at the
+	/// current reserves. Among equally good paths, the one through the fewest pools wins.
+	pub fn best_route_exact_in(
+		asset_in: AssetIdOf<T>,
+		asset_out: AssetIdOf<T>,
+		amount_in: BalanceOf<T>,
+	) -> Option<Route<AssetIdOf<T>, BalanceOf<T>>> {
+		if amount_in.is_zero() {
+			return None
+		}
+
+		Self::routes(asset_in, asset_out)
+			.into_iter()
+			.filter_map(|path| {
+				let amounts = Self::quote_path_exact_in(&path, amount_in).ok()?;
+				(!amounts[amounts.len() - 1].is_zero()).then_some(Route { path, amounts })
+			})
+			.max_by_key(|route| (route.amounts[route.amounts.len() - 1], Reverse(route.path.len())))
+	}
+
+	/// Path from `asset_in` to `asset_out` paying the least for exactly `amount_out`, at the
+	/// current reserves. Among equally good paths, the one through the fewest pools wins.
+	pub fn best_route_exact_out(
+		asset_in: AssetIdOf<T>,
+		asset_out: AssetIdOf<T>,
+		amount_out: BalanceOf<T>,
+	) -> Option<Route<AssetIdOf<T>, BalanceOf<T>>> {
+		if amount_out.is_zero() {
+			return None
+		}
+
+		Self::routes(asset_in, asset_out)
+			.into_iter()
+			.filter_map(|path| {
+				let amounts = Self::quote_path_exact_out(&path, amount_out).ok()?;
+				Some(Route { path, amounts })
+			})
+			.min_by_key(|route| (route.amounts[0], route.path.len()))
+	}
+
+	/// Weight of swapping along `path`: the transfers in and out of the pallet account, and for
+	/// each pool its reserves, lock, virtual orders and statistics.
+	pub fn path_weight(path: &[AssetIdOf<T>]) -> Weight {
+		let hops = path.len().saturating_sub(1) as u64;
+		T::DbWeight::get().reads_writes(
+			4u64.saturating_add(hops.saturating_mul(5)),
+			4u64.saturating_add(hops.saturating_mul(3)),
+		)
+	}
+
+	/// Weight of swapping along each of `routes`.
+	pub fn split_weight(routes: &[(PathOf<T>, BalanceOf<T>)]) -> Weight {
+		routes
+			.iter()
+			.fold(Weight::zero(), |weight, (path, _)| weight.saturating_add(Self::path_weight(path)))
+	}
+
+	/// Check that `path` goes through at least one and at most `MaxHops` pools without visiting an
+	/// asset twice.
+	pub(crate) fn ensure_valid_path(path: &[AssetIdOf<T>]) -> Result<(), Error<T>> {
+		ensure!(path.len() >= 2, Error::<T>::InvalidPath);
+		ensure!(path.len() as u32 <= T::MaxHops::get().saturating_add(1), Error::<T>::InvalidPath);
+		for (index, asset) in path.iter().enumerate() {
+			ensure!(!path[index + 1..].contains(asset), Error::<T>::InvalidPath);
+		}
+		Ok(())
+	}
+
+	/// Amounts moved by selling exactly `amount_in` along `path`, at the current reserves.
+	pub fn quote_path_exact_in(
+		path: &[AssetIdOf<T>],
+		amount_in: BalanceOf<T>,
+	) -> Result<Vec<BalanceOf<T>>, DispatchError> {
+		Self::ensure_valid_path(path)?;
+
+		let mut amounts = Vec::from([amount_in]);
+		for hop in path.windows(2) {
+			let (reserve_in, reserve_out) = Self::oriented_reserves(hop[0].clone(), hop[1].clone())?;
+			amounts.push(Self::get_amount_out(amounts[amounts.len() - 1], reserve_in, reserve_out)?);
+		}
+		Ok(amounts)
+	}
+
+	/// Amounts moved by buying exactly `amount_out` along `path`, at the current reserves.
+	pub fn quote_path_exact_out(
+		path: &[AssetIdOf<T>],
+		amount_out: BalanceOf<T>,
+	) -> Result<Vec<BalanceOf<T>>, DispatchError> {
+		Self::ensure_valid_path(path)?;
+
+		let mut amounts = Vec::from([amount_out]);
+		for hop in path.windows(2).rev() {
+			let (reserve_in, reserve_out) = Self::oriented_reserves(hop[0].clone(), hop[1].clone())?;
+			amounts.push(Self::get_amount_in(amounts[amounts.len() - 1], reserve_in, reserve_out)?);
+		}
+		amounts.reverse();
+		Ok(amounts)
+	}
+
+	/// Sell exactly `amount_in` of the first asset of `path` for at least `amount_out_min` of its
+	/// last asset. Returns the amount received.
+	pub fn do_swap_exact_in_along_path(
+		sender: T::AccountId,
+		path: Vec<AssetIdOf<T>>,
+		amount_in: BalanceOf<T>,
+		amount_out_min: BalanceOf<T>,
+	) -> Result<BalanceOf<T>, DispatchError> {
+		ensure!(amount_in > Zero::zero(), Error::<T>::InvalidAmount);
+		ensure!(amount_out_min > Zero::zero(), Error::<T>::InvalidAmount);
+		Self::prepare_path(&path)?;
+
+		let amounts = Self::quote_path_exact_in(&path, amount_in)?;
+		let amount_out = amounts[amounts.len() - 1];
+		ensure!(amount_out >= amount_out_min, Error::<T>::ProvidedMinimumNotSufficientForSwap);
+
+		Self::swap_along_path(sender, &path, &amounts)?;
+		Ok(amount_out)
+	}
+
+	/// Buy exactly `amount_out` of the last asset of `path` with at most `amount_in_max` of its
+	/// first asset. Returns the amount paid.
+	pub fn do_swap_exact_out_along_path(
+		sender: T::AccountId,
+		path: Vec<AssetIdOf<T>>,
+		amount_out: BalanceOf<T>,
+		amount_in_max: BalanceOf<T>,
+	) -> Result<BalanceOf<T>, DispatchError> {
+		ensure!(amount_out > Zero::zero(), Error::<T>::InvalidAmount);
+		ensure!(amount_in_max > Zero::zero(), Error::<T>::InvalidAmount);
+		Self::prepare_path(&path)?;
+
+		let amounts = Self::quote_path_exact_out(&path, amount_out)?;
+		let amount_in = amounts[0];
+		ensure!(amount_in <= amount_in_max, Error::<T>::ProvidedMaximumNotSufficientForSwap);
+
+		Self::swap_along_path(sender, &path, &amounts)?;
+		Ok(amount_in)
+	}
+
+	/// Check that `routes` holds at least one and at most `MaxSplitRoutes` routes, all from the same
+	/// asset to the same asset, and return those assets.
+	fn ensure_valid_split(
+		routes: &[(Vec<AssetIdOf<T>>, BalanceOf<T>)],
+	) -> Result<(AssetIdOf<T>, AssetIdOf<T>), Error<T>> {
+		ensure!(routes.len() as u32 <= T::MaxSplitRoutes::get(), Error::<T>::InvalidSplit);
+		let (path, _) = routes.first().ok_or(Error::<T>::InvalidSplit)?;
+		let ends = (path.first().cloned(), path.last().cloned());
+		ensure!(
+			routes.iter().all(|(path, _)| (path.first().cloned(), path.last().cloned()) == ends),
+			Error::<T>::InvalidSplit
+		);
+		match ends {
+			(Some(asset_in), Some(asset_out)) => Ok((asset_in, asset_out)),
+			_ => Err(Error::<T>::InvalidPath),
+		}
+	}
+
+	/// Amounts received by selling the amount of each of `routes` along its path, one route after
+	/// the other as `swap_exact_in_along_paths` does, at the current reserves.
+	pub fn quote_split_exact_in(
+		routes: &[(Vec<AssetIdOf<T>>, BalanceOf<T>)],
+	) -> Result<Vec<BalanceOf<T>>, DispatchError> {
+		Self::ensure_valid_split(routes)?;
+
+		// Reserves of the pools traded by the previous routes, as left by them.
+		let mut reserves: Vec<(AssetPair<T>, BalanceOf<T>, BalanceOf<T>)> = Vec::new();
+		let mut amounts_out = Vec::with_capacity(routes.len());
+		for (path, amount_in) in routes {
+			Self::ensure_valid_path(path)?;
+
+			let mut amount = *amount_in;
+			for hop in path.windows(2) {
+				let pair = AssetPair::<T>::new(hop[0].clone(), hop[1].clone())?;
+				let index = match reserves.iter().position(|(traded, ..)| *traded == pair) {
+					Some(index) => index,
+					None => {
+						let pool_info = Pools::<T>::get(&pair).ok_or(Error::<T>::PoolNotFound)?;
+						reserves.push((pair, pool_info.reserve_a, pool_info.reserve_b));
+						reserves.len() - 1
+					},
+				};
+				let (pair, reserve_a, reserve_b) = &mut reserves[index];
+				let (reserve_in, reserve_out) =
+					if hop[0] == pair.asset_a { (reserve_a, reserve_b) } else { (reserve_b, reserve_a) };
+				let amount_out = Self::get_amount_out(amount, *reserve_in, *reserve_out)?;
+
+				*reserve_in = reserve_in.checked_add(&amount).ok_or(Error::<T>::Overflow)?;
+				*reserve_out = reserve_out.checked_sub(&amount_out).ok_or(Error::<T>::Overflow)?;
+				amount = amount_out;
+			}
+			amounts_out.push(amount);
+		}
+		Ok(amounts_out)
+	}
+
+	/// Sell the amount of each of `routes` along its path for at least `amount_out_min` of the last
+	/// asset in total. Returns the amount received.
+	pub fn do_swap_exact_in_along_paths(
+		sender: T::AccountId,
+		routes: Vec<(Vec<AssetIdOf<T>>, BalanceOf<T>)>,
+		amount_out_min: BalanceOf<T>,
+	) -> Result<BalanceOf<T>, DispatchError> {
+		ensure!(amount_out_min > Zero::zero(), Error::<T>::InvalidAmount);
+		let (asset_in, asset_out) = Self::ensure_valid_split(&routes)?;
+		let count = routes.len() as u32;
+
+		with_storage_layer(|| {
+			let mut amount_in = BalanceOf::<T>::zero();
+			let mut amount_out = BalanceOf::<T>::zero();
+			for (path, amount) in routes {
+				ensure!(amount > Zero::zero(), Error::<T>::InvalidAmount);
+				Self::prepare_path(&path)?;
+
+				let amounts = Self::quote_path_exact_in(&path, amount)?;
+				Self::swap_along_path(sender.clone(), &path, &amounts)?;
+
+				amount_in = amount_in.checked_add(&amount).ok_or(Error::<T>::Overflow)?;
+				amount_out =
+					amount_out.checked_add(&amounts[amounts.len() - 1]).ok_or(Error::<T>::Overflow)?;
+			}
+			ensure!(amount_out >= amount_out_min, Error::<T>::ProvidedMinimumNotSufficientForSwap);
+
+			Self::deposit_event(Event::SplitSwapExecuted {
+				sender,
+				asset_in,
+				amount_in,
+				asset_out,
+				amount_out,
+				routes: count,
+			});
+
+			Ok(amount_out)
+		})
+	}
+
+	/// Check `path` and bring the reserves of its pools up to date, so that they can be quoted.
+	fn prepare_path(path: &[AssetIdOf<T>]) -> DispatchResult {
+		Self::ensure_valid_path(path)?;
+		for hop in path.windows(2) {
+			let pair = AssetPair::<T>::new(hop[0].clone(), hop[1].clone())?;
+			Self::ensure_pool_unlocked(&pair)?;
+			Self::execute_virtual_orders(&pair)?;
+		}
+		Ok(())
+	}
+
+	/// Swap the `amounts` quoted for `path` through each of its pools.
+	fn swap_along_path(
+		sender: T::AccountId,
+		path: &[AssetIdOf<T>],
+		amounts: &[BalanceOf<T>],
+	) -> DispatchResult {
+		T::Fungibles::transfer(path[0].clone(), &sender, &Self::account_id(), amounts[0], Preserve)?;
+
+		for (hop, amount) in path.windows(2).zip(amounts.windows(2)) {
+			let (asset_in, asset_out, amount_in, amount_out) = (&hop[0], &hop[1], amount[0], amount[1]);
+			let pair = AssetPair::<T>::new(asset_in.clone(), asset_out.clone())?;
+			let mut pool_info = Pools::<T>::get(&pair).ok_or(Error::<T>::PoolNotFound)?;
+
+			if *asset_in == pair.asset_a {
+				pool_info.reserve_a = pool_info.reserve_a.checked_add(&amount_in).ok_or(Error::<T>::Overflow)?;
+				pool_info.reserve_b = pool_info.reserve_b.checked_sub(&amount_out).ok_or(Error::<T>::Overflow)?;
+			} else {
+				pool_info.reserve_a = pool_info.reserve_a.checked_sub(&amount_out).ok_or(Error::<T>::Overflow)?;
+				pool_info.reserve_b = pool_info.reserve_b.checked_add(&amount_in).ok_or(Error::<T>::Overflow)?;
+			}
+
+			Self::update_reserves(&pair, pool_info.clone());
+			Self::record_trade(&pair, asset_in.clone(), amount_in, amount_out, Self::pool_fee(amount_in));
+			Self::deposit_swap_events(
+				sender.clone(),
+				pair,
+				asset_in.clone(),
+				amount_in,
+				asset_out.clone(),
+				amount_out,
+				&pool_info,
+			);
+		}
+
+		let last = path.len() - 1;
+		T::Fungibles::transfer(path[last].clone(), &Self::account_id(), &sender, amounts[last], Preserve)?;
+		Ok(())
+	}
+}
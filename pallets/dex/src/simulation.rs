@@ -0,0 +1,108 @@
+//! Dry runs of swaps.
+//!
+//! A simulation executes the real swap on behalf of the trader inside a storage transaction that
+//! is always rolled back, so it fails with exactly the error the swap would fail with, and reports
+//! what the swap would have done to the pool.
+
+use crate::*;
+use frame_support::{pallet_prelude::*, storage::with_transaction};
+use sp_runtime::{
+	traits::{CheckedAdd, CheckedSub},
+	FixedPointNumber, FixedU128, Perbill, TransactionOutcome,
+};
+
+/// Breakdown of a simulated swap.
+#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebug, TypeInfo)]
+pub struct SwapSimulation<Balance> {
+	/// Amount paid by the trader.
+	pub amount_in: Balance,
+	/// Amount received by the trader.
+	pub amount_out: Balance,
+	/// Pool fee taken from `amount_in`.
+	pub fee: Balance,
+	/// Price of the asset sold in the asset bought at the reserves before the swap.
+	pub spot_price: FixedU128,
+	/// Price of the asset sold in the asset bought actually paid, `amount_out / amount_in`.
+	pub effective_price: FixedU128,
+	/// How far the effective price falls short of the spot price, fee included.
+	pub price_impact: Perbill,
+	/// Reserve of the pair's `asset_a` after the swap.
+	pub reserve_a: Balance,
+	/// Reserve of the pair's `asset_b` after the swap.
+	pub reserve_b: Balance,
+}
+
+impl<T: Config> Pallet<T> {
+	/// Dry run of `who` selling exactly `amount_in` of `asset_in` for at least `amount_out_min` of
+	/// `asset_out`, as `swap_exact_asset_for_asset` would execute it.
+	pub fn simulate_swap_exact_in(
+		who: T::AccountId,
+		asset_in: AssetIdOf<T>,
+		asset_out: AssetIdOf<T>,
+		amount_in: BalanceOf<T>,
+		amount_out_min: BalanceOf<T>,
+	) -> Result<SwapSimulation<BalanceOf<T>>, DispatchError> {
+		Self::simulate(asset_in.clone(), asset_out.clone(), || {
+			let amount_out =
+				Self::do_swap_exact_asset_for_asset(who, asset_in, asset_out, amount_in, amount_out_min)?;
+			Ok((amount_in, amount_out))
+		})
+	}
+
+	/// Dry run of `who` buying exactly `amount_out` of `asset_out` with at most `amount_in_max` of
+	/// `asset_in`, as `swap_asset_for_exact_asset` would execute it.
+	pub fn simulate_swap_exact_out(
+		who: T::AccountId,
+		asset_in: AssetIdOf<T>,
+		asset_out: AssetIdOf<T>,
+		amount_out: BalanceOf<T>,
+		amount_in_max: BalanceOf<T>,
+	) -> Result<SwapSimulation<BalanceOf<T>>, DispatchError> {
+		Self::simulate(asset_in.clone(), asset_out.clone(), || {
+			let amount_in =
+				Self::do_swap_asset_for_exact_asset(who, asset_in, asset_out, amount_out, amount_in_max)?;
+			Ok((amount_in, amount_out))
+		})
+	}
+
+	/// Run `swap`, which returns the amounts it moved, and describe it from the reserves it left
+	/// the pool of `asset_in` and `asset_out` with, then roll everything back.
+	fn simulate(
+		asset_in: AssetIdOf<T>,
+		asset_out: AssetIdOf<T>,
+		swap: impl FnOnce() -> Result<(BalanceOf<T>, BalanceOf<T>), DispatchError>,
+	) -> Result<SwapSimulation<BalanceOf<T>>, DispatchError> {
+		with_transaction(|| {
+			let simulation = swap().and_then(|(amount_in, amount_out)| {
+				let pair = AssetPair::<T>::new(asset_in.clone(), asset_out.clone())?;
+				let (reserve_in, reserve_out) = Self::oriented_reserves(asset_in.clone(), asset_out)?;
+				// Undo the swap to find the reserves it started from.
+				let reserve_in_before =
+					reserve_in.checked_sub(&amount_in).ok_or(Error::<T>::Overflow)?;
+				let reserve_out_before =
+					reserve_out.checked_add(&amount_out).ok_or(Error::<T>::Overflow)?;
+
+				let spot_price = Self::price(reserve_out_before, reserve_in_before);
+				let effective_price = Self::price(amount_out, amount_in);
+				let price_impact = Perbill::from_rational(
+					spot_price.into_inner().saturating_sub(effective_price.into_inner()),
+					spot_price.into_inner(),
+				);
+				let (reserve_a, reserve_b) =
+					if asset_in == pair.asset_a { (reserve_in, reserve_out) } else { (reserve_out, reserve_in) };
+
+				Ok(SwapSimulation {
+					amount_in,
+					amount_out,
+					fee: Self::pool_fee(amount_in),
+					spot_price,
+					effective_price,
+					price_impact,
+					reserve_a,
+					reserve_b,
+				})
+			});
+			TransactionOutcome::Rollback(simulation)
+		})
+	}
+}
@@ -0,0 +1,125 @@
+//! Trading statistics of pools.
+//!
+//! Every swap through a pool is added to the bucket of the `StatsWindow` blocks it happened in.
+//! Only the last `MaxStatsBuckets` buckets of each pool are kept, oldest first, so dashboards can
+//! sum the buckets they need (e.g. the last 24 hours) through the runtime API.
+
+use crate::*;
+use frame_support::{pallet_prelude::*, sp_std::vec::Vec};
+use frame_system::pallet_prelude::BlockNumberFor;
+use sp_runtime::{
+	traits::{One, Saturating, Zero},
+	FixedU128,
+};
+
+/// Trading statistics of a pool over the `StatsWindow` blocks starting at `start`.
+#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebug, MaxEncodedLen, TypeInfo)]
+pub struct StatsBucket<BlockNumber, Balance> {
+	/// First block of the window.
+	pub start: BlockNumber,
+	/// Amount of the pair's `asset_a` swapped into or out of the pool.
+	pub volume_a: Balance,
+	/// Amount of the pair's `asset_b` swapped into or out of the pool.
+	pub volume_b: Balance,
+	/// Pool fees charged in `asset_a`.
+	pub fees_a: Balance,
+	/// Pool fees charged in `asset_b`.
+	pub fees_b: Balance,
+	/// Number of swaps.
+	pub trades: u32,
+	/// Price of `asset_a` in `asset_b` paid by the last swap.
+	pub last_price: FixedU128,
+}
+
+impl<T: Config> Pallet<T> {
+	/// Add a swap of `amount_in` of `asset_in` for `amount_out` of the other asset of `pair`, of
+	/// which `fee` was taken by the pool, to the pool's statistics.
+	pub(crate) fn record_trade(
+		pair: &AssetPair<T>,
+		asset_in: AssetIdOf<T>,
+		amount_in: BalanceOf<T>,
+		amount_out: BalanceOf<T>,
+		fee: BalanceOf<T>,
+	) {
+		let (amount_a, amount_b) =
+			if asset_in == pair.asset_a { (amount_in, amount_out) } else { (amount_out, amount_in) };
+		if amount_a.is_zero() {
+			return
+		}
+
+		let now = frame_system::Pallet::<T>::block_number();
+		let window = T::StatsWindow::get().max(One::one());
+		let start = now - now % window;
+
+		PoolStats::<T>::mutate(pair, |buckets| {
+			if buckets.last().map_or(true, |bucket| bucket.start != start) {
+				let bucket = StatsBucket {
+					start,
+					volume_a: Zero::zero(),
+					volume_b: Zero::zero(),
+					fees_a: Zero::zero(),
+					fees_b: Zero::zero(),
+					trades: 0,
+					last_price: Zero::zero(),
+				};
+				// Make room for the new bucket by dropping the oldest one.
+				if let Err(bucket) = buckets.try_push(bucket) {
+					if !buckets.is_empty() {
+						buckets.remove(0);
+					}
+					let _ = buckets.try_push(bucket);
+				}
+			}
+			let Some(bucket) = buckets.last_mut() else { return };
+
+			bucket.volume_a = bucket.volume_a.saturating_add(amount_a);
+			bucket.volume_b = bucket.volume_b.saturating_add(amount_b);
+			if asset_in == pair.asset_a {
+				bucket.fees_a = bucket.fees_a.saturating_add(fee);
+			} else {
+				bucket.fees_b = bucket.fees_b.saturating_add(fee);
+			}
+			bucket.trades = bucket.trades.saturating_add(1);
+			bucket.last_price = Self::price(amount_b, amount_a);
+		});
+	}
+
+	/// Trading statistics of the pool of `asset_a` and `asset_b`, oldest bucket first.
+	pub fn pool_stats(
+		asset_a: AssetIdOf<T>,
+		asset_b: AssetIdOf<T>,
+	) -> Vec<StatsBucket<BlockNumberFor<T>, BalanceOf<T>>> {
+		AssetPair::<T>::new(asset_a, asset_b)
+			.map(|pair| PoolStats::<T>::get(pair).into_inner())
+			.unwrap_or_default()
+	}
+
+	/// Swaps through the pool of `asset_a` and `asset_b` in the current block, in order, as the
+	/// amounts of `asset_a` and `asset_b` swapped. Read from the block's events, so only meant for
+	/// off-chain queries.
+	pub fn block_swaps(
+		asset_a: AssetIdOf<T>,
+		asset_b: AssetIdOf<T>,
+	) -> Vec<(BalanceOf<T>, BalanceOf<T>)>
+	where
+		<T as Config>::RuntimeEvent: TryInto<Event<T>>,
+	{
+		let Ok(pair) = AssetPair::<T>::new(asset_a.clone(), asset_b) else { return Vec::new() };
+
+		frame_system::Pallet::<T>::read_events_no_consensus()
+			.filter_map(|record| {
+				let event = <T as Config>::RuntimeEvent::from(record.event).try_into().ok()?;
+				match event {
+					Event::SwapExecuted { pair: swap_pair, asset_in, amount_in, amount_out, .. }
+						if swap_pair == pair =>
+						Some(if asset_in == asset_a {
+							(amount_in, amount_out)
+						} else {
+							(amount_out, amount_in)
+						}),
+					_ => None,
+				}
+			})
+			.collect()
+	}
+}
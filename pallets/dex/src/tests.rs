@@ -1,33 +1,23 @@
-use crate::{mock::*, AssetPair, Error, Event, Pools};
+use crate::{migrations, mock::*, AllowedAssets, AssetPair, DexFeeAdapter, Error, Event, FarmStakes, HoldReason, FlashLoan, LockedPosition, LongTermOrders, LpPosition, PathOf, PoolInfo, PoolStatus, PoolStats, Pools, Route, StatsBucket, Swap, split_greedily, SplitRoutesOf, SwapIntents, SwapSimulation, TwammPools, VirtualOrdersCursor};
 use frame_support::{assert_noop, assert_ok};
-use frame_support::traits::fungibles::InspectEnumerable;
+use frame_support::dispatch::{DispatchInfo, GetDispatchInfo, PostDispatchInfo};
+use frame_support::traits::{ConstU32, GetStorageVersion, Hooks, OnRuntimeUpgrade, StorageVersion};
+use frame_support::traits::fungible::InspectHold;
+use frame_support::traits::fungibles::{Balanced, Inspect, InspectEnumerable};
 use frame_support::traits::fungibles::Mutate;
-use sp_runtime::{DispatchError, Percent, TokenError};
+use frame_support::traits::tokens::{Fortitude, Precision, Preservation};
+use pallet_asset_tx_payment::OnChargeAssetTransaction;
+use sp_runtime::{traits::Dispatchable, DispatchError, FixedPointNumber, FixedU128, Perbill, Percent, TokenError};
 
 
 
 type NativeBalance = <Test as crate::Config>::NativeBalance;
 type Fungibles = <Test as crate::Config>::Fungibles;
+type PoolAssets = <Test as crate::Config>::PoolAssets;
 
 
 
 
-// Implementation for `Ord` and `PartialOrd` traits for `mock::Test`.
-// This is necessary for certain structs where `Test` is used as a generic parameter,
-// and those structs derive the `RuntimeDebugNoBound` trait.
-// e.g. AssetPair<T: Config>
-impl Ord for Test {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
-    }
-}
-
-impl PartialOrd for Test {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
 /// Creates a new token for the specified owner with the given asset ID.
 fn create_token(owner: u64, asset_id: u32) {
 	assert_ok!(
@@ -57,13 +47,21 @@ fn get_asset_balance(who: u64, asset_id: u32) -> u128 {
 	Fungibles::balance(asset_id, who)
 }
 
+/// Retrieves the balance of a specific LP token for the specified account.
+/// 
+/// # Returns
+/// The balance of the specified LP token for the specified account.
+fn get_lp_balance(who: u64, lp_asset: u32) -> u128 {
+	PoolAssets::balance(lp_asset, who)
+}
+
 /// Retrieves a sorted list of all liquidity pool pairs (AssetPairs) available in the system.
 /// 
 /// # Returns
 /// A sorted vector containing all available liquidity pool pairs (AssetPairs) in the system.
 fn get_pools() -> Vec<AssetPair<Test>> {
 	let mut s: Vec<_> = Pools::<Test>::iter().map(|x| x.0).collect();
-	s.sort();
+	s.sort_by_key(|pair| (pair.asset_a, pair.asset_b));
 	s
 }
 
@@ -77,6 +75,103 @@ fn get_assets() -> Vec<u32> {
 	s
 }
 
+/// Retrieves a sorted list of all LP token IDs available in the system.
+/// 
+/// # Returns
+/// A sorted vector containing all available LP token IDs in the system.
+fn get_lp_assets() -> Vec<u32> {
+	let mut s: Vec<_> = PoolAssets::asset_ids().map(|id| id).collect();
+	s.sort();
+	s
+}
+
+/// Creates `asset_a` and `asset_b` unless they exist, opens a pool for them and seeds it with
+/// `amount_a` and `amount_b` provided by `creator`.
+fn create_pool_with_liquidity(
+	creator: u64,
+	asset_a: u32,
+	asset_b: u32,
+	lp_asset: u32,
+	amount_a: u128,
+	amount_b: u128,
+) {
+	for asset in [asset_a, asset_b] {
+		if !get_assets().contains(&asset) {
+			create_token(creator, asset);
+		}
+	}
+
+	assert_ok!(
+		NativeBalance::force_set_balance(
+			RuntimeOrigin::root(),
+			creator,
+			1_000_000u128
+		)
+	);
+
+	assert_ok!(
+		Dex::create_pool(
+			RuntimeOrigin::signed(creator),
+			asset_a,
+			asset_b,
+			lp_asset
+		)
+	);
+
+	let min_balance = MinBalance::get();
+	assert_ok!(Fungibles::mint_into(asset_a.into(), &creator, amount_a + min_balance));
+	assert_ok!(Fungibles::mint_into(asset_b.into(), &creator, amount_b + min_balance));
+
+	assert_ok!(
+		Dex::add_liquidity(
+			RuntimeOrigin::signed(creator),
+			asset_a,
+			asset_b,
+			amount_a,
+			amount_b,
+			1,
+			1,
+			creator,
+		)
+	);
+}
+
+/// Gives `who` enough native balance to hold assets and mints them `amount` of `asset_id`.
+fn fund_account(who: u64, asset_id: u32, amount: u128) {
+	assert_ok!(
+		NativeBalance::force_set_balance(
+			RuntimeOrigin::root(),
+			who,
+			10_000u128
+		)
+	);
+	assert_ok!(Fungibles::mint_into(asset_id.into(), &who, amount));
+}
+
+/// Advances the chain to block `n`, running `on_initialize` for every block on the way.
+fn run_to_block(n: u64) {
+	while System::block_number() < n {
+		let next = System::block_number() + 1;
+		System::set_block_number(next);
+		Dex::on_initialize(next);
+	}
+}
+
+/// Bounds `path` as the swap extrinsics take it.
+fn bounded_path(path: &[u32]) -> PathOf<Test> {
+	path.to_vec().try_into().unwrap()
+}
+
+/// Bounds `routes` as `swap_exact_in_along_paths` takes them.
+fn bounded_routes(routes: &[(Vec<u32>, u128)]) -> SplitRoutesOf<Test> {
+	routes
+		.iter()
+		.map(|(path, amount)| (bounded_path(path), *amount))
+		.collect::<Vec<_>>()
+		.try_into()
+		.unwrap()
+}
+
 /// Retrieves events related to the Dex runtime from the system's event log.
 ///
 /// # Returns
@@ -104,12 +199,51 @@ fn get_events() -> Vec<Event<Test>> {
 #[test]
 fn check_max_numbers() {
 	new_test_ext().execute_with(|| {
-		assert!(Dex::quote(3u128, u128::MAX, u128::MAX).is_err()); // 3 * u128::MAX / u128::MAX
+		assert_eq!(Dex::quote(3u128, u128::MAX, u128::MAX).ok().unwrap(), 3u128); // 3 * u128::MAX / u128::MAX
 		assert!(Dex::quote(u128::MAX, 3u128, u128::MAX).is_err()); // u128::MAX * u128::MAX / 3
 		assert_eq!(Dex::quote(u128::MAX, u128::MAX, 1u128).ok().unwrap(), 1u128); // u128::MAX * 1u128 / u128::MAX
 	});
 }
 
+#[test]
+fn amm_math_handles_large_reserves() {
+	new_test_ext().execute_with(|| {
+		// 10^12 tokens of 18 decimals, and reserves at the limit of the balance type.
+		let reserve = 1_000_000_000_000u128 * 10u128.pow(18);
+		let max = u128::MAX / 2;
+
+		let amount_out = Dex::get_amount_out(reserve, reserve, reserve).unwrap();
+		assert_eq!(amount_out, reserve * 99 / 199);
+		assert!(Dex::get_amount_out(max, max, max).is_ok());
+
+		let amount_in = Dex::get_amount_in(reserve / 2, reserve, reserve).unwrap();
+		assert!(Dex::get_amount_out(amount_in, reserve, reserve).unwrap() >= reserve / 2);
+		assert!(Dex::get_amount_in(max / 2, max, max).is_ok());
+		assert_eq!(Dex::get_amount_in(u128::MAX - 1, u128::MAX, u128::MAX), Err(Error::<Test>::Overflow));
+
+		assert_eq!(Dex::get_lp_amount_for_zero_supply(reserve, reserve).unwrap(), reserve - 10u128);
+		assert_eq!(Dex::get_lp_amount_for_zero_supply(u128::MAX, u128::MAX).unwrap(), u128::MAX - 10u128);
+	});
+}
+
+#[test]
+fn pools_with_large_reserves_are_usable() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let reserve = 1_000_000_000_000u128 * 10u128.pow(18);
+		create_pool_with_liquidity(provider, 1u32, 2u32, 1234u32, reserve, reserve);
+		fund_account(alice, 1u32, reserve);
+
+		assert_ok!(Dex::swap_exact_asset_for_asset(RuntimeOrigin::signed(alice), 1u32, 2u32, reserve / 10, 1));
+		assert_ok!(Dex::swap_asset_for_exact_asset(RuntimeOrigin::signed(alice), 1u32, 2u32, reserve / 10, reserve));
+
+		let lp_balance = get_lp_balance(provider, 1234u32);
+		assert_ok!(Dex::remove_liquidity(RuntimeOrigin::signed(provider), 1u32, 2u32, lp_balance / 2, 1, 1));
+		assert_ok!(Dex::do_try_state());
+	});
+}
+
 
 #[test]
 fn create_pool_successfully() {
@@ -151,9 +285,12 @@ fn create_pool_successfully() {
 			initial_balance - fee 
 		);
 
-		// Get pallet account balance
-		let pallet_balance = get_native_balance(PalletAccountId::get());
-		assert_eq!(pallet_balance, fee);
+		// The fee is held from the creator as a deposit, not paid to the pallet account
+		assert_eq!(
+			NativeBalance::balance_on_hold(&HoldReason::PoolCreation.into(), &creator),
+			fee
+		);
+		assert_eq!(get_native_balance(PalletAccountId::get()), 0);
 
 		// Check emited events
 		assert_eq!(
@@ -168,7 +305,8 @@ fn create_pool_successfully() {
 		// Check pair added to the pool
 		assert_eq!(get_pools(), vec![pair.clone()]);
 		// Check lp_asset created 
-		assert_eq!(get_assets(), vec![asset_a.clone(), asset_b.clone(), lp_asset.clone()]);
+		assert_eq!(get_assets(), vec![asset_a.clone(), asset_b.clone()]);
+		assert_eq!(get_lp_assets(), vec![lp_asset.clone()]);
 
 		assert_noop!(
 			Dex::create_pool(
@@ -280,9 +418,12 @@ fn create_pool_twice_failure() {
 			initial_balance - fee 
 		);
 
-		// Get pallet account balance
-		let pallet_balance = get_native_balance(PalletAccountId::get());
-		assert_eq!(pallet_balance, fee);
+		// The fee is held from the creator as a deposit, not paid to the pallet account
+		assert_eq!(
+			NativeBalance::balance_on_hold(&HoldReason::PoolCreation.into(), &creator),
+			fee
+		);
+		assert_eq!(get_native_balance(PalletAccountId::get()), 0);
 
 		// Check emited events
 		assert_eq!(
@@ -307,12 +448,37 @@ fn create_pool_twice_failure() {
 		// Check pair added to the pool
 		assert_eq!(get_pools(), vec![pair.clone()]);
 		// Check lp_asset created 
-		assert_eq!(get_assets(), vec![asset_a.clone(), asset_b.clone(), lp_asset.clone()]);
+		assert_eq!(get_assets(), vec![asset_a.clone(), asset_b.clone()]);
+		assert_eq!(get_lp_assets(), vec![lp_asset.clone()]);
 
 		// Can we create a pool if one of the token not native?
 	});
 }
 
+#[test]
+fn lp_assets_do_not_collide_with_traded_assets() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+
+		// The LP token of the pool of 1 and 2 shares its id with the traded asset 3.
+		create_pool_with_liquidity(provider, 1u32, 2u32, 3u32, 10_000u128, 10_000u128);
+		create_token(provider, 3u32);
+		fund_account(alice, 3u32, 500u128);
+
+		let lp_balance = get_lp_balance(provider, 3u32);
+		assert!(lp_balance > 0);
+		assert_eq!(get_asset_balance(provider, 3u32), 0);
+		assert_eq!(get_asset_balance(alice, 3u32), 500u128);
+		assert_eq!(get_lp_balance(alice, 3u32), 0);
+
+		assert_ok!(Dex::remove_liquidity(RuntimeOrigin::signed(provider), 1u32, 2u32, lp_balance, 1, 1));
+		assert_eq!(get_lp_balance(provider, 3u32), 0);
+		assert_eq!(get_asset_balance(alice, 3u32), 500u128);
+		assert_ok!(Dex::do_try_state());
+	});
+}
+
 #[test]
 fn different_pools_should_have_different_lp_assets() {
 	new_test_ext().execute_with(|| {
@@ -466,7 +632,7 @@ fn add_liqudity_sucessfully() {
 		assert_eq!(get_asset_balance(pallet_account_id, asset_b.clone()), 10u128);
 		assert_eq!(get_asset_balance(sender, asset_a.clone()), 90000u128 + 10u128);
 		assert_eq!(get_asset_balance(sender, asset_b.clone()), 100_000u128);
-		assert_eq!(get_asset_balance(sender, lp_asset_a), 306u128);
+		assert_eq!(get_lp_balance(sender, lp_asset_a), 306u128);
 	});
 }
 
@@ -681,9 +847,9 @@ fn remove_liquidity_sucessfully() {
 		let pallet_account_id = PalletAccountId::get();
 		assert_eq!(get_asset_balance(pallet_account_id, asset_a.clone()), 100_000u128);
 		assert_eq!(get_asset_balance(pallet_account_id, asset_b.clone()), 10_000u128);
-		assert_eq!(get_asset_balance(pallet_account_id, lp_asset_a.clone()), 10);
+		assert_eq!(get_lp_balance(pallet_account_id, lp_asset_a.clone()), 10);
 
-		let sender_lp_asset_balance = get_asset_balance(sender, lp_asset_a.clone());
+		let sender_lp_asset_balance = get_lp_balance(sender, lp_asset_a.clone());
 
 		assert_ok!(
 			Dex::remove_liquidity(
@@ -721,7 +887,7 @@ fn remove_liquidity_sucessfully() {
 		let pallet_account_id = PalletAccountId::get();
 		assert_eq!(get_asset_balance(pallet_account_id, asset_a.clone()), 1031);
 		assert_eq!(get_asset_balance(pallet_account_id, asset_b.clone()), 104);
-		assert_eq!(get_asset_balance(pallet_account_id, lp_asset_a.clone()), 10);
+		assert_eq!(get_lp_balance(pallet_account_id, lp_asset_a.clone()), 10);
 
 		assert_eq!(
 			get_asset_balance(sender, asset_a.clone()),
@@ -733,7 +899,7 @@ fn remove_liquidity_sucessfully() {
 		);
 
 		// All minted amount for sender removed
-		assert_eq!(get_asset_balance(sender, lp_asset_a), 0);
+		assert_eq!(get_lp_balance(sender, lp_asset_a), 0);
 	});
 }
 
@@ -793,7 +959,7 @@ fn can_not_redeem_more_lp_tokens_than_were_minted() {
 
 		// Pool balances after add liquidity
 		let minted_lp_amount = 306u128;
-		assert_eq!(get_asset_balance(sender, lp_asset_a.clone()), minted_lp_amount);
+		assert_eq!(get_lp_balance(sender, lp_asset_a.clone()), minted_lp_amount);
 
 		assert_noop!(
 			Dex::remove_liquidity(
@@ -958,6 +1124,21 @@ fn quote_prices() {
 	});
 }
 
+#[test]
+fn get_amount_in_quotes_enough_to_cover_the_fee() {
+	new_test_ext().execute_with(|| {
+		// With a 1% fee, the old quote of `reserve_in * amount_out / (reserve_out - fee(amount_out)) + 1`
+		// was 1_000_000 / 9_999 + 1 = 101, which only buys 99 of the 100 requested.
+		assert_eq!(Dex::get_amount_out(101, 10_000, 10_000), Ok(99));
+
+		// The corrected quote divides by the output reserve left after the swap, net of the fee:
+		// 1_000_000 * 100 / (9_900 * 99) + 1 = 103.
+		assert_eq!(Dex::get_amount_in(100, 10_000, 10_000), Ok(103));
+		assert_eq!(Dex::get_amount_out(103, 10_000, 10_000), Ok(100));
+		assert_eq!(Dex::get_amount_out(102, 10_000, 10_000), Ok(99));
+	});
+}
+
 #[test]
 fn swap_successfully() {
 	new_test_ext().execute_with(|| {
@@ -1117,6 +1298,76 @@ fn swappable_with_real_values() {
 	});
 }
 
+#[test]
+fn swap_and_liquidity_events_carry_fees_and_reserves() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let pair = AssetPair::<Test>::new(1u32, 2u32).unwrap();
+		create_pool_with_liquidity(provider, 1u32, 2u32, 1234u32, 10_000u128, 10_000u128);
+		fund_account(alice, 1u32, 1_000u128);
+		get_events();
+
+		let amount_out = Dex::get_amount_out(500u128, 10_000u128, 10_000u128).unwrap();
+		assert_ok!(Dex::swap_exact_asset_for_asset(RuntimeOrigin::signed(alice), 1u32, 2u32, 500u128, 1u128));
+		let events = get_events();
+		assert!(events.contains(&Event::<Test>::SwapExecuted {
+			sender: alice,
+			pair: pair.clone(),
+			asset_in: 1u32,
+			amount_in: 500u128,
+			asset_out: 2u32,
+			amount_out,
+			fee: 5u128,
+			reserve_a: 10_500u128,
+			reserve_b: 10_000u128 - amount_out,
+		}));
+		assert!(events.contains(&Event::<Test>::ReservesUpdated {
+			pair: pair.clone(),
+			reserve_a: 10_500u128,
+			reserve_b: 10_000u128 - amount_out,
+		}));
+
+		let lp_balance = get_lp_balance(provider, 1234u32);
+		let (reserve_a, reserve_b) = Dex::get_reserves(1u32, 2u32).unwrap();
+		assert_ok!(Dex::remove_liquidity(RuntimeOrigin::signed(provider), 1u32, 2u32, lp_balance / 2, 1, 1));
+		assert!(get_events().iter().any(|event| matches!(
+			event,
+			Event::<Test>::LiquidityWithdrawn { pair: p, burned, fee, amount_a, amount_b, reserve_a: a, reserve_b: b, .. }
+				if *p == pair && *burned == lp_balance / 2 && *fee == Dex::pool_fee(lp_balance / 2) &&
+					*a == reserve_a - amount_a && *b == reserve_b - amount_b
+		)));
+	});
+}
+
+#[test]
+fn legacy_events_are_only_emitted_while_enabled() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		create_pool_with_liquidity(provider, 1u32, 2u32, 1234u32, 10_000u128, 10_000u128);
+		fund_account(alice, 1u32, 1_000u128);
+
+		assert_ok!(Dex::swap_exact_asset_for_asset(RuntimeOrigin::signed(alice), 1u32, 2u32, 100u128, 1u128));
+		let events = get_events();
+		assert!(events.iter().any(|event| matches!(event, Event::<Test>::Swaped { .. })));
+		assert!(events.iter().any(|event| matches!(event, Event::<Test>::SwapExecuted { .. })));
+
+		LegacyEvents::set(false);
+		assert_ok!(Dex::swap_exact_asset_for_asset(RuntimeOrigin::signed(alice), 1u32, 2u32, 100u128, 1u128));
+		let events = get_events();
+		assert!(!events.iter().any(|event| matches!(event, Event::<Test>::Swaped { .. })));
+		assert!(events.iter().any(|event| matches!(event, Event::<Test>::SwapExecuted { .. })));
+
+		fund_account(provider, 1u32, 1_000u128);
+		fund_account(provider, 2u32, 1_000u128);
+		assert_ok!(Dex::add_liquidity(RuntimeOrigin::signed(provider), 1u32, 2u32, 100u128, 100u128, 1, 1, provider));
+		let events = get_events();
+		assert!(!events.iter().any(|event| matches!(event, Event::<Test>::LiquidityAdded { .. })));
+		assert!(events.iter().any(|event| matches!(event, Event::<Test>::LiquidityProvided { .. })));
+	});
+}
+
 #[test]
 fn disallow_swap_in_empty_liquidity_pool() {
 	new_test_ext().execute_with(|| {
@@ -1401,3 +1652,1759 @@ fn swap_of_same_asset_not_allowed() {
 		);
 	});
 }
+
+#[test]
+fn batch_intents_settle_at_uniform_price() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let bob = 3;
+		let asset_a = 1u32;
+		let asset_b = 2u32;
+
+		create_pool_with_liquidity(provider, asset_a, asset_b, 1234u32, 10_000u128, 10_000u128);
+
+		let min_balance = MinBalance::get();
+		fund_account(alice, asset_a, 1_000u128 + min_balance);
+		fund_account(bob, asset_b, 500u128 + min_balance);
+
+		assert_ok!(Dex::submit_swap_intent(RuntimeOrigin::signed(alice), asset_a, asset_b, 1_000u128, 1));
+		assert_ok!(Dex::submit_swap_intent(RuntimeOrigin::signed(bob), asset_b, asset_a, 500u128, 1));
+
+		// Inputs are escrowed until the end of the block.
+		assert_eq!(get_asset_balance(alice, asset_a), min_balance);
+		assert_eq!(get_asset_balance(bob, asset_b), min_balance);
+		assert_eq!(SwapIntents::<Test>::get().len(), 2);
+
+		Dex::on_finalize(1);
+
+		assert!(SwapIntents::<Test>::get().is_empty());
+
+		// Alice sold asset a, which outweighs Bob's side at the pool price, so only the imbalance
+		// was traded against the pool and the rest was crossed with Bob.
+		let alice_out = get_asset_balance(alice, asset_b);
+		let bob_out = get_asset_balance(bob, asset_a);
+		assert_eq!(alice_out, 944u128);
+		assert_eq!(bob_out, 530u128);
+
+		// Both sides paid the same price: alice_out / 1_000 == 500 / bob_out, up to rounding.
+		assert!((alice_out * bob_out).abs_diff(1_000u128 * 500u128) <= 1_000u128);
+
+		let pair = AssetPair::<Test>::new(asset_a, asset_b).expect("Can create pair");
+		assert_eq!(Dex::get_reserves(asset_a, asset_b).unwrap(), (10_000u128 + 470u128, 10_000u128 - 444u128));
+		let events = get_events();
+		assert!(
+			events.contains(
+				&Event::<Test>::BatchSettled {
+					pair: pair.clone(),
+					amount_a_in: 1_000u128,
+					amount_b_in: 500u128,
+					amount_a_out: 530u128,
+					amount_b_out: 944u128,
+				}
+			)
+		);
+
+		// The imbalance traded against the pool counts as a swap by the pallet account.
+		assert!(
+			events.contains(
+				&Event::<Test>::SwapExecuted {
+					sender: PalletAccountId::get(),
+					pair: pair.clone(),
+					asset_in: asset_a,
+					amount_in: 470u128,
+					asset_out: asset_b,
+					amount_out: 444u128,
+					fee: Dex::pool_fee(470u128),
+					reserve_a: 10_470u128,
+					reserve_b: 9_556u128,
+				}
+			)
+		);
+		let bucket = &PoolStats::<Test>::get(&pair)[0];
+		assert_eq!((bucket.volume_a, bucket.volume_b, bucket.trades), (470u128, 444u128, 1));
+		assert_eq!((bucket.fees_a, bucket.fees_b), (Dex::pool_fee(470u128), 0));
+	});
+}
+
+#[test]
+fn batch_intents_below_minimum_are_refunded() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let bob = 3;
+		let asset_a = 1u32;
+		let asset_b = 2u32;
+
+		create_pool_with_liquidity(provider, asset_a, asset_b, 1234u32, 10_000u128, 10_000u128);
+
+		let min_balance = MinBalance::get();
+		fund_account(alice, asset_a, 1_000u128 + min_balance);
+		fund_account(bob, asset_b, 500u128 + min_balance);
+
+		assert_ok!(Dex::submit_swap_intent(RuntimeOrigin::signed(alice), asset_a, asset_b, 1_000u128, 1));
+		// Bob asks for more than the clearing price can give him.
+		assert_ok!(Dex::submit_swap_intent(RuntimeOrigin::signed(bob), asset_b, asset_a, 500u128, 600u128));
+
+		Dex::on_finalize(1);
+
+		// Bob is refunded and Alice is settled alone against the pool.
+		assert_eq!(get_asset_balance(bob, asset_b), 500u128 + min_balance);
+		assert_eq!(get_asset_balance(bob, asset_a), 0);
+		assert_eq!(get_asset_balance(alice, asset_b), Dex::get_amount_out(1_000u128, 10_000u128, 10_000u128).unwrap());
+
+		assert!(
+			get_events().contains(
+				&Event::<Test>::SwapIntentRefunded {
+					who: bob,
+					asset_in: asset_b,
+					amount_in: 500u128,
+				}
+			)
+		);
+	});
+}
+
+#[test]
+fn failed_swap_intent_refunds_are_reported() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let asset_a = 1u32;
+		let asset_b = 2u32;
+		let pallet_account = PalletAccountId::get();
+
+		create_pool_with_liquidity(provider, asset_a, asset_b, 1234u32, 10_000u128, 10_000u128);
+		fund_account(alice, asset_a, 1_000u128 + MinBalance::get());
+		assert_ok!(Dex::submit_swap_intent(RuntimeOrigin::signed(alice), asset_a, asset_b, 1_000u128, 1));
+
+		// The batch cannot settle without its pool, and the escrow is gone.
+		Pools::<Test>::remove(AssetPair::<Test>::new(asset_a, asset_b).unwrap());
+		let escrow = get_asset_balance(pallet_account, asset_a);
+		assert_ok!(Fungibles::burn_from(asset_a, &pallet_account, escrow, Precision::Exact, Fortitude::Force));
+		get_events();
+
+		Dex::on_finalize(1);
+
+		assert_eq!(
+			get_events(),
+			vec![Event::<Test>::SwapIntentRefundFailed { who: alice, asset: asset_a, amount: 1_000u128 }]
+		);
+	});
+}
+
+#[test]
+fn swap_intent_requires_pool_with_liquidity() {
+	new_test_ext().execute_with(|| {
+		let sender = 1;
+		let asset_a = 1u32;
+		let asset_b = 2u32;
+
+		create_token(sender, asset_a);
+		create_token(sender, asset_b);
+
+		assert_noop!(
+			Dex::submit_swap_intent(RuntimeOrigin::signed(sender), asset_a, asset_b, 10u128, 1),
+			Error::<Test>::PoolNotFound
+		);
+
+		assert_ok!(NativeBalance::force_set_balance(RuntimeOrigin::root(), sender, 10_000u128));
+		assert_ok!(Dex::create_pool(RuntimeOrigin::signed(sender), asset_a, asset_b, 1234u32));
+
+		assert_noop!(
+			Dex::submit_swap_intent(RuntimeOrigin::signed(sender), asset_a, asset_b, 10u128, 1),
+			Error::<Test>::ReserveIsZero
+		);
+	});
+}
+
+#[test]
+fn long_term_order_sells_over_its_duration() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let asset_a = 1u32;
+		let asset_b = 2u32;
+
+		create_pool_with_liquidity(provider, asset_a, asset_b, 1234u32, 10_000u128, 10_000u128);
+
+		let min_balance = MinBalance::get();
+		fund_account(alice, asset_a, 1_000u128 + min_balance);
+
+		assert_ok!(Dex::submit_long_term_order(RuntimeOrigin::signed(alice), asset_a, asset_b, 1_000u128, 10));
+		assert_eq!(get_asset_balance(alice, asset_a), min_balance);
+		assert!(
+			get_events().contains(
+				&Event::<Test>::LongTermOrderSubmitted {
+					order_id: 0,
+					who: alice,
+					asset_in: asset_a,
+					asset_out: asset_b,
+					sale_rate: 100u128,
+					expiry: 11,
+				}
+			)
+		);
+
+		// Half way through, half of the order has been sold into the pool.
+		run_to_block(6);
+		assert_eq!(Dex::get_reserves(asset_a, asset_b).unwrap().0, 10_500u128);
+
+		// Every executed block is a trade against the pool by the pallet account.
+		let bucket = &Dex::pool_stats(asset_a, asset_b)[0];
+		assert_eq!((bucket.volume_a, bucket.trades), (500u128, 5));
+		let swaps = get_events()
+			.into_iter()
+			.filter(|event| {
+				matches!(
+					event,
+					Event::<Test>::SwapExecuted { sender, asset_in, amount_in: 100u128, .. }
+						if *sender == PalletAccountId::get() && *asset_in == asset_a
+				)
+			})
+			.count();
+		assert_eq!(swaps, 5);
+
+		assert_ok!(Dex::withdraw_long_term_proceeds(RuntimeOrigin::signed(alice), 0));
+		let first_proceeds = get_asset_balance(alice, asset_b);
+		assert!(first_proceeds > 0);
+		assert!(LongTermOrders::<Test>::contains_key(0));
+
+		run_to_block(11);
+		assert_ok!(Dex::withdraw_long_term_proceeds(RuntimeOrigin::signed(alice), 0));
+
+		// The whole order was sold and the order is closed.
+		let (reserve_a, reserve_b) = Dex::get_reserves(asset_a, asset_b).unwrap();
+		assert_eq!(reserve_a, 11_000u128);
+		assert!(!LongTermOrders::<Test>::contains_key(0));
+		assert!(!TwammPools::<Test>::contains_key(AssetPair::<Test>::new(asset_a, asset_b).unwrap()));
+
+		// Everything that left the pool was paid out, up to the accumulator rounding.
+		let proceeds = get_asset_balance(alice, asset_b);
+		assert!(proceeds > first_proceeds);
+		assert!(proceeds <= 10_000u128 - reserve_b);
+		assert!(10_000u128 - reserve_b - proceeds <= 2);
+
+		// Nothing more to withdraw.
+		assert_noop!(
+			Dex::withdraw_long_term_proceeds(RuntimeOrigin::signed(alice), 0),
+			Error::<Test>::LongTermOrderNotFound
+		);
+	});
+}
+
+#[test]
+fn cancel_long_term_order_refunds_unsold_amount() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let bob = 3;
+		let asset_a = 1u32;
+		let asset_b = 2u32;
+
+		create_pool_with_liquidity(provider, asset_a, asset_b, 1234u32, 10_000u128, 10_000u128);
+
+		let min_balance = MinBalance::get();
+		fund_account(alice, asset_a, 1_000u128 + min_balance);
+
+		assert_ok!(Dex::submit_long_term_order(RuntimeOrigin::signed(alice), asset_a, asset_b, 1_000u128, 10));
+
+		run_to_block(4);
+
+		assert_noop!(
+			Dex::cancel_long_term_order(RuntimeOrigin::signed(bob), 0),
+			Error::<Test>::NotOrderOwner
+		);
+		assert_ok!(Dex::cancel_long_term_order(RuntimeOrigin::signed(alice), 0));
+
+		// Three blocks were sold, the remaining seven are refunded.
+		assert_eq!(get_asset_balance(alice, asset_a), min_balance + 700u128);
+		assert_eq!(Dex::get_reserves(asset_a, asset_b).unwrap().0, 10_300u128);
+		assert!(get_asset_balance(alice, asset_b) > 0);
+		assert!(!LongTermOrders::<Test>::contains_key(0));
+
+		// The pool is no longer traded once the order is gone.
+		run_to_block(8);
+		assert_eq!(Dex::get_reserves(asset_a, asset_b).unwrap().0, 10_300u128);
+	});
+}
+
+#[test]
+fn virtual_orders_of_a_bounded_number_of_pools_execute_per_block() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		MaxVirtualOrderPools::set(2);
+
+		let pairs = [(1u32, 2u32), (1u32, 3u32), (2u32, 3u32)];
+		for (index, (asset_in, asset_out)) in pairs.into_iter().enumerate() {
+			create_pool_with_liquidity(provider, asset_in, asset_out, 100u32 + index as u32, 10_000u128, 10_000u128);
+		}
+		fund_account(alice, 1u32, 10_000u128);
+		fund_account(alice, 2u32, 10_000u128);
+		for (asset_in, asset_out) in pairs {
+			assert_ok!(Dex::submit_long_term_order(RuntimeOrigin::signed(alice), asset_in, asset_out, 1_000u128, 10));
+		}
+
+		let executed_at = |block: u64| {
+			pairs
+				.iter()
+				.filter(|(asset_a, asset_b)| {
+					let pair = AssetPair::<Test>::new(*asset_a, *asset_b).unwrap();
+					TwammPools::<Test>::get(&pair).unwrap().last_executed == block
+				})
+				.count()
+		};
+
+		// Two pools execute, the third waits for the next block.
+		run_to_block(2);
+		assert_eq!((executed_at(2), executed_at(1)), (2, 1));
+		assert!(VirtualOrdersCursor::<Test>::exists());
+
+		run_to_block(3);
+		assert_eq!((executed_at(3), executed_at(2)), (1, 2));
+		assert!(!VirtualOrdersCursor::<Test>::exists());
+
+		// Then the first two again.
+		run_to_block(4);
+		assert_eq!((executed_at(4), executed_at(3)), (2, 1));
+	});
+}
+
+#[test]
+fn long_term_orders_and_liquidity_can_leave_a_pool_failing_its_virtual_orders() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let asset_a = 1u32;
+		let asset_b = 2u32;
+		let pair = AssetPair::<Test>::new(asset_a, asset_b).unwrap();
+
+		create_pool_with_liquidity(provider, asset_a, asset_b, 1234u32, 10_000u128, 10_000u128);
+		let min_balance = MinBalance::get();
+		fund_account(alice, asset_a, 1_000u128 + min_balance);
+		assert_ok!(Dex::submit_long_term_order(RuntimeOrigin::signed(alice), asset_a, asset_b, 1_000u128, 10));
+		run_to_block(4);
+
+		// A sale rate nothing backs makes the catch-up of the following blocks overflow.
+		MaxVirtualOrderPools::set(0);
+		TwammPools::<Test>::mutate(&pair, |state| state.as_mut().unwrap().sale_rate_b = u128::MAX);
+		run_to_block(7);
+		get_events();
+
+		// Proceeds are paid up to block 4, the last one executed.
+		assert_ok!(Dex::withdraw_long_term_proceeds(RuntimeOrigin::signed(alice), 0));
+		let proceeds = get_asset_balance(alice, asset_b);
+		assert!(proceeds > 0);
+		assert!(get_events().contains(&Event::<Test>::VirtualOrdersFailed {
+			pair: pair.clone(),
+			error: Error::<Test>::Overflow.into(),
+		}));
+
+		// The seven blocks from block 4 are refunded, including the three never executed.
+		assert_ok!(Dex::cancel_long_term_order(RuntimeOrigin::signed(alice), 0));
+		assert_eq!(get_asset_balance(alice, asset_a), min_balance + 700u128);
+		assert_eq!(get_asset_balance(alice, asset_b), proceeds);
+		assert_eq!(Dex::get_reserves(asset_a, asset_b).unwrap().0, 10_300u128);
+		assert!(!LongTermOrders::<Test>::contains_key(0));
+
+		let lp_balance = get_lp_balance(provider, 1234u32);
+		assert_ok!(Dex::remove_liquidity(RuntimeOrigin::signed(provider), asset_a, asset_b, lp_balance / 2, 1, 1));
+	});
+}
+
+#[test]
+fn long_term_order_duration_is_bounded() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let asset_a = 1u32;
+		let asset_b = 2u32;
+
+		create_pool_with_liquidity(provider, asset_a, asset_b, 1234u32, 10_000u128, 10_000u128);
+		fund_account(alice, asset_a, 1_000u128 + MinBalance::get());
+
+		assert_noop!(
+			Dex::submit_long_term_order(RuntimeOrigin::signed(alice), asset_a, asset_b, 1_000u128, 0),
+			Error::<Test>::InvalidOrderDuration
+		);
+		assert_noop!(
+			Dex::submit_long_term_order(RuntimeOrigin::signed(alice), asset_a, asset_b, 1_000u128, 101),
+			Error::<Test>::InvalidOrderDuration
+		);
+	});
+}
+
+#[test]
+fn flash_swap_is_repaid_after_inner_call() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let asset_a = 1u32;
+		let asset_b = 2u32;
+
+		create_pool_with_liquidity(provider, asset_a, asset_b, 1234u32, 10_000u128, 10_000u128);
+
+		let min_balance = MinBalance::get();
+		fund_account(alice, asset_a, 2_000u128 + min_balance);
+
+		let inner = Box::new(RuntimeCall::System(frame_system::Call::remark { remark: vec![] }));
+		assert_noop!(
+			Dex::flash_swap(RuntimeOrigin::signed(alice), asset_b, asset_a, 1_000u128, 1_000u128, inner.clone()),
+			Error::<Test>::ProvidedMaximumNotSufficientForSwap
+		);
+
+		assert_ok!(Dex::flash_swap(RuntimeOrigin::signed(alice), asset_b, asset_a, 1_000u128, 2_000u128, inner));
+
+		// 10_000 * 1_000 / (9_000 * 0.99), rounded up with one unit of margin for the fee.
+		let amount_in = 1_124u128;
+		assert_eq!(get_asset_balance(alice, asset_b), 1_000u128);
+		assert_eq!(get_asset_balance(alice, asset_a), 2_000u128 + min_balance - amount_in);
+
+		let (reserve_a, reserve_b) = Dex::get_reserves(asset_a, asset_b).unwrap();
+		assert_eq!((reserve_a, reserve_b), (10_000u128 + amount_in, 9_000u128));
+		assert!(reserve_a * reserve_b >= 10_000u128 * 10_000u128);
+
+		assert!(
+			get_events().contains(
+				&Event::<Test>::FlashSwapped {
+					who: alice,
+					asset_out: asset_b,
+					amount_out: 1_000u128,
+					asset_in: asset_a,
+					amount_in,
+				}
+			)
+		);
+	});
+}
+
+#[test]
+fn flash_calls_charge_their_own_work_on_top_of_inner_call() {
+	let inner = Box::new(RuntimeCall::System(frame_system::Call::remark { remark: vec![] }));
+	let inner_weight = inner.get_dispatch_info().weight;
+
+	let flash_swap = RuntimeCall::Dex(crate::Call::flash_swap {
+		asset_out: 2u32,
+		asset_in: 1u32,
+		amount_out: 1u128,
+		amount_in_max: 1u128,
+		call: inner.clone(),
+	});
+	let flash_loan = RuntimeCall::Dex(crate::Call::flash_loan {
+		asset: 1u32,
+		paired_asset: 2u32,
+		amount: 1u128,
+		call: inner,
+	});
+
+	for call in [flash_swap, flash_loan] {
+		assert!(call.get_dispatch_info().weight.any_gt(inner_weight));
+	}
+}
+
+#[test]
+fn flash_swap_locks_the_pool_during_inner_call() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let asset_a = 1u32;
+		let asset_b = 2u32;
+
+		create_pool_with_liquidity(provider, asset_a, asset_b, 1234u32, 10_000u128, 10_000u128);
+		fund_account(alice, asset_a, 2_000u128 + MinBalance::get());
+
+		// Swapping the borrowed asset back into the same pool is not allowed.
+		let inner = Box::new(RuntimeCall::Dex(crate::Call::swap_exact_asset_for_asset {
+			exact_in: asset_b,
+			asset_out: asset_a,
+			amount_in: 500u128,
+			amount_out_min: 1,
+		}));
+		let call = RuntimeCall::Dex(crate::Call::flash_swap {
+			asset_out: asset_b,
+			asset_in: asset_a,
+			amount_out: 1_000u128,
+			amount_in_max: 2_000u128,
+			call: inner,
+		});
+
+		assert_noop!(call.dispatch(RuntimeOrigin::signed(alice)), Error::<Test>::PoolLocked);
+	});
+}
+
+#[test]
+fn unpaid_flash_swap_is_reverted() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let asset_a = 1u32;
+		let asset_b = 2u32;
+
+		create_pool_with_liquidity(provider, asset_a, asset_b, 1234u32, 10_000u128, 10_000u128);
+		assert_ok!(NativeBalance::force_set_balance(RuntimeOrigin::root(), alice, 10_000u128));
+
+		// Alice has nothing to repay with, so the whole call is reverted.
+		let call = RuntimeCall::Dex(crate::Call::flash_swap {
+			asset_out: asset_b,
+			asset_in: asset_a,
+			amount_out: 1_000u128,
+			amount_in_max: 2_000u128,
+			call: Box::new(RuntimeCall::System(frame_system::Call::remark { remark: vec![] })),
+		});
+		assert!(call.dispatch(RuntimeOrigin::signed(alice)).is_err());
+
+		assert_eq!(get_asset_balance(alice, asset_b), 0);
+		assert_eq!(Dex::get_reserves(asset_a, asset_b).unwrap(), (10_000u128, 10_000u128));
+	});
+}
+
+#[test]
+fn flash_loan_fee_accrues_to_pool() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let asset_a = 1u32;
+		let asset_b = 2u32;
+
+		create_pool_with_liquidity(provider, asset_a, asset_b, 1234u32, 10_000u128, 10_000u128);
+
+		let min_balance = MinBalance::get();
+		fund_account(alice, asset_a, 100u128 + min_balance);
+
+		let inner = Box::new(RuntimeCall::System(frame_system::Call::remark { remark: vec![] }));
+		assert_noop!(
+			Dex::flash_loan(RuntimeOrigin::signed(alice), asset_a, asset_b, 10_000u128, inner.clone()),
+			Error::<Test>::InsufficientLiquidity
+		);
+
+		assert_ok!(Dex::flash_loan(RuntimeOrigin::signed(alice), asset_a, asset_b, 1_000u128, inner));
+
+		// A 1% fee on 1_000 is added to the reserve.
+		assert_eq!(get_asset_balance(alice, asset_a), 90u128 + min_balance);
+		assert_eq!(Dex::get_reserves(asset_a, asset_b).unwrap(), (10_010u128, 10_000u128));
+		assert!(
+			get_events().contains(
+				&Event::<Test>::FlashLoaned { who: alice, asset: asset_a, amount: 1_000u128, fee: 10u128 }
+			)
+		);
+	});
+}
+
+#[test]
+fn flash_loan_through_trait_reverts_on_failure() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let asset_a = 1u32;
+		let asset_b = 2u32;
+
+		create_pool_with_liquidity(provider, asset_a, asset_b, 1234u32, 10_000u128, 10_000u128);
+
+		let min_balance = MinBalance::get();
+		fund_account(alice, asset_a, 100u128 + min_balance);
+
+		assert_eq!(<Dex as FlashLoan<_, _, _>>::flash_loan_fee(1_000u128), 10u128);
+
+		// The borrowed amount is available while the loan is used, and the pool is locked.
+		let fee = <Dex as FlashLoan<_, _, _>>::flash_loan(&alice, asset_a, asset_b, 1_000u128, || {
+			assert_eq!(get_asset_balance(alice, asset_a), 1_100u128 + min_balance);
+			assert_eq!(
+				<Dex as FlashLoan<_, _, _>>::flash_loan(&alice, asset_a, asset_b, 1u128, || Ok(())),
+				Err(Error::<Test>::PoolLocked.into())
+			);
+			Ok(())
+		});
+		assert_eq!(fee, Ok(10u128));
+		assert_eq!(get_asset_balance(alice, asset_a), 90u128 + min_balance);
+
+		// A failing loan leaves balances and reserves untouched.
+		assert_noop!(
+			<Dex as FlashLoan<_, _, _>>::flash_loan(&alice, asset_a, asset_b, 1_000u128, || {
+				Err(DispatchError::Other("liquidation failed"))
+			}),
+			DispatchError::Other("liquidation failed")
+		);
+	});
+}
+
+#[test]
+fn swap_trait_quotes_match_swaps() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let asset_a = 1u32;
+		let asset_b = 2u32;
+
+		create_pool_with_liquidity(provider, asset_a, asset_b, 1234u32, 10_000u128, 10_000u128);
+
+		let min_balance = MinBalance::get();
+		fund_account(alice, asset_b, 2_000u128 + min_balance);
+
+		let quote = <Dex as Swap<_, _, _>>::quote_exact_in(asset_b, asset_a, 1_000u128).unwrap();
+		assert_eq!(<Dex as Swap<_, _, _>>::swap_exact_in(&alice, asset_b, asset_a, 1_000u128, 1), Ok(quote));
+		assert_eq!(get_asset_balance(alice, asset_a), quote);
+
+		let quote = <Dex as Swap<_, _, _>>::quote_exact_out(asset_b, asset_a, 100u128).unwrap();
+		assert_eq!(<Dex as Swap<_, _, _>>::swap_exact_out(&alice, asset_b, asset_a, 100u128, 1_000u128), Ok(quote));
+		assert_eq!(get_asset_balance(alice, asset_b), 1_000u128 + min_balance - quote);
+
+		// A failing swap leaves no trace.
+		assert_noop!(
+			<Dex as Swap<_, _, _>>::swap_exact_in(&alice, asset_b, asset_a, 100u128, 1_000u128),
+			Error::<Test>::ProvidedMinimumNotSufficientForSwap
+		);
+
+		assert_eq!(<Dex as Swap<_, _, _>>::quote_exact_in(asset_a, 3u32, 1_000u128), None);
+		assert_eq!(<() as Swap<u64, u32, u128>>::quote_exact_in(asset_b, asset_a, 1_000u128), None);
+		assert_eq!(
+			<() as Swap<u64, u32, u128>>::swap_exact_in(&alice, asset_b, asset_a, 1_000u128, 1),
+			Err(DispatchError::Unavailable)
+		);
+	});
+}
+
+#[test]
+fn wrap_and_unwrap_native() {
+	new_test_ext().execute_with(|| {
+		let alice = 2;
+		let native_asset = 0u32;
+
+		create_token(alice, native_asset);
+		assert_ok!(NativeBalance::force_set_balance(RuntimeOrigin::root(), alice, 10_000u128));
+
+		assert_ok!(Dex::wrap_native(RuntimeOrigin::signed(alice), 1_000u128));
+		assert_eq!(get_asset_balance(alice, native_asset), 1_000u128);
+		assert_eq!(Balances::free_balance(alice), 9_000u128);
+		assert_eq!(Balances::free_balance(Dex::account_id()), 1_000u128);
+
+		assert_ok!(Dex::unwrap_native(RuntimeOrigin::signed(alice), 400u128));
+		assert_eq!(get_asset_balance(alice, native_asset), 600u128);
+		assert_eq!(Balances::free_balance(alice), 9_400u128);
+		assert_eq!(Balances::free_balance(Dex::account_id()), 600u128);
+
+		// Only wrapped tokens can be unwrapped.
+		assert!(Dex::unwrap_native(RuntimeOrigin::signed(alice), 601u128).is_err());
+		assert!(get_events().contains(&Event::<Test>::NativeUnwrapped { who: alice, amount: 400u128 }));
+	});
+}
+
+#[test]
+fn fees_paid_in_asset_are_swapped_to_native_and_refunded() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		let native_asset = 0u32;
+		let asset_b = 2u32;
+
+		// A pool of the wrapped native token against asset b.
+		create_token(provider, native_asset);
+		create_token(provider, asset_b);
+		assert_ok!(NativeBalance::force_set_balance(RuntimeOrigin::root(), provider, 1_000_000u128));
+		assert_ok!(Dex::wrap_native(RuntimeOrigin::signed(provider), 10_000u128));
+		assert_ok!(Fungibles::mint_into(asset_b, &provider, 10_000u128 + MinBalance::get()));
+		assert_ok!(Dex::create_pool(RuntimeOrigin::signed(provider), native_asset, asset_b, 1234u32));
+		assert_ok!(Dex::add_liquidity(RuntimeOrigin::signed(provider), native_asset, asset_b, 10_000u128, 10_000u128, 1, 1, provider));
+
+		let min_balance = MinBalance::get();
+		fund_account(alice, asset_b, 1_000u128 + min_balance);
+
+		let call = RuntimeCall::System(frame_system::Call::remark { remark: vec![] });
+		let info = DispatchInfo::default();
+
+		// A fee of 100 native is withdrawn as asset b at the pool price.
+		let paid = <DexFeeAdapter<Test> as OnChargeAssetTransaction<Test>>::withdraw_fee(
+			&alice, &call, &info, asset_b, 100u128, 0,
+		)
+		.unwrap();
+		let withdrawn = Dex::get_amount_in(100u128, 10_000u128, 10_000u128).unwrap();
+		assert_eq!(paid.peek(), withdrawn);
+		assert_eq!(get_asset_balance(alice, asset_b), 1_000u128 + min_balance - withdrawn);
+
+		let native_issuance = Balances::total_issuance();
+
+		// Only 60 was needed in the end; the rest of asset b is refunded.
+		assert_ok!(<DexFeeAdapter<Test> as OnChargeAssetTransaction<Test>>::correct_and_deposit_fee(
+			&alice,
+			&info,
+			&PostDispatchInfo::default(),
+			60u128,
+			0,
+			paid,
+		));
+		let used = Dex::get_amount_in(60u128, 10_000u128, 10_000u128).unwrap();
+		assert_eq!(get_asset_balance(alice, asset_b), 1_000u128 + min_balance - used);
+
+		// The used asset was sold into the pool and the native bought with it was burnt.
+		let native_out = Dex::get_amount_out(used, 10_000u128, 10_000u128).unwrap();
+		assert_eq!(Dex::get_reserves(native_asset, asset_b).unwrap(), (10_000u128 - native_out, 10_000u128 + used));
+		assert_eq!(Balances::total_issuance(), native_issuance - native_out);
+		assert_eq!(Fungibles::total_issuance(native_asset), 10_000u128 - native_out);
+	});
+}
+
+#[test]
+fn fees_paid_in_wrapped_native_burn_their_backing() {
+	new_test_ext().execute_with(|| {
+		let alice = 2;
+		let native_asset = 0u32;
+
+		create_token(alice, native_asset);
+		assert_ok!(NativeBalance::force_set_balance(RuntimeOrigin::root(), alice, 10_000u128));
+		assert_ok!(Dex::wrap_native(RuntimeOrigin::signed(alice), 1_000u128));
+
+		let call = RuntimeCall::System(frame_system::Call::remark { remark: vec![] });
+		let info = DispatchInfo::default();
+
+		let paid = <DexFeeAdapter<Test> as OnChargeAssetTransaction<Test>>::withdraw_fee(
+			&alice, &call, &info, native_asset, 100u128, 0,
+		)
+		.unwrap();
+		assert_ok!(<DexFeeAdapter<Test> as OnChargeAssetTransaction<Test>>::correct_and_deposit_fee(
+			&alice,
+			&info,
+			&PostDispatchInfo::default(),
+			60u128,
+			0,
+			paid,
+		));
+
+		// 60 was burnt and 40 refunded, and every wrapped token is still backed 1:1.
+		assert_eq!(get_asset_balance(alice, native_asset), 940u128);
+		assert_eq!(get_asset_balance(Dex::account_id(), native_asset), 0u128);
+		assert_eq!(Fungibles::total_issuance(native_asset), 940u128);
+		assert_eq!(Balances::free_balance(Dex::account_id()), Fungibles::total_issuance(native_asset));
+
+		// The backing can all be unwrapped again.
+		assert_ok!(Dex::unwrap_native(RuntimeOrigin::signed(alice), 940u128));
+		assert_eq!(Balances::free_balance(Dex::account_id()), 0u128);
+	});
+}
+
+#[test]
+fn fees_that_cannot_be_sold_are_burnt_after_dispatch() {
+	new_test_ext().execute_with(|| {
+		let alice = 2;
+		let asset_b = 2u32;
+
+		create_token(alice, 0u32);
+		create_token(alice, asset_b);
+		assert_ok!(Fungibles::mint_into(asset_b, &alice, 1_000u128));
+
+		// There is no pool of asset b against the wrapped native token to sell the fee into.
+		let paid =
+			Fungibles::withdraw(asset_b, &alice, 100u128, Precision::Exact, Preservation::Preserve, Fortitude::Polite)
+				.unwrap();
+		assert_ok!(<DexFeeAdapter<Test> as OnChargeAssetTransaction<Test>>::correct_and_deposit_fee(
+			&alice,
+			&DispatchInfo::default(),
+			&PostDispatchInfo::default(),
+			60u128,
+			0,
+			paid,
+		));
+
+		// Nothing was refunded, and what was withdrawn left circulation.
+		assert_eq!(get_asset_balance(alice, asset_b), 900u128);
+		assert_eq!(get_asset_balance(Dex::account_id(), asset_b), 0u128);
+		assert_eq!(Fungibles::total_issuance(asset_b), 900u128);
+	});
+}
+
+/// Creates a pool of assets 1 and 2 with a farm rewarding 10 of asset 3 per block, funded with
+/// 10_000 of it, and gives `staker` 3_000 LP tokens of the pool besides the `provider`'s.
+fn create_pool_with_farm(provider: u64, staker: u64) -> AssetPair<Test> {
+	let lp_asset = 1234u32;
+	let reward_asset = 3u32;
+
+	create_pool_with_liquidity(provider, 1u32, 2u32, lp_asset, 10_000u128, 10_000u128);
+	create_token(provider, reward_asset);
+
+	assert_ok!(Dex::create_farm(RuntimeOrigin::root(), 1u32, 2u32, reward_asset, 10u128));
+
+	let pair = AssetPair::<Test>::new(1u32, 2u32).unwrap();
+	assert_ok!(Fungibles::mint_into(reward_asset, &Dex::farm_account(&pair), 10_000u128));
+	assert_ok!(NativeBalance::force_set_balance(RuntimeOrigin::root(), staker, 10_000u128));
+	assert_ok!(<PoolAssets as Mutate<_>>::transfer(lp_asset, &provider, &staker, 3_000u128, Preservation::Expendable));
+
+	pair
+}
+
+#[test]
+fn farm_rewards_are_shared_pro_rata() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let bob = 3;
+		let reward_asset = 3u32;
+		create_pool_with_farm(provider, bob);
+
+		assert_noop!(
+			Dex::create_farm(RuntimeOrigin::signed(provider), 1u32, 2u32, reward_asset, 10u128),
+			DispatchError::BadOrigin
+		);
+		assert_noop!(
+			Dex::create_farm(RuntimeOrigin::root(), 1u32, 2u32, reward_asset, 10u128),
+			Error::<Test>::FarmExists
+		);
+
+		// The provider farms alone for 10 blocks, then shares with a stake three times as large.
+		assert_ok!(Dex::stake(RuntimeOrigin::signed(provider), 1u32, 2u32, 1_000u128));
+		run_to_block(11);
+		assert_ok!(Dex::stake(RuntimeOrigin::signed(bob), 1u32, 2u32, 3_000u128));
+		run_to_block(21);
+
+		assert_ok!(Dex::claim_rewards(RuntimeOrigin::signed(provider), 1u32, 2u32));
+		assert_ok!(Dex::claim_rewards(RuntimeOrigin::signed(bob), 1u32, 2u32));
+		assert_eq!(get_asset_balance(provider, reward_asset), 100u128 + 25u128);
+		assert_eq!(get_asset_balance(bob, reward_asset), 75u128);
+
+		// Unstaking keeps rewards accruing up to the unstake, and stops them afterwards.
+		run_to_block(25);
+		assert_ok!(Dex::unstake(RuntimeOrigin::signed(bob), 1u32, 2u32, 3_000u128));
+		assert_eq!(get_lp_balance(bob, 1234u32), 3_000u128);
+		run_to_block(30);
+		assert_ok!(Dex::claim_rewards(RuntimeOrigin::signed(bob), 1u32, 2u32));
+		assert_eq!(get_asset_balance(bob, reward_asset), 75u128 + 30u128);
+
+		assert!(
+			get_events().contains(
+				&Event::<Test>::RewardsClaimed {
+					who: bob,
+					pair: AssetPair::<Test>::new(1u32, 2u32).unwrap(),
+					reward_asset,
+					amount: 30u128,
+				}
+			)
+		);
+	});
+}
+
+#[test]
+fn emergency_withdraw_forfeits_rewards() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let bob = 3;
+		let reward_asset = 3u32;
+		let pair = create_pool_with_farm(provider, bob);
+
+		assert_ok!(Dex::stake(RuntimeOrigin::signed(bob), 1u32, 2u32, 3_000u128));
+		run_to_block(6);
+
+		assert_noop!(
+			Dex::unstake(RuntimeOrigin::signed(bob), 1u32, 2u32, 3_001u128),
+			Error::<Test>::InsufficientStake
+		);
+
+		assert_ok!(Dex::emergency_withdraw(RuntimeOrigin::signed(bob), 1u32, 2u32));
+		assert_eq!(get_lp_balance(bob, 1234u32), 3_000u128);
+		assert!(!FarmStakes::<Test>::contains_key(&pair, bob));
+
+		assert_noop!(
+			Dex::claim_rewards(RuntimeOrigin::signed(bob), 1u32, 2u32),
+			Error::<Test>::StakeNotFound
+		);
+		assert_eq!(get_asset_balance(bob, reward_asset), 0);
+	});
+}
+
+#[test]
+fn emergency_withdraw_leaves_earned_rewards_with_the_other_stakers() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let bob = 3;
+		let carol = 4;
+		let reward_asset = 3u32;
+		create_pool_with_farm(provider, bob);
+		assert_ok!(NativeBalance::force_set_balance(RuntimeOrigin::root(), carol, 10_000u128));
+		assert_ok!(<PoolAssets as Mutate<_>>::transfer(1234u32, &provider, &carol, 3_000u128, Preservation::Expendable));
+
+		// Bob holds a quarter of the shares until carol withdraws, without the farm being updated
+		// in between.
+		assert_ok!(Dex::stake(RuntimeOrigin::signed(bob), 1u32, 2u32, 1_000u128));
+		assert_ok!(Dex::stake(RuntimeOrigin::signed(carol), 1u32, 2u32, 3_000u128));
+		run_to_block(11);
+		assert_ok!(Dex::emergency_withdraw(RuntimeOrigin::signed(carol), 1u32, 2u32));
+		run_to_block(21);
+
+		// A quarter of the first 100 rewards and all of the next 100, not 200 over bob's shares
+		// alone.
+		assert_ok!(Dex::claim_rewards(RuntimeOrigin::signed(bob), 1u32, 2u32));
+		assert_eq!(get_asset_balance(bob, reward_asset), 25u128 + 100u128);
+		assert_eq!(get_asset_balance(carol, reward_asset), 0);
+	});
+}
+
+#[test]
+fn locked_stakes_earn_boosted_rewards_until_expiry() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let bob = 3;
+		let reward_asset = 3u32;
+		let pair = create_pool_with_farm(provider, bob);
+
+		assert_noop!(
+			Dex::stake_locked(RuntimeOrigin::signed(bob), 1u32, 2u32, 3_000u128, 0),
+			Error::<Test>::InvalidLockDuration
+		);
+		assert_noop!(
+			Dex::stake_locked(RuntimeOrigin::signed(bob), 1u32, 2u32, 3_000u128, 101),
+			Error::<Test>::InvalidLockDuration
+		);
+
+		// Locking for the maximum duration doubles bob's reward shares.
+		assert_ok!(Dex::stake(RuntimeOrigin::signed(provider), 1u32, 2u32, 1_000u128));
+		assert_ok!(Dex::stake_locked(RuntimeOrigin::signed(bob), 1u32, 2u32, 3_000u128, 100));
+		assert_eq!(
+			Dex::locked_positions(&bob),
+			vec![(0, LockedPosition { pair: pair.clone(), amount: 3_000u128, boost: 3_000u128, expiry: 101 })]
+		);
+
+		run_to_block(11);
+		assert_ok!(Dex::claim_rewards(RuntimeOrigin::signed(provider), 1u32, 2u32));
+		assert_ok!(Dex::claim_rewards(RuntimeOrigin::signed(bob), 1u32, 2u32));
+		assert_eq!(get_asset_balance(provider, reward_asset), 14u128);
+		assert_eq!(get_asset_balance(bob, reward_asset), 85u128);
+
+		assert_noop!(
+			Dex::unstake(RuntimeOrigin::signed(bob), 1u32, 2u32, 1u128),
+			Error::<Test>::InsufficientStake
+		);
+		assert_noop!(
+			Dex::emergency_withdraw(RuntimeOrigin::signed(bob), 1u32, 2u32),
+			Error::<Test>::InsufficientStake
+		);
+
+		// The position unlocks by itself at expiry, and bob's shares fall back to the amount staked.
+		run_to_block(101);
+		assert!(Dex::locked_positions(&bob).is_empty());
+		assert_eq!(FarmStakes::<Test>::get(&pair, bob).unwrap().shares, 3_000u128);
+		assert!(get_events().contains(&Event::<Test>::LpUnlocked {
+			who: bob,
+			pair,
+			lock_id: 0,
+			amount: 3_000u128,
+		}));
+
+		assert_ok!(Dex::unstake(RuntimeOrigin::signed(bob), 1u32, 2u32, 3_000u128));
+		assert_eq!(get_lp_balance(bob, 1234u32), 3_000u128);
+	});
+}
+
+#[test]
+fn lock_expiries_past_the_block_limit_spread_to_the_next_block() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let bob = 3;
+		let pair = create_pool_with_farm(provider, bob);
+
+		// One more position than a block can unlock, all expiring at block 11.
+		for _ in 0..17 {
+			assert_ok!(Dex::stake_locked(RuntimeOrigin::signed(bob), 1u32, 2u32, 100u128, 10));
+		}
+		assert_eq!(crate::LockExpiries::<Test>::get(11).len(), 16);
+		assert_eq!(crate::LockExpiries::<Test>::get(12).into_inner(), vec![(bob, 16)]);
+
+		// Positions cannot be unlocked by their owner before expiry.
+		assert_noop!(Dex::unlock(RuntimeOrigin::signed(bob), 16), Error::<Test>::LockNotExpired);
+
+		run_to_block(11);
+		assert_eq!(Dex::locked_positions(&bob).len(), 1);
+		assert_eq!(FarmStakes::<Test>::get(&pair, bob).unwrap().locked, 100u128);
+
+		// The overflow unlocks a block later, or as soon as its owner asks once expired.
+		assert_ok!(Dex::unlock(RuntimeOrigin::signed(bob), 16));
+		assert!(Dex::locked_positions(&bob).is_empty());
+		assert_eq!(FarmStakes::<Test>::get(&pair, bob).unwrap().locked, 0u128);
+		assert_noop!(Dex::unlock(RuntimeOrigin::signed(bob), 16), Error::<Test>::StakeNotFound);
+
+		run_to_block(12);
+		assert!(crate::LockExpiries::<Test>::get(12).is_empty());
+		assert_eq!(FarmStakes::<Test>::get(&pair, bob).unwrap().shares, 1_700u128);
+	});
+}
+
+#[test]
+fn treasury_provides_protocol_owned_liquidity() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let treasury = TreasuryAccount::get();
+		let lp_asset = 1234u32;
+		create_pool_with_liquidity(provider, 1u32, 2u32, lp_asset, 10_000u128, 10_000u128);
+
+		assert_ok!(NativeBalance::force_set_balance(RuntimeOrigin::root(), treasury, 10_000u128));
+		assert_ok!(Fungibles::mint_into(1u32, &treasury, 1_000u128));
+		assert_ok!(Fungibles::mint_into(2u32, &treasury, 1_000u128));
+
+		assert_noop!(
+			Dex::treasury_add_liquidity(RuntimeOrigin::signed(provider), 1u32, 2u32, 500u128, 500u128, 0, 0),
+			DispatchError::BadOrigin
+		);
+
+		assert_ok!(Dex::treasury_add_liquidity(RuntimeOrigin::root(), 1u32, 2u32, 500u128, 500u128, 0, 0));
+		let minted = get_lp_balance(treasury, lp_asset);
+		assert!(minted > 0);
+		assert_eq!(get_asset_balance(treasury, 1u32), 500u128);
+
+		let pair = AssetPair::<Test>::new(1u32, 2u32).unwrap();
+		assert!(get_events().contains(&Event::<Test>::TreasuryLiquidityAdded {
+			pair: pair.clone(),
+			amount_a: 500u128,
+			amount_b: 500u128,
+			minted,
+		}));
+
+		assert_ok!(Dex::treasury_remove_liquidity(RuntimeOrigin::root(), 1u32, 2u32, minted, 0, 0));
+		assert_eq!(get_lp_balance(treasury, lp_asset), 0);
+		assert!(get_events().iter().any(|event| matches!(
+			event,
+			Event::<Test>::TreasuryLiquidityRemoved { pair: p, burned, .. } if *p == pair && *burned == minted
+		)));
+	});
+}
+
+#[test]
+fn pools_of_unlisted_assets_are_unverified() {
+	new_test_ext().execute_with(|| {
+		let creator = 1;
+		create_token(creator, 1u32);
+		create_token(creator, 2u32);
+		create_token(creator, 3u32);
+		assert_ok!(NativeBalance::force_set_balance(RuntimeOrigin::root(), creator, 1_000_000u128));
+
+		assert_noop!(Dex::allow_asset(RuntimeOrigin::signed(creator), 1u32), DispatchError::BadOrigin);
+		assert_ok!(Dex::allow_asset(RuntimeOrigin::root(), 1u32));
+		assert_ok!(Dex::allow_asset(RuntimeOrigin::root(), 2u32));
+
+		assert_ok!(Dex::create_pool(RuntimeOrigin::signed(creator), 1u32, 2u32, 100u32));
+		assert_ok!(Dex::create_pool(RuntimeOrigin::signed(creator), 1u32, 3u32, 101u32));
+
+		let listed = AssetPair::<Test>::new(1u32, 2u32).unwrap();
+		let unlisted = AssetPair::<Test>::new(1u32, 3u32).unwrap();
+		assert_eq!(Pools::<Test>::get(&listed).unwrap().status, PoolStatus::Verified);
+		assert_eq!(Pools::<Test>::get(&unlisted).unwrap().status, PoolStatus::Unverified);
+
+		assert_ok!(Dex::set_pool_status(RuntimeOrigin::root(), 1u32, 3u32, PoolStatus::Verified));
+		assert_eq!(Pools::<Test>::get(&unlisted).unwrap().status, PoolStatus::Verified);
+
+		// Without unlisted pools, only pools between listed assets can be created.
+		AllowUnlistedPools::set(false);
+		assert_ok!(Dex::disallow_asset(RuntimeOrigin::root(), 2u32));
+		assert_noop!(
+			Dex::create_pool(RuntimeOrigin::signed(creator), 2u32, 3u32, 102u32),
+			Error::<Test>::AssetNotAllowed
+		);
+	});
+}
+
+#[test]
+fn pool_deposit_is_released_on_destroy_or_slashed() {
+	new_test_ext().execute_with(|| {
+		let creator = 1;
+		let alice = 2;
+		let fee = 100u128;
+		create_token(creator, 1u32);
+		create_token(creator, 2u32);
+		create_token(creator, 3u32);
+		assert_ok!(NativeBalance::force_set_balance(RuntimeOrigin::root(), creator, 1_000u128));
+
+		assert_ok!(Dex::create_pool(RuntimeOrigin::signed(creator), 1u32, 2u32, 100u32));
+		assert_ok!(Dex::create_pool(RuntimeOrigin::signed(creator), 1u32, 3u32, 101u32));
+		assert_eq!(NativeBalance::balance_on_hold(&HoldReason::PoolCreation.into(), &creator), 2 * fee);
+
+		// Only the creator can destroy the pool, which releases the deposit.
+		assert_noop!(
+			Dex::destroy_pool(RuntimeOrigin::signed(alice), 1u32, 2u32),
+			Error::<Test>::NotPoolCreator
+		);
+		assert_ok!(Dex::destroy_pool(RuntimeOrigin::signed(creator), 1u32, 2u32));
+		assert!(Pools::<Test>::get(AssetPair::<Test>::new(1u32, 2u32).unwrap()).is_none());
+		assert!(!Dex::lp_asset_exists(&100u32));
+		assert_eq!(get_native_balance(creator), 1_000u128 - fee);
+
+		// A slashed deposit is burnt.
+		let issuance = Balances::total_issuance();
+		assert_noop!(
+			Dex::slash_pool_deposit(RuntimeOrigin::signed(alice), 1u32, 3u32),
+			DispatchError::BadOrigin
+		);
+		assert_ok!(Dex::slash_pool_deposit(RuntimeOrigin::root(), 1u32, 3u32));
+		assert_eq!(NativeBalance::balance_on_hold(&HoldReason::PoolCreation.into(), &creator), 0);
+		assert_eq!(Balances::total_issuance(), issuance - fee);
+		assert_noop!(
+			Dex::slash_pool_deposit(RuntimeOrigin::root(), 1u32, 3u32),
+			Error::<Test>::DepositNotFound
+		);
+
+		// Without a deposit, the pool has no creator left and anyone can destroy it.
+		assert_ok!(Dex::destroy_pool(RuntimeOrigin::signed(alice), 1u32, 3u32));
+		assert!(!Dex::lp_asset_exists(&101u32));
+		assert!(get_events().contains(&Event::<Test>::PoolDestroyed {
+			pair: AssetPair::<Test>::new(1u32, 3u32).unwrap(),
+			deposit_released: 0,
+		}));
+
+		// The pool and its LP token can be created again.
+		assert_ok!(Dex::create_pool(RuntimeOrigin::signed(creator), 1u32, 3u32, 101u32));
+	});
+}
+
+#[test]
+fn pools_with_a_farm_or_long_term_orders_cannot_be_destroyed() {
+	new_test_ext().execute_with(|| {
+		let creator = 1;
+		create_token(creator, 1u32);
+		create_token(creator, 2u32);
+		create_token(creator, 3u32);
+		assert_ok!(NativeBalance::force_set_balance(RuntimeOrigin::root(), creator, 1_000u128));
+		assert_ok!(Dex::create_pool(RuntimeOrigin::signed(creator), 1u32, 2u32, 100u32));
+		assert_ok!(Dex::create_farm(RuntimeOrigin::root(), 1u32, 2u32, 3u32, 10u128));
+
+		assert_noop!(
+			Dex::destroy_pool(RuntimeOrigin::signed(creator), 1u32, 2u32),
+			Error::<Test>::PoolInUse
+		);
+
+		// Long-term orders awaiting withdrawal refer to the pool too.
+		let pair = AssetPair::<Test>::new(1u32, 3u32).unwrap();
+		assert_ok!(Dex::create_pool(RuntimeOrigin::signed(creator), 1u32, 3u32, 101u32));
+		crate::OrderExpiries::<Test>::insert(&pair, 10, crate::OrderExpiry::<Test> { orders: 1, ..Default::default() });
+		assert_noop!(
+			Dex::destroy_pool(RuntimeOrigin::signed(creator), 1u32, 3u32),
+			Error::<Test>::PoolInUse
+		);
+	});
+}
+
+#[test]
+fn pools_from_before_deposits_can_be_destroyed() {
+	new_test_ext().execute_with(|| {
+		let creator = 1;
+		let alice = 2;
+		create_token(creator, 1u32);
+		create_token(creator, 2u32);
+		assert_ok!(NativeBalance::force_set_balance(RuntimeOrigin::root(), creator, 1_000u128));
+		assert_ok!(Dex::create_pool(RuntimeOrigin::signed(creator), 1u32, 2u32, 100u32));
+
+		// A pool created before deposits were taken has no deposit entry.
+		let pair = AssetPair::<Test>::new(1u32, 2u32).unwrap();
+		crate::PoolDeposits::<Test>::remove(&pair);
+
+		assert_ok!(Dex::destroy_pool(RuntimeOrigin::signed(alice), 1u32, 2u32));
+		assert!(Pools::<Test>::get(&pair).is_none());
+		assert!(!Dex::lp_asset_exists(&100u32));
+	});
+}
+
+/// Inserts a pool in the storage layout of version 0.
+fn insert_v0_pool(asset_a: u32, asset_b: u32, lp_asset: u32, reserve_a: u128, reserve_b: u128) -> AssetPair<Test> {
+	let pair = AssetPair::<Test>::new(asset_a, asset_b).unwrap();
+	migrations::v0::Pools::<Test>::insert(&pair, migrations::v0::PoolInfo { lp_asset, reserve_a, reserve_b });
+	pair
+}
+
+#[test]
+fn migration_to_v1_sets_pool_status() {
+	new_test_ext().execute_with(|| {
+		StorageVersion::new(0).put::<Dex>();
+		AllowedAssets::<Test>::insert(1u32, ());
+		AllowedAssets::<Test>::insert(2u32, ());
+
+		let listed = insert_v0_pool(1u32, 2u32, 100u32, 1_000u128, 2_000u128);
+		let unlisted = insert_v0_pool(1u32, 3u32, 101u32, 3_000u128, 4_000u128);
+
+		// Existing pools are verified whether or not their assets are on the allow-list.
+		migrations::v1::MigrateToV1::<Test>::on_runtime_upgrade();
+
+		assert_eq!(Dex::on_chain_storage_version(), 1);
+		assert_eq!(
+			Pools::<Test>::get(&listed),
+			Some(PoolInfo { lp_asset: 100u32, reserve_a: 1_000u128, reserve_b: 2_000u128, status: PoolStatus::Verified })
+		);
+		assert_eq!(
+			Pools::<Test>::get(&unlisted),
+			Some(PoolInfo { lp_asset: 101u32, reserve_a: 3_000u128, reserve_b: 4_000u128, status: PoolStatus::Verified })
+		);
+
+		// Running the migration again leaves the migrated pools alone.
+		assert_ok!(Dex::set_pool_status(RuntimeOrigin::root(), 1u32, 3u32, PoolStatus::Unverified));
+		migrations::v1::MigrateToV1::<Test>::on_runtime_upgrade();
+		assert_eq!(Pools::<Test>::get(&unlisted).unwrap().status, PoolStatus::Unverified);
+	});
+}
+
+#[test]
+fn migration_to_v2_moves_lp_assets_to_the_pool_assets() {
+	new_test_ext().execute_with(|| {
+		StorageVersion::new(1).put::<Dex>();
+		let provider = 1;
+		let alice = 2;
+		let pallet_account = PalletAccountId::get();
+
+		// Up to version 1, LP tokens were minted in the assets backing `Fungibles`.
+		let pair = AssetPair::<Test>::new(1u32, 2u32).unwrap();
+		Pools::<Test>::insert(
+			&pair,
+			PoolInfo { lp_asset: 100u32, reserve_a: 1_000u128, reserve_b: 1_000u128, status: PoolStatus::Verified },
+		);
+		assert_ok!(Fungibles::force_create(RuntimeOrigin::root(), 100u32.into(), pallet_account, true, 1u32.into()));
+		assert_ok!(Fungibles::mint_into(100u32, &pallet_account, 10u128));
+		assert_ok!(Fungibles::mint_into(100u32, &provider, 700u128));
+		assert_ok!(Fungibles::mint_into(100u32, &alice, 300u128));
+		for asset in [1u32, 2u32] {
+			create_token(provider, asset);
+			assert_ok!(Fungibles::mint_into(asset, &pallet_account, 1_000u128));
+		}
+		assert_ok!(NativeBalance::force_set_balance(RuntimeOrigin::root(), alice, 1_000u128));
+
+		migrations::v2::MigrateToV2::<Test, Assets, ConstU32<3>>::on_runtime_upgrade();
+
+		assert_eq!(Dex::on_chain_storage_version(), 2);
+		assert!(!Fungibles::asset_exists(100u32));
+		assert_eq!(PoolAssets::total_issuance(100u32), 1_010u128);
+		assert_eq!(get_lp_balance(pallet_account, 100u32), 10u128);
+		assert_eq!(get_lp_balance(provider, 100u32), 700u128);
+		assert_eq!(get_lp_balance(alice, 100u32), 300u128);
+		assert_ok!(Dex::do_try_state());
+
+		// The pool keeps working with its moved LP token.
+		assert_ok!(Dex::remove_liquidity(RuntimeOrigin::signed(alice), 1u32, 2u32, 300u128, 1u128, 1u128));
+		assert_eq!(get_lp_balance(alice, 100u32), 0);
+
+		// Running the migration again leaves the moved LP token alone.
+		migrations::v2::MigrateToV2::<Test, Assets, ConstU32<3>>::on_runtime_upgrade();
+		assert_eq!(get_lp_balance(provider, 100u32), 700u128);
+	});
+}
+
+#[test]
+fn migration_to_v2_is_skipped_past_its_holder_bound() {
+	new_test_ext().execute_with(|| {
+		StorageVersion::new(1).put::<Dex>();
+		let pallet_account = PalletAccountId::get();
+
+		let pair = AssetPair::<Test>::new(1u32, 2u32).unwrap();
+		Pools::<Test>::insert(
+			&pair,
+			PoolInfo { lp_asset: 100u32, reserve_a: 1_000u128, reserve_b: 1_000u128, status: PoolStatus::Verified },
+		);
+		assert_ok!(Fungibles::force_create(RuntimeOrigin::root(), 100u32.into(), pallet_account, true, 1u32.into()));
+		for who in [pallet_account, 1, 2, 3] {
+			assert_ok!(Fungibles::mint_into(100u32, &who, 10u128));
+		}
+
+		// Four holders are more than the upgrade block is weighed for.
+		migrations::v2::MigrateToV2::<Test, Assets, ConstU32<3>>::on_runtime_upgrade();
+
+		assert_eq!(Dex::on_chain_storage_version(), 1);
+		assert_eq!(Fungibles::total_issuance(100u32), 40u128);
+		assert!(!PoolAssets::asset_exists(100u32));
+	});
+}
+
+#[test]
+fn try_state_checks_pool_accounting() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		create_pool_with_liquidity(provider, 1u32, 2u32, 1234u32, 10_000u128, 10_000u128);
+		fund_account(alice, 1u32, 1_000u128);
+		assert_ok!(Dex::swap_exact_asset_for_asset(RuntimeOrigin::signed(alice), 1u32, 2u32, 500u128, 1u128));
+		assert_ok!(Dex::do_try_state());
+
+		// Reserves the pallet account does not hold are caught.
+		let pair = AssetPair::<Test>::new(1u32, 2u32).unwrap();
+		Pools::<Test>::mutate(&pair, |pool_info| pool_info.as_mut().unwrap().reserve_a += 1_000_000u128);
+		assert_eq!(
+			Dex::do_try_state(),
+			Err(DispatchError::Other("pool reserves exceed the pallet account balance"))
+		);
+	});
+}
+
+#[test]
+fn swaps_are_recorded_in_rolling_pool_stats() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+		create_pool_with_liquidity(provider, 1u32, 2u32, 1234u32, 10_000u128, 10_000u128);
+		fund_account(alice, 1u32, 10_000u128);
+		fund_account(alice, 2u32, 10_000u128);
+		let pair = AssetPair::<Test>::new(1u32, 2u32).unwrap();
+
+		// Liquidity changes are not trades.
+		assert!(Dex::pool_stats(1u32, 2u32).is_empty());
+
+		let out_b = Dex::get_amount_out(500u128, 10_000u128, 10_000u128).unwrap();
+		assert_ok!(Dex::swap_exact_asset_for_asset(RuntimeOrigin::signed(alice), 1u32, 2u32, 500u128, 1u128));
+		assert_eq!(
+			Dex::pool_stats(1u32, 2u32),
+			vec![StatsBucket {
+				start: 0,
+				volume_a: 500u128,
+				volume_b: out_b,
+				fees_a: 5u128,
+				fees_b: 0,
+				trades: 1,
+				last_price: FixedU128::from_rational(out_b, 500u128),
+			}]
+		);
+
+		// Trades within the same window add up, in both directions.
+		run_to_block(5);
+		let (reserve_a, reserve_b) = Dex::get_reserves(1u32, 2u32).unwrap();
+		let out_a = Dex::get_amount_out(100u128, reserve_b, reserve_a).unwrap();
+		assert_ok!(Dex::swap_exact_asset_for_asset(RuntimeOrigin::signed(alice), 2u32, 1u32, 100u128, 1u128));
+		let bucket = &Dex::pool_stats(2u32, 1u32)[0];
+		assert_eq!((bucket.volume_a, bucket.volume_b), (500u128 + out_a, out_b + 100u128));
+		assert_eq!((bucket.fees_a, bucket.fees_b, bucket.trades), (5u128, 1u128, 2));
+
+		// Only the most recent windows are kept.
+		for block in [10, 20, 30] {
+			run_to_block(block);
+			assert_ok!(Dex::swap_exact_asset_for_asset(RuntimeOrigin::signed(alice), 1u32, 2u32, 100u128, 1u128));
+		}
+		let starts: Vec<u64> = PoolStats::<Test>::get(&pair).iter().map(|bucket| bucket.start).collect();
+		assert_eq!(starts, vec![10, 20, 30]);
+	});
+}
+
+#[test]
+fn best_route_finds_cheapest_path() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+
+		// The direct pool is much shallower than the detour through asset 3.
+		create_pool_with_liquidity(provider, 1u32, 2u32, 100u32, 1_000u128, 1_000u128);
+		create_pool_with_liquidity(provider, 1u32, 3u32, 101u32, 10_000u128, 10_000u128);
+		create_pool_with_liquidity(provider, 3u32, 2u32, 102u32, 10_000u128, 10_000u128);
+
+		let amounts = Dex::quote_path_exact_in(&[1u32, 3u32, 2u32], 500u128).unwrap();
+		assert!(amounts[2] > Dex::quote_exact_in(1u32, 2u32, 500u128).unwrap());
+		assert_eq!(
+			Dex::best_route_exact_in(1u32, 2u32, 500u128),
+			Some(Route { path: vec![1u32, 3u32, 2u32], amounts })
+		);
+
+		let amounts = Dex::quote_path_exact_out(&[1u32, 3u32, 2u32], 300u128).unwrap();
+		assert_eq!(amounts[2], 300u128);
+		assert_eq!(
+			Dex::best_route_exact_out(1u32, 2u32, 300u128),
+			Some(Route { path: vec![1u32, 3u32, 2u32], amounts })
+		);
+
+		// Small trades barely move the price, so the direct pool wins by saving a fee.
+		let route = Dex::best_route_exact_in(1u32, 2u32, 10u128).unwrap();
+		assert_eq!(route.path, vec![1u32, 2u32]);
+
+		// Assets without a pool cannot be reached.
+		create_token(provider, 9u32);
+		assert_eq!(Dex::best_route_exact_in(1u32, 9u32, 500u128), None);
+		assert_eq!(Dex::best_route_exact_in(1u32, 1u32, 500u128), None);
+	});
+}
+
+#[test]
+fn best_route_is_bounded_by_max_hops() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+
+		create_pool_with_liquidity(provider, 1u32, 2u32, 100u32, 10_000u128, 10_000u128);
+		create_pool_with_liquidity(provider, 2u32, 3u32, 101u32, 10_000u128, 10_000u128);
+		create_pool_with_liquidity(provider, 3u32, 4u32, 102u32, 10_000u128, 10_000u128);
+		create_pool_with_liquidity(provider, 4u32, 5u32, 103u32, 10_000u128, 10_000u128);
+
+		let route = Dex::best_route_exact_in(1u32, 4u32, 500u128).unwrap();
+		assert_eq!(route.path, vec![1u32, 2u32, 3u32, 4u32]);
+		assert_eq!(Dex::best_route_exact_in(1u32, 5u32, 500u128), None);
+		assert_noop!(
+			Dex::quote_path_exact_in(&[1u32, 2u32, 3u32, 4u32, 5u32], 500u128),
+			Error::<Test>::InvalidPath
+		);
+	});
+}
+
+#[test]
+fn routes_are_bounded_in_densely_connected_pools() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+
+		// Every pair of five assets has a pool, so ten paths of at most three pools join two of
+		// them.
+		let mut lp_asset = 100u32;
+		for asset_a in 1u32..=5 {
+			for asset_b in asset_a + 1..=5 {
+				create_pool_with_liquidity(provider, asset_a, asset_b, lp_asset, 10_000u128, 10_000u128);
+				lp_asset += 1;
+			}
+		}
+
+		let routes = Dex::routes(1u32, 2u32);
+		assert_eq!(routes.len(), 8);
+		for (index, route) in routes.iter().enumerate() {
+			assert!(Dex::quote_path_exact_in(route, 500u128).is_ok());
+			assert!(!routes[index + 1..].contains(route));
+		}
+		assert!(Dex::best_route_exact_in(1u32, 2u32, 500u128).is_some());
+	});
+}
+
+#[test]
+fn swap_along_path_moves_only_end_assets() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+
+		create_pool_with_liquidity(provider, 1u32, 3u32, 100u32, 10_000u128, 10_000u128);
+		create_pool_with_liquidity(provider, 3u32, 2u32, 101u32, 10_000u128, 10_000u128);
+		fund_account(alice, 1u32, 10_000u128);
+
+		let path = vec![1u32, 3u32, 2u32];
+		let amounts = Dex::quote_path_exact_in(&path, 500u128).unwrap();
+		assert_noop!(
+			Dex::swap_exact_in_along_path(RuntimeOrigin::signed(alice), bounded_path(&path), 500u128, amounts[2] + 1),
+			Error::<Test>::ProvidedMinimumNotSufficientForSwap
+		);
+		assert_ok!(Dex::swap_exact_in_along_path(RuntimeOrigin::signed(alice), bounded_path(&path), 500u128, amounts[2]));
+
+		assert_eq!(get_asset_balance(alice, 1u32), 10_000u128 - 500u128);
+		assert_eq!(get_asset_balance(alice, 3u32), 0);
+		assert_eq!(get_asset_balance(alice, 2u32), amounts[2]);
+		assert_eq!(Dex::get_reserves(1u32, 3u32).unwrap(), (10_000u128 + 500u128, 10_000u128 - amounts[1]));
+		assert_eq!(Dex::get_reserves(2u32, 3u32).unwrap(), (10_000u128 - amounts[2], 10_000u128 + amounts[1]));
+
+		let amounts = Dex::quote_path_exact_out(&path, 100u128).unwrap();
+		assert_noop!(
+			Dex::swap_exact_out_along_path(RuntimeOrigin::signed(alice), bounded_path(&path), 100u128, amounts[0] - 1),
+			Error::<Test>::ProvidedMaximumNotSufficientForSwap
+		);
+		assert_ok!(Dex::swap_exact_out_along_path(RuntimeOrigin::signed(alice), bounded_path(&path), 100u128, amounts[0]));
+		assert_eq!(get_asset_balance(alice, 1u32), 10_000u128 - 500u128 - amounts[0]);
+		assert_eq!(get_asset_balance(alice, 3u32), 0);
+
+		for path in [&[1u32][..], &[1u32, 3u32, 1u32]] {
+			assert_noop!(
+				Dex::swap_exact_in_along_path(RuntimeOrigin::signed(alice), bounded_path(path), 500u128, 1u128),
+				Error::<Test>::InvalidPath
+			);
+		}
+		// Paths through more than `MaxHops` pools do not even decode.
+		assert!(PathOf::<Test>::try_from(vec![1u32, 2u32, 3u32, 4u32, 5u32]).is_err());
+	});
+}
+
+#[test]
+fn swap_along_paths_splits_trade() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+
+		create_pool_with_liquidity(provider, 1u32, 2u32, 100u32, 10_000u128, 10_000u128);
+		create_pool_with_liquidity(provider, 1u32, 3u32, 101u32, 10_000u128, 10_000u128);
+		create_pool_with_liquidity(provider, 3u32, 2u32, 102u32, 10_000u128, 10_000u128);
+		fund_account(alice, 1u32, 10_000u128);
+
+		let routes = vec![(vec![1u32, 2u32], 1_000u128), (vec![1u32, 3u32, 2u32], 1_000u128)];
+		let amount_out: u128 = Dex::quote_split_exact_in(&routes).unwrap().iter().sum();
+		// Spreading the trade over both paths beats sending it all through the direct pool.
+		assert!(amount_out > Dex::quote_exact_in(1u32, 2u32, 2_000u128).unwrap());
+
+		assert_noop!(
+			Dex::swap_exact_in_along_paths(RuntimeOrigin::signed(alice), bounded_routes(&routes), amount_out + 1),
+			Error::<Test>::ProvidedMinimumNotSufficientForSwap
+		);
+		assert_ok!(Dex::swap_exact_in_along_paths(RuntimeOrigin::signed(alice), bounded_routes(&routes), amount_out));
+
+		assert_eq!(get_asset_balance(alice, 1u32), 10_000u128 - 2_000u128);
+		assert_eq!(get_asset_balance(alice, 2u32), amount_out);
+		assert!(
+			get_events().contains(
+				&Event::<Test>::SplitSwapExecuted {
+					sender: alice,
+					asset_in: 1u32,
+					amount_in: 2_000u128,
+					asset_out: 2u32,
+					amount_out,
+					routes: 2,
+				}
+			)
+		);
+	});
+}
+
+#[test]
+fn swap_along_paths_rejects_invalid_splits() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+
+		create_pool_with_liquidity(provider, 1u32, 2u32, 100u32, 10_000u128, 10_000u128);
+		create_pool_with_liquidity(provider, 1u32, 3u32, 101u32, 10_000u128, 10_000u128);
+		fund_account(alice, 1u32, 10_000u128);
+
+		let direct = (vec![1u32, 2u32], 100u128);
+		for routes in [vec![], vec![direct.clone(), (vec![1u32, 3u32], 100u128)]] {
+			assert_noop!(
+				Dex::swap_exact_in_along_paths(RuntimeOrigin::signed(alice), bounded_routes(&routes), 1u128),
+				Error::<Test>::InvalidSplit
+			);
+		}
+		// Splits across more than `MaxSplitRoutes` paths do not even decode.
+		let routes = vec![(bounded_path(&direct.0), direct.1); 4];
+		assert!(SplitRoutesOf::<Test>::try_from(routes).is_err());
+		// Quotes still reject them.
+		assert_noop!(
+			Dex::quote_split_exact_in(&[direct.clone(), direct.clone(), direct.clone(), direct.clone()]),
+			Error::<Test>::InvalidSplit
+		);
+
+		// Routes through the same pool trade at the reserves left by the previous ones.
+		let amounts = Dex::quote_split_exact_in(&[direct.clone(), direct.clone()]).unwrap();
+		assert!(amounts[1] < amounts[0]);
+		assert_ok!(Dex::swap_exact_in_along_paths(
+			RuntimeOrigin::signed(alice),
+			bounded_routes(&[direct.clone(), direct]),
+			amounts[0] + amounts[1]
+		));
+	});
+}
+
+#[test]
+fn greedy_split_needs_parts() {
+	let quote = |allocation: &[u128]| Ok::<_, ()>(Some(allocation.iter().sum()));
+
+	assert_eq!(split_greedily(2, 1_000u128, 0, 2, quote), Ok(None));
+	assert_eq!(split_greedily(2, 1_000u128, 1, 2, quote), Ok(Some((vec![1_000u128, 0], 1_000u128))));
+}
+
+#[test]
+fn greedy_split_is_executable_and_beats_the_best_route() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+
+		create_pool_with_liquidity(provider, 1u32, 2u32, 100u32, 10_000u128, 10_000u128);
+		create_pool_with_liquidity(provider, 1u32, 3u32, 101u32, 10_000u128, 10_000u128);
+		create_pool_with_liquidity(provider, 3u32, 2u32, 102u32, 10_000u128, 10_000u128);
+		create_pool_with_liquidity(provider, 1u32, 4u32, 103u32, 10_000u128, 10_000u128);
+		create_pool_with_liquidity(provider, 4u32, 2u32, 104u32, 10_000u128, 10_000u128);
+		fund_account(alice, 1u32, 10_000u128);
+
+		let paths = Dex::routes(1u32, 2u32);
+		let routes_of = |allocation: &[u128]| -> Vec<(Vec<u32>, u128)> {
+			paths
+				.iter()
+				.cloned()
+				.zip(allocation.iter().copied())
+				.filter(|(_, amount)| *amount > 0)
+				.collect()
+		};
+		let suggest = |max_routes| {
+			split_greedily(paths.len(), 4_000u128, 20, max_routes, |allocation| {
+				let amounts = Dex::quote_split_exact_in(&routes_of(allocation)).ok();
+				Ok::<_, ()>(amounts.map(|amounts| amounts.iter().sum()))
+			})
+			.unwrap()
+			.unwrap()
+		};
+
+		// A single route takes the whole trade.
+		let (allocation, _) = suggest(1);
+		assert_eq!(allocation.iter().filter(|amount| **amount > 0).count(), 1);
+
+		let (allocation, amount_out) = suggest(3);
+		let routes = routes_of(&allocation);
+		assert_eq!(routes.len(), 3);
+		assert_eq!(routes.iter().map(|(_, amount)| amount).sum::<u128>(), 4_000u128);
+		let best = Dex::best_route_exact_in(1u32, 2u32, 4_000u128).unwrap();
+		assert!(amount_out > best.amounts[best.amounts.len() - 1]);
+
+		assert_ok!(Dex::swap_exact_in_along_paths(
+			RuntimeOrigin::signed(alice),
+			bounded_routes(&routes),
+			amount_out
+		));
+		assert_eq!(get_asset_balance(alice, 1u32), 10_000u128 - 4_000u128);
+		assert_eq!(get_asset_balance(alice, 2u32), amount_out);
+	});
+}
+
+#[test]
+fn simulate_swap_reports_breakdown_without_trading() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+
+		create_pool_with_liquidity(provider, 1u32, 2u32, 1234u32, 10_000u128, 20_000u128);
+		fund_account(alice, 1u32, 10_000u128);
+		get_events();
+
+		let amount_out = Dex::get_amount_out(1_000u128, 10_000u128, 20_000u128).unwrap();
+		let simulation = Dex::simulate_swap_exact_in(alice, 1u32, 2u32, 1_000u128, 1u128).unwrap();
+		let effective_price = FixedU128::from_rational(amount_out, 1_000u128);
+		assert_eq!(
+			simulation,
+			SwapSimulation {
+				amount_in: 1_000u128,
+				amount_out,
+				fee: 10u128,
+				spot_price: FixedU128::saturating_from_integer(2u32),
+				effective_price,
+				price_impact: Perbill::from_rational(
+					FixedU128::saturating_from_integer(2u32).into_inner() - effective_price.into_inner(),
+					FixedU128::saturating_from_integer(2u32).into_inner()
+				),
+				reserve_a: 11_000u128,
+				reserve_b: 20_000u128 - amount_out,
+			}
+		);
+
+		// Nothing happened.
+		assert_eq!(Dex::get_reserves(1u32, 2u32).unwrap(), (10_000u128, 20_000u128));
+		assert_eq!(get_asset_balance(alice, 1u32), 10_000u128);
+		assert!(get_events().is_empty());
+
+		// The real swap does what was simulated.
+		assert_ok!(Dex::swap_exact_asset_for_asset(RuntimeOrigin::signed(alice), 1u32, 2u32, 1_000u128, 1u128));
+		assert_eq!(get_asset_balance(alice, 2u32), simulation.amount_out);
+		assert_eq!(Dex::get_reserves(1u32, 2u32).unwrap(), (simulation.reserve_a, simulation.reserve_b));
+
+		// Buying exactly, from asset b's side.
+		let amount_in = Dex::get_amount_in(500u128, 20_000u128 - amount_out, 11_000u128).unwrap();
+		let simulation = Dex::simulate_swap_exact_out(alice, 2u32, 1u32, 500u128, amount_in).unwrap();
+		assert_eq!((simulation.amount_in, simulation.amount_out), (amount_in, 500u128));
+		assert_eq!((simulation.reserve_a, simulation.reserve_b), (10_500u128, 20_000u128 - amount_out + amount_in));
+	});
+}
+
+#[test]
+fn simulate_swap_fails_like_swap() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+
+		create_pool_with_liquidity(provider, 1u32, 2u32, 1234u32, 10_000u128, 10_000u128);
+		fund_account(alice, 1u32, 100u128);
+
+		assert_eq!(
+			Dex::simulate_swap_exact_in(alice, 1u32, 2u32, 50u128, 50u128),
+			Err(Error::<Test>::ProvidedMinimumNotSufficientForSwap.into())
+		);
+		assert_eq!(
+			Dex::simulate_swap_exact_out(alice, 1u32, 2u32, 50u128, 1u128),
+			Err(Error::<Test>::ProvidedMaximumNotSufficientForSwap.into())
+		);
+		assert_eq!(
+			Dex::simulate_swap_exact_in(alice, 1u32, 2u32, 1_000u128, 1u128),
+			Err(DispatchError::Token(TokenError::FundsUnavailable))
+		);
+		assert_eq!(
+			Dex::simulate_swap_exact_in(alice, 1u32, 3u32, 50u128, 1u128),
+			Err(Error::<Test>::PoolNotFound.into())
+		);
+	});
+}
+
+#[test]
+fn lp_positions_value_full_withdrawal() {
+	new_test_ext().execute_with(|| {
+		let provider = 1;
+		let alice = 2;
+
+		create_pool_with_liquidity(provider, 1u32, 2u32, 1234u32, 10_000u128, 40_000u128);
+		create_pool_with_liquidity(provider, 1u32, 3u32, 1235u32, 10_000u128, 10_000u128);
+		assert!(Dex::lp_positions(&alice).is_empty());
+
+		let positions = Dex::lp_positions(&provider);
+		assert_eq!(positions.len(), 2);
+		let position = positions.into_iter().find(|position| position.lp_asset == 1234u32).unwrap();
+
+		// The pool minted 20_000 LP tokens, `MinLiquidity` of them locked away for good.
+		let lp_balance = get_lp_balance(provider, 1234u32);
+		let fee = Dex::pool_fee(lp_balance);
+		assert_eq!(
+			position,
+			LpPosition {
+				asset_a: 1u32,
+				asset_b: 2u32,
+				lp_asset: 1234u32,
+				lp_balance,
+				staked: 0,
+				share: Perbill::from_rational(lp_balance, 20_000u128),
+				amount_a: (lp_balance - fee) * 10_000u128 / 20_000u128,
+				amount_b: (lp_balance - fee) * 40_000u128 / 20_000u128,
+				fee,
+				fee_a: fee * 10_000u128 / 20_000u128,
+				fee_b: fee * 40_000u128 / 20_000u128,
+			}
+		);
+
+		// Withdrawing pays out exactly the valuation.
+		let (balance_a, balance_b) = (get_asset_balance(provider, 1u32), get_asset_balance(provider, 2u32));
+		assert_ok!(Dex::remove_liquidity(RuntimeOrigin::signed(provider), 1u32, 2u32, lp_balance, 1, 1));
+		assert_eq!(get_asset_balance(provider, 1u32) - balance_a, position.amount_a);
+		assert_eq!(get_asset_balance(provider, 2u32) - balance_b, position.amount_b);
+		assert_eq!(Dex::lp_positions(&provider).len(), 1);
+	});
+}
+
+mod enum_asset_ids {
+	use crate::{mock_enum::*, AssetPair, Pools};
+	use frame_support::assert_ok;
+	use frame_support::traits::fungible::Mutate as FungibleMutate;
+	use frame_support::traits::fungibles::{Inspect, Mutate};
+
+	#[test]
+	fn pools_of_unordered_asset_ids_can_be_created_funded_and_traded() {
+		new_test_ext().execute_with(|| {
+			let provider = 1;
+			let trader = 2;
+			let (token_1, token_2, lp_asset) = (AssetId::Token(1), AssetId::Token(2), AssetId::Lp(1));
+
+			for token in [token_1, token_2] {
+				assert_ok!(Assets::force_create(RuntimeOrigin::root(), token, provider, false, 1));
+				assert_ok!(Assets::mint_into(token, &provider, 100_000));
+			}
+			assert_ok!(Balances::mint_into(&provider, 1_000_000));
+			assert_ok!(Balances::mint_into(&trader, 1_000));
+			assert_ok!(Assets::mint_into(token_2, &trader, 2_000));
+
+			// Assets given in reverse are put in the order of `NativeFirst`.
+			assert_ok!(Dex::create_pool(RuntimeOrigin::signed(provider), token_2, token_1, lp_asset));
+			let pair = AssetPair::<Test>::new(token_2, token_1).unwrap();
+			assert_eq!((pair.asset_a, pair.asset_b), (token_1, token_2));
+			assert_eq!(Pools::<Test>::get(&pair).unwrap().lp_asset, lp_asset);
+
+			assert_ok!(Dex::add_liquidity(
+				RuntimeOrigin::signed(provider),
+				token_1,
+				token_2,
+				10_000,
+				40_000,
+				10_000,
+				40_000,
+				provider
+			));
+			let pool_info = Pools::<Test>::get(&pair).unwrap();
+			assert_eq!((pool_info.reserve_a, pool_info.reserve_b), (10_000, 40_000));
+			assert!(PoolAssets::balance(lp_asset, provider) > 0);
+
+			assert_ok!(Dex::swap_exact_asset_for_asset(
+				RuntimeOrigin::signed(trader),
+				token_2,
+				token_1,
+				1_000,
+				1
+			));
+			let received = Assets::balance(token_1, trader);
+			assert!(received > 0);
+			assert_eq!(Assets::balance(token_2, trader), 1_000);
+
+			let pool_info = Pools::<Test>::get(&pair).unwrap();
+			assert_eq!((pool_info.reserve_a, pool_info.reserve_b), (10_000 - received, 41_000));
+
+			// The swap is found under the runtime's ordering, in the order the assets are asked for.
+			assert_eq!(Dex::block_swaps(token_2, token_1), vec![(1_000, received)]);
+			assert_eq!(Dex::block_swaps(token_1, token_2), vec![(received, 1_000)]);
+		});
+	}
+}
@@ -0,0 +1,117 @@
+//! Interfaces through which other pallets can use the DEX.
+
+use frame_support::pallet_prelude::{DispatchError, DispatchResult};
+
+/// Flash loans of a single asset out of a pool's reserve.
+pub trait FlashLoan<AccountId, AssetId, Balance> {
+	/// Fee charged for borrowing `amount`.
+	fn flash_loan_fee(amount: Balance) -> Balance;
+
+	/// Lend `amount` of `asset` out of the reserve of the pool of `asset` and `paired_asset` to
+	/// `borrower`, run `use_loan`, then collect `amount` plus the fee back from `borrower`.
+	///
+	/// Returns the fee paid. If `use_loan` or the repayment fails, nothing is changed.
+	fn flash_loan<F: FnOnce() -> DispatchResult>(
+		borrower: &AccountId,
+		asset: AssetId,
+		paired_asset: AssetId,
+		amount: Balance,
+		use_loan: F,
+	) -> Result<Balance, DispatchError>;
+}
+
+/// Swaps between two assets through the DEX pools.
+///
+/// Quotes are computed against the pools' current reserves. Swaps either complete or change
+/// nothing.
+pub trait Swap<AccountId, AssetId, Balance> {
+	/// Amount of `asset_out` received for exactly `amount_in` of `asset_in`, if it can be swapped.
+	fn quote_exact_in(asset_in: AssetId, asset_out: AssetId, amount_in: Balance) -> Option<Balance>;
+
+	/// Amount of `asset_in` needed to receive exactly `amount_out` of `asset_out`, if it can be
+	/// swapped.
+	fn quote_exact_out(asset_in: AssetId, asset_out: AssetId, amount_out: Balance)
+		-> Option<Balance>;
+
+	/// Sell exactly `amount_in` of `asset_in` from `who` for at least `amount_out_min` of
+	/// `asset_out`. Returns the amount received.
+	fn swap_exact_in(
+		who: &AccountId,
+		asset_in: AssetId,
+		asset_out: AssetId,
+		amount_in: Balance,
+		amount_out_min: Balance,
+	) -> Result<Balance, DispatchError>;
+
+	/// Buy exactly `amount_out` of `asset_out` for `who` with at most `amount_in_max` of
+	/// `asset_in`. Returns the amount paid.
+	fn swap_exact_out(
+		who: &AccountId,
+		asset_in: AssetId,
+		asset_out: AssetId,
+		amount_out: Balance,
+		amount_in_max: Balance,
+	) -> Result<Balance, DispatchError>;
+}
+
+/// A `Swap` without any pools, for runtimes and tests that do not need swaps.
+impl<AccountId, AssetId, Balance> Swap<AccountId, AssetId, Balance> for () {
+	fn quote_exact_in(_: AssetId, _: AssetId, _: Balance) -> Option<Balance> {
+		None
+	}
+
+	fn quote_exact_out(_: AssetId, _: AssetId, _: Balance) -> Option<Balance> {
+		None
+	}
+
+	fn swap_exact_in(
+		_: &AccountId,
+		_: AssetId,
+		_: AssetId,
+		_: Balance,
+		_: Balance,
+	) -> Result<Balance, DispatchError> {
+		Err(DispatchError::Unavailable)
+	}
+
+	fn swap_exact_out(
+		_: &AccountId,
+		_: AssetId,
+		_: AssetId,
+		_: Balance,
+		_: Balance,
+	) -> Result<Balance, DispatchError> {
+		Err(DispatchError::Unavailable)
+	}
+}
+
+/// Orders the two assets of a pair, for the runtime's asset id type.
+pub trait PairOrdering<AssetId> {
+	/// Whether `asset_a` comes first in its pair with `asset_b`. Must be a strict total order.
+	fn precedes(asset_a: &AssetId, asset_b: &AssetId) -> bool;
+}
+
+/// Orders pairs by the asset ids' own `Ord`.
+pub struct OrdPairOrdering;
+
+impl<AssetId: Ord> PairOrdering<AssetId> for OrdPairOrdering {
+	fn precedes(asset_a: &AssetId, asset_b: &AssetId) -> bool {
+		asset_a < asset_b
+	}
+}
+
+/// Generates the id of the LP token of a new pool, for the runtime's asset id type.
+pub trait LpAssetIdGenerator<AssetId> {
+	/// Id of the LP token of the pool of `asset_a` and `asset_b`, given in pair order, from the id
+	/// `requested` by the pool's creator.
+	fn lp_asset_id(asset_a: &AssetId, asset_b: &AssetId, requested: AssetId) -> AssetId;
+}
+
+/// Uses the LP token id requested by the pool's creator, for asset ids they can pick freely.
+pub struct RequestedLpAssetId;
+
+impl<AssetId> LpAssetIdGenerator<AssetId> for RequestedLpAssetId {
+	fn lp_asset_id(_asset_a: &AssetId, _asset_b: &AssetId, requested: AssetId) -> AssetId {
+		requested
+	}
+}
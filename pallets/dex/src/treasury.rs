@@ -0,0 +1,64 @@
+//! Protocol-owned liquidity.
+//!
+//! `Config::TreasuryOrigin` can add and remove liquidity with the assets of
+//! `Config::TreasuryAccount`, which also holds the resulting LP tokens.
+
+use crate::*;
+use frame_support::pallet_prelude::*;
+
+impl<T: Config> Pallet<T> {
+	/// Add liquidity from the treasury account, minting the LP tokens to it.
+	pub fn do_treasury_add_liquidity(
+		asset_a: AssetIdOf<T>,
+		asset_b: AssetIdOf<T>,
+		amount_a_desired: BalanceOf<T>,
+		amount_b_desired: BalanceOf<T>,
+		amount_a_min: BalanceOf<T>,
+		amount_b_min: BalanceOf<T>,
+	) -> DispatchResult {
+		let treasury = T::TreasuryAccount::get();
+		let pair = AssetPair::<T>::new(asset_a.clone(), asset_b.clone())?;
+		let (amount_a, amount_b, minted) = Self::do_add_liquidity(
+			treasury.clone(),
+			asset_a,
+			asset_b,
+			amount_a_desired,
+			amount_b_desired,
+			amount_a_min,
+			amount_b_min,
+			treasury,
+		)?;
+
+		Self::deposit_event(Event::TreasuryLiquidityAdded { pair, amount_a, amount_b, minted });
+
+		Ok(())
+	}
+
+	/// Burn LP tokens of the treasury account, returning the pool's assets to it.
+	pub fn do_treasury_remove_liquidity(
+		asset_a: AssetIdOf<T>,
+		asset_b: AssetIdOf<T>,
+		lp_token_burn: BalanceOf<T>,
+		amount_a_min_receive: BalanceOf<T>,
+		amount_b_min_receive: BalanceOf<T>,
+	) -> DispatchResult {
+		let pair = AssetPair::<T>::new(asset_a.clone(), asset_b.clone())?;
+		let (amount_a, amount_b) = Self::do_remove_liquidity(
+			T::TreasuryAccount::get(),
+			asset_a,
+			asset_b,
+			lp_token_burn,
+			amount_a_min_receive,
+			amount_b_min_receive,
+		)?;
+
+		Self::deposit_event(Event::TreasuryLiquidityRemoved {
+			pair,
+			amount_a,
+			amount_b,
+			burned: lp_token_burn,
+		});
+
+		Ok(())
+	}
+}
@@ -0,0 +1,59 @@
+//! Invariants of the pool accounting, checked by `try-runtime` after every block.
+
+use crate::*;
+use frame_support::{ensure, sp_std::vec::Vec, traits::fungibles::Inspect};
+use sp_runtime::traits::{CheckedAdd, Zero};
+
+impl<T: Config> Pallet<T> {
+	/// Check that every pool is backed by the pallet account and that its LP token is consistent
+	/// with its reserves.
+	pub fn do_try_state() -> Result<(), DispatchError> {
+		let pallet_account = Self::account_id();
+		let mut reserves = Vec::<(AssetIdOf<T>, BalanceOf<T>)>::new();
+
+		for (pair, pool_info) in Pools::<T>::iter() {
+			ensure!(
+				Self::lp_asset_exists(&pool_info.lp_asset),
+				DispatchError::Other("pool LP asset does not exist")
+			);
+
+			let reserves_empty = pool_info.reserve_a.is_zero() && pool_info.reserve_b.is_zero();
+			let lp_supply = Self::lp_total_issuance(pool_info.lp_asset.clone());
+			ensure!(
+				lp_supply.is_zero() == reserves_empty,
+				DispatchError::Other("pool LP supply and reserves disagree on being empty")
+			);
+			ensure!(
+				lp_supply.is_zero() ||
+					T::PoolAssets::balance(pool_info.lp_asset.clone(), &pallet_account) >= T::MinLiquidity::get(),
+				DispatchError::Other("pool minimum liquidity is not locked in the pallet account")
+			);
+
+			for (asset, reserve) in
+				[(pair.asset_a, pool_info.reserve_a), (pair.asset_b, pool_info.reserve_b)]
+			{
+				let index = match reserves.iter().position(|(known, _)| *known == asset) {
+					Some(index) => index,
+					None => {
+						reserves.push((asset, Zero::zero()));
+						reserves.len() - 1
+					},
+				};
+				let total = &mut reserves[index].1;
+				*total = total
+					.checked_add(&reserve)
+					.ok_or(DispatchError::Other("pool reserves overflow"))?;
+			}
+		}
+
+		// Assets can be in several pools, so their balance must cover all their reserves.
+		for (asset, total) in reserves {
+			ensure!(
+				T::Fungibles::balance(asset, &pallet_account) >= total,
+				DispatchError::Other("pool reserves exceed the pallet account balance")
+			);
+		}
+
+		Ok(())
+	}
+}
@@ -0,0 +1,470 @@
+//! Time-weighted average market maker (TWAMM) long-term orders.
+//!
+//! A long-term order sells a total amount evenly over a number of blocks. Orders are not traded
+//! one by one: the combined sale rates of both directions of a pair are executed lazily as
+//! "virtual orders", whenever the pool is touched and in `on_initialize`. Each executed interval
+//! is cleared like a batch of swap intents, so both directions trade at one price and only the
+//! imbalance moves the pool.
+//!
+//! Proceeds are tracked with an earnings-per-sale-rate accumulator for each direction, so an
+//! order's proceeds are `sale_rate * (accumulator_now - accumulator_at_last_withdrawal)`.
+
+use crate::*;
+use frame_support::{
+	pallet_prelude::*,
+	sp_std::vec::Vec,
+	storage::with_storage_layer,
+	traits::{
+		fungibles::Mutate,
+		tokens::Preservation::{Expendable, Preserve},
+	},
+};
+use frame_system::pallet_prelude::BlockNumberFor;
+use sp_runtime::{
+	traits::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, One, Saturating, Zero},
+	FixedPointNumber, FixedU128, Rounding, SaturatedConversion,
+};
+
+/// Identifier of a long-term order.
+pub type LongTermOrderId = u32;
+
+/// Per-pair state of the long-term orders selling into a pool.
+#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebugNoBound, MaxEncodedLen, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+pub struct TwammPool<T: Config> {
+	/// Combined per-block sale rate of the orders selling asset a.
+	pub sale_rate_a: BalanceOf<T>,
+	/// Combined per-block sale rate of the orders selling asset b.
+	pub sale_rate_b: BalanceOf<T>,
+	/// Asset b earned so far per unit of sale rate by orders selling asset a.
+	pub earnings_per_rate_a: FixedU128,
+	/// Asset a earned so far per unit of sale rate by orders selling asset b.
+	pub earnings_per_rate_b: FixedU128,
+	/// Last block whose share of the virtual orders has been executed.
+	pub last_executed: BlockNumberFor<T>,
+}
+
+/// Sale rates that stop at a block, with the accumulators snapshotted when that block executed.
+#[derive(
+	Clone, Encode, Decode, Eq, PartialEq, RuntimeDebugNoBound, DefaultNoBound, MaxEncodedLen, TypeInfo,
+)]
+#[scale_info(skip_type_params(T))]
+pub struct OrderExpiry<T: Config> {
+	/// Sale rate of the orders selling asset a that end at this block.
+	pub sale_rate_a: BalanceOf<T>,
+	/// Sale rate of the orders selling asset b that end at this block.
+	pub sale_rate_b: BalanceOf<T>,
+	/// Value of `earnings_per_rate_a` once this block has executed.
+	pub earnings_per_rate_a: FixedU128,
+	/// Value of `earnings_per_rate_b` once this block has executed.
+	pub earnings_per_rate_b: FixedU128,
+	/// Number of orders still referring to this entry.
+	pub orders: u32,
+}
+
+/// A long-term order selling `sale_rate` of one asset of `pair` every block until `expiry`.
+#[derive(Clone, Encode, Decode, Eq, PartialEq, RuntimeDebugNoBound, MaxEncodedLen, TypeInfo)]
+#[scale_info(skip_type_params(T))]
+pub struct LongTermOrder<T: Config> {
+	pub owner: T::AccountId,
+	pub pair: AssetPair<T>,
+	/// Whether the order sells asset a of the pair, otherwise it sells asset b.
+	pub sells_a: bool,
+	pub sale_rate: BalanceOf<T>,
+	/// Last block in which the order sells.
+	pub expiry: BlockNumberFor<T>,
+	/// Accumulator value up to which proceeds have been paid out.
+	pub earnings_per_rate: FixedU128,
+}
+
+impl<T: Config> LongTermOrder<T> {
+	/// The assets sold and bought by the order.
+	pub fn assets(&self) -> (AssetIdOf<T>, AssetIdOf<T>) {
+		if self.sells_a {
+			(self.pair.asset_a.clone(), self.pair.asset_b.clone())
+		} else {
+			(self.pair.asset_b.clone(), self.pair.asset_a.clone())
+		}
+	}
+}
+
+impl<T: Config> Pallet<T> {
+	/// Escrow `amount_in` and start selling it evenly over the next `number_of_blocks` blocks.
+	///
+	/// Any remainder of `amount_in` that does not divide evenly into a per-block sale rate is left
+	/// with the sender.
+	pub fn do_submit_long_term_order(
+		who: T::AccountId,
+		asset_in: AssetIdOf<T>,
+		asset_out: AssetIdOf<T>,
+		amount_in: BalanceOf<T>,
+		number_of_blocks: BlockNumberFor<T>,
+	) -> Result<LongTermOrderId, DispatchError> {
+		ensure!(
+			!number_of_blocks.is_zero() && number_of_blocks <= T::MaxOrderBlocks::get(),
+			Error::<T>::InvalidOrderDuration
+		);
+
+		let pair = AssetPair::<T>::new(asset_in.clone(), asset_out.clone())?;
+		let pool_info = Pools::<T>::get(&pair).ok_or(Error::<T>::PoolNotFound)?;
+		ensure!(
+			!pool_info.reserve_a.is_zero() && !pool_info.reserve_b.is_zero(),
+			Error::<T>::ReserveIsZero
+		);
+
+		Self::ensure_pool_unlocked(&pair)?;
+		Self::execute_virtual_orders(&pair)?;
+
+		let blocks = Self::blocks_to_balance(number_of_blocks);
+		let sale_rate = amount_in.checked_div(&blocks).ok_or(Error::<T>::Overflow)?;
+		ensure!(!sale_rate.is_zero(), Error::<T>::InvalidAmount);
+		let amount = sale_rate.checked_mul(&blocks).ok_or(Error::<T>::Overflow)?;
+
+		T::Fungibles::transfer(asset_in.clone(), &who, &Self::account_id(), amount, Preserve)?;
+
+		let now = frame_system::Pallet::<T>::block_number();
+		let expiry = now.saturating_add(number_of_blocks);
+		let sells_a = asset_in == pair.asset_a;
+
+		let mut state = TwammPools::<T>::get(&pair).unwrap_or(TwammPool {
+			sale_rate_a: Zero::zero(),
+			sale_rate_b: Zero::zero(),
+			earnings_per_rate_a: Zero::zero(),
+			earnings_per_rate_b: Zero::zero(),
+			last_executed: now,
+		});
+
+		let earnings_per_rate = if sells_a {
+			state.sale_rate_a = state.sale_rate_a.checked_add(&sale_rate).ok_or(Error::<T>::Overflow)?;
+			state.earnings_per_rate_a
+		} else {
+			state.sale_rate_b = state.sale_rate_b.checked_add(&sale_rate).ok_or(Error::<T>::Overflow)?;
+			state.earnings_per_rate_b
+		};
+
+		OrderExpiries::<T>::try_mutate(&pair, expiry, |entry| -> DispatchResult {
+			if sells_a {
+				entry.sale_rate_a = entry.sale_rate_a.checked_add(&sale_rate).ok_or(Error::<T>::Overflow)?;
+			} else {
+				entry.sale_rate_b = entry.sale_rate_b.checked_add(&sale_rate).ok_or(Error::<T>::Overflow)?;
+			}
+			entry.orders = entry.orders.saturating_add(1);
+			Ok(())
+		})?;
+
+		let order_id = NextLongTermOrderId::<T>::get();
+		NextLongTermOrderId::<T>::put(order_id.checked_add(1).ok_or(Error::<T>::Overflow)?);
+
+		LongTermOrders::<T>::insert(
+			order_id,
+			LongTermOrder {
+				owner: who.clone(),
+				pair: pair.clone(),
+				sells_a,
+				sale_rate,
+				expiry,
+				earnings_per_rate,
+			},
+		);
+		TwammPools::<T>::insert(&pair, state);
+
+		Self::deposit_event(Event::LongTermOrderSubmitted {
+			order_id,
+			who,
+			asset_in,
+			asset_out,
+			sale_rate,
+			expiry,
+		});
+
+		Ok(order_id)
+	}
+
+	/// Pay out the proceeds an order has earned so far. An order that has run to its expiry is
+	/// removed once its proceeds are withdrawn.
+	pub fn do_withdraw_long_term_proceeds(
+		who: T::AccountId,
+		order_id: LongTermOrderId,
+	) -> Result<BalanceOf<T>, DispatchError> {
+		let mut order = LongTermOrders::<T>::get(order_id).ok_or(Error::<T>::LongTermOrderNotFound)?;
+		ensure!(order.owner == who, Error::<T>::NotOrderOwner);
+
+		// Proceeds are paid up to the last block the virtual orders executed, so that they can be
+		// withdrawn even while the pool fails to execute them.
+		Self::ensure_pool_unlocked(&order.pair)?;
+		Self::try_execute_virtual_orders(&order.pair);
+		let executed = Self::virtual_orders_executed_until(&order.pair);
+
+		let earnings_per_rate = Self::order_earnings_per_rate(&order, executed);
+		let proceeds = Self::order_proceeds(&order, earnings_per_rate)?;
+		let (_, asset_out) = order.assets();
+
+		let completed = executed >= order.expiry;
+		if completed {
+			LongTermOrders::<T>::remove(order_id);
+			Self::release_order_expiry(&order.pair, order.expiry);
+		} else {
+			order.earnings_per_rate = earnings_per_rate;
+			LongTermOrders::<T>::insert(order_id, order);
+		}
+
+		if !proceeds.is_zero() {
+			T::Fungibles::transfer(asset_out.clone(), &Self::account_id(), &who, proceeds, Expendable)?;
+		}
+
+		Self::deposit_event(Event::LongTermProceedsWithdrawn {
+			order_id,
+			who,
+			asset_out,
+			amount: proceeds,
+			completed,
+		});
+
+		Ok(proceeds)
+	}
+
+	/// Stop an order before its expiry, refunding the part not sold yet along with the proceeds.
+	pub fn do_cancel_long_term_order(
+		who: T::AccountId,
+		order_id: LongTermOrderId,
+	) -> DispatchResult {
+		let order = LongTermOrders::<T>::get(order_id).ok_or(Error::<T>::LongTermOrderNotFound)?;
+		ensure!(order.owner == who, Error::<T>::NotOrderOwner);
+
+		let now = frame_system::Pallet::<T>::block_number();
+		ensure!(now < order.expiry, Error::<T>::LongTermOrderExpired);
+
+		// The blocks the virtual orders did not execute, if the pool fails to execute them, are
+		// refunded as unsold.
+		Self::ensure_pool_unlocked(&order.pair)?;
+		Self::try_execute_virtual_orders(&order.pair);
+		let executed = Self::virtual_orders_executed_until(&order.pair);
+
+		let proceeds = Self::order_proceeds(&order, Self::order_earnings_per_rate(&order, executed))?;
+		let remaining_blocks = Self::blocks_to_balance(order.expiry.saturating_sub(executed));
+		let unsold = order.sale_rate.checked_mul(&remaining_blocks).ok_or(Error::<T>::Overflow)?;
+
+		let mut state = TwammPools::<T>::get(&order.pair).ok_or(Error::<T>::LongTermOrderNotFound)?;
+		let mut expiry = OrderExpiries::<T>::get(&order.pair, order.expiry);
+		if order.sells_a {
+			state.sale_rate_a = state.sale_rate_a.checked_sub(&order.sale_rate).ok_or(Error::<T>::Overflow)?;
+			expiry.sale_rate_a = expiry.sale_rate_a.checked_sub(&order.sale_rate).ok_or(Error::<T>::Overflow)?;
+		} else {
+			state.sale_rate_b = state.sale_rate_b.checked_sub(&order.sale_rate).ok_or(Error::<T>::Overflow)?;
+			expiry.sale_rate_b = expiry.sale_rate_b.checked_sub(&order.sale_rate).ok_or(Error::<T>::Overflow)?;
+		}
+		OrderExpiries::<T>::insert(&order.pair, order.expiry, expiry);
+		Self::release_order_expiry(&order.pair, order.expiry);
+		Self::store_twamm_pool(&order.pair, state);
+		LongTermOrders::<T>::remove(order_id);
+
+		let (asset_in, asset_out) = order.assets();
+		T::Fungibles::transfer(asset_in, &Self::account_id(), &who, unsold, Expendable)?;
+		if !proceeds.is_zero() {
+			T::Fungibles::transfer(asset_out, &Self::account_id(), &who, proceeds, Expendable)?;
+		}
+
+		Self::deposit_event(Event::LongTermOrderCancelled {
+			order_id,
+			who,
+			unsold,
+			proceeds,
+		});
+
+		Ok(())
+	}
+
+	/// Execute the virtual orders of up to `MaxVirtualOrderPools` pairs with long-term orders, up to
+	/// the current block, resuming after the pair the previous block stopped at.
+	pub(crate) fn execute_all_virtual_orders() -> Weight {
+		let limit = T::MaxVirtualOrderPools::get() as usize;
+		let pairs = match VirtualOrdersCursor::<T>::take() {
+			Some(cursor) =>
+				TwammPools::<T>::iter_keys_from(TwammPools::<T>::hashed_key_for(cursor)),
+			None => TwammPools::<T>::iter_keys(),
+		};
+		// One pair more than the limit tells whether any are left for the next block.
+		let pairs: Vec<AssetPair<T>> = pairs.take(limit.saturating_add(1)).collect();
+		let count = pairs.len().min(limit) as u64;
+
+		if pairs.len() > limit && limit > 0 {
+			VirtualOrdersCursor::<T>::put(pairs[limit - 1].clone());
+		}
+		for pair in pairs.iter().take(limit) {
+			Self::try_execute_virtual_orders(pair);
+		}
+
+		T::DbWeight::get().reads_writes(2 + 4 * count, 1 + 2 * count)
+	}
+
+	/// Execute the virtual orders of `pair`, leaving it untouched if that fails so that it is
+	/// retried later.
+	pub(crate) fn try_execute_virtual_orders(pair: &AssetPair<T>) {
+		if let Err(error) = with_storage_layer(|| Self::execute_virtual_orders(pair)) {
+			log::warn!(
+				target: LOG_TARGET,
+				"failed to execute the virtual orders of {:?}: {:?}",
+				pair,
+				error,
+			);
+			Self::deposit_event(Event::VirtualOrdersFailed { pair: pair.clone(), error });
+		}
+	}
+
+	/// Execute the virtual orders of `pair` for every block since they were last executed.
+	///
+	/// Blocks between two order expiries are executed as one interval. After each interval the
+	/// sale rates of the orders expiring there are removed and the accumulators are snapshotted.
+	pub(crate) fn execute_virtual_orders(pair: &AssetPair<T>) -> DispatchResult {
+		let Some(mut state) = TwammPools::<T>::get(pair) else { return Ok(()) };
+
+		let now = frame_system::Pallet::<T>::block_number();
+		if state.last_executed >= now {
+			return Ok(())
+		}
+
+		let mut pool_info = Pools::<T>::get(pair).ok_or(Error::<T>::PoolNotFound)?;
+		let mut block = state.last_executed;
+
+		while block < now {
+			let mut next = block.saturating_add(One::one());
+			while next < now && !OrderExpiries::<T>::contains_key(pair, next) {
+				next = next.saturating_add(One::one());
+			}
+
+			let blocks = Self::blocks_to_balance(next.saturating_sub(block));
+			Self::execute_twamm_interval(pair, &mut state, &mut pool_info, blocks)?;
+
+			if OrderExpiries::<T>::contains_key(pair, next) {
+				let mut expiry = OrderExpiries::<T>::get(pair, next);
+				state.sale_rate_a = state.sale_rate_a.saturating_sub(expiry.sale_rate_a);
+				state.sale_rate_b = state.sale_rate_b.saturating_sub(expiry.sale_rate_b);
+				expiry.earnings_per_rate_a = state.earnings_per_rate_a;
+				expiry.earnings_per_rate_b = state.earnings_per_rate_b;
+				OrderExpiries::<T>::insert(pair, next, expiry);
+			}
+
+			block = next;
+		}
+
+		state.last_executed = now;
+		Self::update_reserves(pair, pool_info);
+		Self::store_twamm_pool(pair, state);
+
+		Ok(())
+	}
+
+	/// Sell `blocks` worth of both sale rates into the pool of `pair` at one clearing price and
+	/// credit the proceeds to the accumulators.
+	fn execute_twamm_interval(
+		pair: &AssetPair<T>,
+		state: &mut TwammPool<T>,
+		pool_info: &mut PoolInfo<T>,
+		blocks: BalanceOf<T>,
+	) -> DispatchResult {
+		let total_a = state.sale_rate_a.checked_mul(&blocks).ok_or(Error::<T>::Overflow)?;
+		let total_b = state.sale_rate_b.checked_mul(&blocks).ok_or(Error::<T>::Overflow)?;
+		if total_a.is_zero() && total_b.is_zero() {
+			return Ok(())
+		}
+
+		let clearing = Self::clear_batch(pool_info.reserve_a, pool_info.reserve_b, total_a, total_b)?;
+
+		pool_info.reserve_a = pool_info
+			.reserve_a
+			.checked_add(&clearing.pool_in_a)
+			.and_then(|reserve| reserve.checked_sub(&clearing.pool_out_a))
+			.ok_or(Error::<T>::Overflow)?;
+		pool_info.reserve_b = pool_info
+			.reserve_b
+			.checked_add(&clearing.pool_in_b)
+			.and_then(|reserve| reserve.checked_sub(&clearing.pool_out_b))
+			.ok_or(Error::<T>::Overflow)?;
+		Self::record_pool_trade(pair, &clearing, pool_info);
+
+		if !state.sale_rate_a.is_zero() {
+			state.earnings_per_rate_a = state.earnings_per_rate_a.saturating_add(
+				Self::earnings_per_rate(clearing.paid_b, state.sale_rate_a),
+			);
+		}
+		if !state.sale_rate_b.is_zero() {
+			state.earnings_per_rate_b = state.earnings_per_rate_b.saturating_add(
+				Self::earnings_per_rate(clearing.paid_a, state.sale_rate_b),
+			);
+		}
+
+		Ok(())
+	}
+
+	/// Last block the virtual orders of `pair` have executed up to. Once no order sells into the
+	/// pool, that is the current block.
+	fn virtual_orders_executed_until(pair: &AssetPair<T>) -> BlockNumberFor<T> {
+		TwammPools::<T>::get(pair)
+			.map_or_else(frame_system::Pallet::<T>::block_number, |state| state.last_executed)
+	}
+
+	/// Accumulator value an order's proceeds are measured against, once the virtual orders have
+	/// executed up to block `executed`.
+	fn order_earnings_per_rate(order: &LongTermOrder<T>, executed: BlockNumberFor<T>) -> FixedU128 {
+		if executed >= order.expiry {
+			let expiry = OrderExpiries::<T>::get(&order.pair, order.expiry);
+			if order.sells_a { expiry.earnings_per_rate_a } else { expiry.earnings_per_rate_b }
+		} else {
+			TwammPools::<T>::get(&order.pair)
+				.map(|state| {
+					if order.sells_a { state.earnings_per_rate_a } else { state.earnings_per_rate_b }
+				})
+				.unwrap_or(order.earnings_per_rate)
+		}
+	}
+
+	/// Proceeds earned by `order` between its last withdrawal and `earnings_per_rate`.
+	fn order_proceeds(
+		order: &LongTermOrder<T>,
+		earnings_per_rate: FixedU128,
+	) -> Result<BalanceOf<T>, Error<T>> {
+		let proceeds = earnings_per_rate
+			.saturating_sub(order.earnings_per_rate)
+			.saturating_mul_int(order.sale_rate.saturated_into::<u128>());
+
+		proceeds.try_into().map_err(|_| Error::<T>::Overflow)
+	}
+
+	/// `earned / sale_rate`, rounded down so proceeds never exceed what was actually earned.
+	fn earnings_per_rate(earned: BalanceOf<T>, sale_rate: BalanceOf<T>) -> FixedU128 {
+		FixedU128::from_rational_with_rounding(
+			earned.saturated_into::<u128>(),
+			sale_rate.saturated_into::<u128>(),
+			Rounding::Down,
+		)
+	}
+
+	/// Drop one order's reference to an expiry entry, removing the entry with the last one.
+	fn release_order_expiry(pair: &AssetPair<T>, expiry: BlockNumberFor<T>) {
+		OrderExpiries::<T>::mutate_exists(pair, expiry, |maybe_entry| {
+			if let Some(entry) = maybe_entry {
+				entry.orders = entry.orders.saturating_sub(1);
+				if entry.orders == 0 {
+					*maybe_entry = None;
+				}
+			}
+		});
+	}
+
+	/// Store the TWAMM state of a pair, dropping it once no order is selling into the pool.
+	///
+	/// Orders that are no longer selling read their proceeds from their expiry snapshot, so the
+	/// accumulators can safely restart from zero for later orders.
+	fn store_twamm_pool(pair: &AssetPair<T>, state: TwammPool<T>) {
+		if state.sale_rate_a.is_zero() && state.sale_rate_b.is_zero() {
+			TwammPools::<T>::remove(pair);
+		} else {
+			TwammPools::<T>::insert(pair, state);
+		}
+	}
+
+	/// Express a number of blocks in the balance type, to multiply it with a sale rate.
+	fn blocks_to_balance(blocks: BlockNumberFor<T>) -> BalanceOf<T> {
+		blocks.saturated_into::<u128>().saturated_into()
+	}
+}
@@ -7,7 +7,10 @@
 include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
 
 use codec::{Decode, Encode};
-use frame_support::{traits::AsEnsureOriginWithArg, PalletId};
+use frame_support::{
+	traits::{AsEnsureOriginWithArg, Get, NeverEnsureOrigin},
+	PalletId,
+};
 use frame_system::{EnsureRoot, EnsureSigned};
 use pallet_grandpa::AuthorityId as GrandpaId;
 use sp_api::impl_runtime_apis;
@@ -16,8 +19,8 @@ use sp_core::{crypto::KeyTypeId, OpaqueMetadata};
 use sp_runtime::{
 	create_runtime_str, generic, impl_opaque_keys,
 	traits::{
-		AccountIdLookup, BlakeTwo256, Block as BlockT, Convert, IdentifyAccount, NumberFor, One,
-		Verify,
+		AccountIdConversion, AccountIdLookup, BlakeTwo256, Block as BlockT, Convert,
+		IdentifyAccount, NumberFor, One, Verify,
 	},
 	transaction_validity::{TransactionSource, TransactionValidity},
 	ApplyExtrinsicResult, MultiSignature,
@@ -105,10 +108,10 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
 	//   `spec_version`, and `authoring_version` are the same between Wasm and native.
 	// This value is set to 100 to notify Polkadot-JS App (https://polkadot.js.org/apps) to use
 	//   the compatible custom types.
-	spec_version: 100,
+	spec_version: 101,
 	impl_version: 1,
 	apis: RUNTIME_API_VERSIONS,
-	transaction_version: 1,
+	transaction_version: 2,
 	state_version: 1,
 };
 
@@ -246,7 +249,7 @@ impl pallet_balances::Config for Runtime {
 	type WeightInfo = pallet_balances::weights::SubstrateWeight<Runtime>;
 	type FreezeIdentifier = ();
 	type MaxFreezes = ConstU32<50>;
-	type RuntimeHoldReason = ();
+	type RuntimeHoldReason = RuntimeHoldReason;
 	type MaxHolds = ConstU32<50>;
 }
 
@@ -300,6 +303,30 @@ impl pallet_assets::Config for Runtime {
 	type BenchmarkHelper = ();
 }
 
+// LP tokens of the DEX, only ever created by the DEX pallet itself and free of deposits.
+impl pallet_assets::Config<pallet_assets::Instance1> for Runtime {
+	type RuntimeEvent = RuntimeEvent;
+	type Balance = u128;
+	type AssetId = u32;
+	type AssetIdParameter = codec::Compact<u32>;
+	type Currency = Balances;
+	type CreateOrigin = AsEnsureOriginWithArg<NeverEnsureOrigin<AccountId>>;
+	type ForceOrigin = EnsureRoot<AccountId>;
+	type AssetDeposit = ConstU128<0>;
+	type AssetAccountDeposit = ConstU128<0>;
+	type MetadataDepositBase = ConstU128<0>;
+	type MetadataDepositPerByte = ConstU128<0>;
+	type ApprovalDeposit = ConstU128<0>;
+	type StringLimit = StringLimit;
+	type Freezer = ();
+	type Extra = ();
+	type WeightInfo = pallet_assets::weights::SubstrateWeight<Runtime>;
+	type RemoveItemsLimit = ConstU32<1000>;
+	type CallbackHandle = ();
+	#[cfg(feature = "runtime-benchmarks")]
+	type BenchmarkHelper = ();
+}
+
 pub struct AuthorityToAccount;
 
 impl Convert<AuraId, AccountId> for AuthorityToAccount {
@@ -313,32 +340,74 @@ impl Convert<AuraId, AccountId> for AuthorityToAccount {
 
 parameter_types! {
 	pub const DexPalletId: PalletId = PalletId(*b"py/dodex");
+	pub const FlashLoanFee: Permill = Permill::from_parts(900);
+	pub const NativeAssetId: u32 = NATIVE_ASSET_ID;
+	pub const MaxLockBoost: Perbill = Perbill::from_percent(100);
+	pub const TreasuryPalletId: PalletId = PalletId(*b"py/trsry");
+	pub TreasuryAccount: AccountId = TreasuryPalletId::get().into_account_truncating();
 }
 
+/// Asset id of the wrapped native token traded in DEX pools, created at genesis.
+pub const NATIVE_ASSET_ID: u32 = 0;
+
 /// Configure the pallet-dex in pallets/dex.
 impl pallet_dex::Config for Runtime {
 	type RuntimeEvent = RuntimeEvent;
+	type RuntimeCall = RuntimeCall;
+	type RuntimeHoldReason = RuntimeHoldReason;
 	type NativeBalance = Balances;
+	type PairOrdering = pallet_dex::OrdPairOrdering;
+	type LpAssetIds = pallet_dex::RequestedLpAssetId;
 	type Fungibles = Assets;
+	type PoolAssets = PoolAssets;
 	type PalletId = DexPalletId;
 	type PoolCreateFee = ConstU128<100>;
 	type MinLiquidity = ConstU128<10>;
 	type MinBalance = ConstU128<1>;
 	type PoolFeePercent = ConstU8<1>; 
+	type MaxSwapIntents = ConstU32<64>;
+	type MaxOrderBlocks = ConstU32<{ 7 * DAYS }>;
+	type FlashLoanFee = FlashLoanFee;
+	type NativeAssetId = NativeAssetId;
+	type FarmingOrigin = EnsureRoot<AccountId>;
+	type MaxLockBlocks = ConstU32<{ 365 * DAYS }>;
+	type MaxLockBoost = MaxLockBoost;
+	type MaxLockExpiries = ConstU32<64>;
+	type TreasuryOrigin = EnsureRoot<AccountId>;
+	type TreasuryAccount = TreasuryAccount;
+	type PoolCreationOrigin = EnsureSigned<AccountId>;
+	type AssetListingOrigin = EnsureRoot<AccountId>;
+	type AllowUnlistedPools = ConstBool<true>;
+	type LegacyEvents = ConstBool<true>;
+	type StatsWindow = ConstU32<HOURS>;
+	type MaxStatsBuckets = ConstU32<24>;
+	type MaxHops = ConstU32<3>;
+	type MaxSplitRoutes = ConstU32<4>;
+	type MaxRoutes = ConstU32<16>;
+	type MaxVirtualOrderPools = ConstU32<32>;
+}
+
+/// Fees can be paid in any asset with a DEX pool against the wrapped native token.
+impl pallet_asset_tx_payment::Config for Runtime {
+	type RuntimeEvent = RuntimeEvent;
+	type Fungibles = Assets;
+	type OnChargeAssetTransaction = pallet_dex::DexFeeAdapter<Runtime>;
 }
 
 // Create the runtime by composing the FRAME pallets that were previously configured.
 construct_runtime!(
 	pub struct Runtime {
-		System: frame_system,
-		Timestamp: pallet_timestamp,
-		Aura: pallet_aura,
-		Grandpa: pallet_grandpa,
-		Balances: pallet_balances,
-		TransactionPayment: pallet_transaction_payment,
-		Sudo: pallet_sudo,
-		Assets: pallet_assets,
-		Dex: pallet_dex,
+		System: frame_system = 0,
+		Timestamp: pallet_timestamp = 1,
+		Aura: pallet_aura = 2,
+		Grandpa: pallet_grandpa = 3,
+		Balances: pallet_balances = 4,
+		TransactionPayment: pallet_transaction_payment = 5,
+		Sudo: pallet_sudo = 6,
+		Assets: pallet_assets = 7,
+		Dex: pallet_dex = 8,
+		AssetTxPayment: pallet_asset_tx_payment = 9,
+		PoolAssets: pallet_assets::<Instance1> = 10,
 	}
 );
 
@@ -357,7 +426,7 @@ pub type SignedExtra = (
 	frame_system::CheckEra<Runtime>,
 	frame_system::CheckNonce<Runtime>,
 	frame_system::CheckWeight<Runtime>,
-	pallet_transaction_payment::ChargeTransactionPayment<Runtime>,
+	pallet_asset_tx_payment::ChargeAssetTxPayment<Runtime>,
 );
 
 /// Unchecked extrinsic type as expected by this runtime.
@@ -372,8 +441,17 @@ pub type Executive = frame_executive::Executive<
 	frame_system::ChainContext<Runtime>,
 	Runtime,
 	AllPalletsWithSystem,
+	Migrations,
 >;
 
+/// Storage migrations to run on the next runtime upgrade.
+pub type Migrations = (
+	pallet_dex::migrations::v1::MigrateToV1<Runtime>,
+	// Moving an LP holder's balance takes nine storage accesses, so 2_000 holders fit well within
+	// the upgrade block.
+	pallet_dex::migrations::v2::MigrateToV2<Runtime, Assets, ConstU32<2_000>>,
+);
+
 #[cfg(feature = "runtime-benchmarks")]
 #[macro_use]
 extern crate frame_benchmarking;
@@ -558,6 +636,72 @@ impl_runtime_apis! {
 		}
 	}
 
+	impl pallet_dex_runtime_api::DexApi<Block, u32, Balance, BlockNumber> for Runtime {
+		fn pool_stats(asset_a: u32, asset_b: u32) -> Vec<pallet_dex::StatsBucket<BlockNumber, Balance>> {
+			Dex::pool_stats(asset_a, asset_b)
+		}
+
+		fn block_swaps(asset_a: u32, asset_b: u32) -> Vec<(Balance, Balance)> {
+			Dex::block_swaps(asset_a, asset_b)
+		}
+
+		fn best_route_exact_in(
+			asset_in: u32,
+			asset_out: u32,
+			amount_in: Balance,
+		) -> Option<pallet_dex::Route<u32, Balance>> {
+			Dex::best_route_exact_in(asset_in, asset_out, amount_in)
+		}
+
+		fn best_route_exact_out(
+			asset_in: u32,
+			asset_out: u32,
+			amount_out: Balance,
+		) -> Option<pallet_dex::Route<u32, Balance>> {
+			Dex::best_route_exact_out(asset_in, asset_out, amount_out)
+		}
+
+		fn routes(asset_in: u32, asset_out: u32) -> Vec<Vec<u32>> {
+			Dex::routes(asset_in, asset_out)
+		}
+
+		fn quote_split_exact_in(routes: Vec<(Vec<u32>, Balance)>) -> Option<Vec<Balance>> {
+			Dex::quote_split_exact_in(&routes).ok()
+		}
+
+		fn max_split_routes() -> u32 {
+			<Runtime as pallet_dex::Config>::MaxSplitRoutes::get()
+		}
+	}
+
+	impl pallet_dex_runtime_api::DexSimulationApi<Block, AccountId, u32, Balance> for Runtime {
+		fn simulate_swap_exact_in(
+			who: AccountId,
+			asset_in: u32,
+			asset_out: u32,
+			amount_in: Balance,
+			amount_out_min: Balance,
+		) -> Result<pallet_dex::SwapSimulation<Balance>, sp_runtime::DispatchError> {
+			Dex::simulate_swap_exact_in(who, asset_in, asset_out, amount_in, amount_out_min)
+		}
+
+		fn simulate_swap_exact_out(
+			who: AccountId,
+			asset_in: u32,
+			asset_out: u32,
+			amount_out: Balance,
+			amount_in_max: Balance,
+		) -> Result<pallet_dex::SwapSimulation<Balance>, sp_runtime::DispatchError> {
+			Dex::simulate_swap_exact_out(who, asset_in, asset_out, amount_out, amount_in_max)
+		}
+	}
+
+	impl pallet_dex_runtime_api::DexPositionsApi<Block, AccountId, u32, Balance> for Runtime {
+		fn lp_positions(who: AccountId) -> Vec<pallet_dex::LpPosition<u32, Balance>> {
+			Dex::lp_positions(&who)
+		}
+	}
+
 	#[cfg(feature = "runtime-benchmarks")]
 	impl frame_benchmarking::Benchmark<Block> for Runtime {
 		fn benchmark_metadata(extra: bool) -> (